@@ -16,13 +16,22 @@
 
 use std::path::PathBuf;
 
-use clap::{load_yaml, App};
+use clap::{load_yaml, value_t, App};
 
 #[derive(Clone)]
 pub struct CliOpts {
 	pub file: PathBuf,
 	pub log_level: log::LevelFilter,
 	pub chain_spec: node_template::chain_spec::ChainSpec,
+	pub command: Option<Command>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Command {
+	/// Queue a range of blocks to be executed again
+	Reexecute { from: u64, to: u64 },
+	/// Check the archived storage of a range of blocks
+	Verify { from: u64, to: u64 },
 }
 
 impl CliOpts {
@@ -56,10 +65,22 @@ impl CliOpts {
 		} else {
 			panic!("Chain spec could not be loaded; is the path correct?")
 		}
+		let command = match matches.subcommand() {
+			("reexecute", Some(reexecute)) => Some(Command::Reexecute {
+				from: value_t!(reexecute, "from", u64).unwrap_or_else(|e| e.exit()),
+				to: value_t!(reexecute, "to", u64).unwrap_or_else(|e| e.exit()),
+			}),
+			("verify", Some(verify)) => Some(Command::Verify {
+				from: value_t!(verify, "from", u64).unwrap_or_else(|e| e.exit()),
+				to: value_t!(verify, "to", u64).unwrap_or_else(|e| e.exit()),
+			}),
+			_ => None,
+		};
 		CliOpts {
 			file: PathBuf::from(file),
 			log_level,
 			chain_spec: chain_spec.expect("Chain spec could not be loaded"),
+			command,
 		}
 	}
 }
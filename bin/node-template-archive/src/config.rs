@@ -14,6 +14,7 @@
 // You should have received a copy of the GNU General Public License
 // along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 
 use anyhow::Result;
@@ -31,6 +32,7 @@ pub struct Config {
 	cache_size: Option<usize>,
 	block_workers: Option<usize>,
 	wasm_pages: Option<u64>,
+	metrics_addr: Option<SocketAddr>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -39,6 +41,7 @@ struct TomlConfig {
 	cache_size: Option<usize>,
 	block_workers: Option<usize>,
 	wasm_pages: Option<u64>,
+	metrics_addr: Option<SocketAddr>,
 	db_host: Option<String>,
 	db_port: Option<String>,
 	db_user: Option<String>,
@@ -67,6 +70,7 @@ impl Config {
 			cache_size: toml_conf.cache_size,
 			block_workers: toml_conf.block_workers,
 			wasm_pages: toml_conf.wasm_pages,
+			metrics_addr: toml_conf.metrics_addr,
 		})
 	}
 
@@ -98,4 +102,8 @@ impl Config {
 	pub fn wasm_pages(&self) -> Option<u64> {
 		self.wasm_pages.clone()
 	}
+
+	pub fn metrics_addr(&self) -> Option<SocketAddr> {
+		self.metrics_addr
+	}
 }
@@ -27,6 +27,10 @@ use substrate_archive_backend::SecondaryRocksDB;
 pub fn main() -> anyhow::Result<()> {
 	let config = config::Config::new()?;
 	substrate_archive::init_logger(config.cli().log_level, log::LevelFilter::Debug)?;
+	#[cfg(feature = "metrics")]
+	if let Some(addr) = config.metrics_addr() {
+		substrate_archive::metrics::serve(addr)?;
+	}
 
 	let mut archive =
 		ArchiveBuilder::<Block, runtime::RuntimeApi, node_template::service::Executor, SecondaryRocksDB> {
@@ -39,6 +43,21 @@ pub fn main() -> anyhow::Result<()> {
 		.pg_url(config.psql_conf().url())
 		.chain_spec(Box::new(config.cli().chain_spec.clone()))
 		.build()?;
+	match config.cli().command {
+		Some(cli_opts::Command::Reexecute { from, to }) => {
+			let queued = archive.reexecute(from..=to)?;
+			log::info!("Queued {} blocks for execution. They are executed while the archive runs", queued);
+			return Ok(());
+		}
+		Some(cli_opts::Command::Verify { from, to }) => {
+			let report = archive.verify(from..=to)?;
+			for (num, hash) in report.mismatched.iter() {
+				log::warn!("Block {} (0x{}) does not match its state root", num, hex::encode(hash));
+			}
+			return Ok(());
+		}
+		None => (),
+	}
 	archive.drive()?;
 
 	let running = Arc::new(AtomicBool::new(true));
@@ -48,7 +67,8 @@ pub fn main() -> anyhow::Result<()> {
 		r.store(false, Ordering::SeqCst);
 	})
 	.expect("Error setting Ctrl-C handler");
-	while running.load(Ordering::SeqCst) {}
+	// an archive indexing a bounded range of blocks stops by itself once it is done
+	while running.load(Ordering::SeqCst) && !archive.is_stopped() {}
 	archive.shutdown()?;
 	Ok(())
 }
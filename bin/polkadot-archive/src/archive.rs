@@ -14,13 +14,18 @@
 // You should have received a copy of the GNU General Public License
 // along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::sync::Arc;
+
 use anyhow::{anyhow, Context, Result};
 use polkadot_service::kusama_runtime as ksm_rt;
 use polkadot_service::polkadot_runtime as dot_rt;
 use polkadot_service::westend_runtime as westend_rt;
 use polkadot_service::Block;
 use sc_chain_spec::ChainSpec;
-use substrate_archive::{Archive, ArchiveBuilder};
+use substrate_archive::{
+	sink::{FileFormat, FileSink},
+	Archive, ArchiveBuilder, ArchiveSink, StorageFilter,
+};
 use substrate_archive_common::ReadOnlyDB;
 
 use crate::config::Config;
@@ -54,6 +59,19 @@ pub fn run_archive<D: ReadOnlyDB + 'static>(config: Config) -> Result<Box<dyn Ar
 				block_workers: config.block_workers(),
 				wasm_pages: config.wasm_pages(),
 				max_block_load: config.max_block_load(),
+				index_forks: config.index_forks(),
+				start_block: config.start_block(),
+				end_block: config.end_block(),
+				finalized_only: config.finalized_only(),
+				confirmation_depth: config.confirmation_depth(),
+				decode: config.decode(),
+				copy_insert: config.copy_insert(),
+				shutdown_timeout: config.shutdown_timeout(),
+				storage_filter: Some(StorageFilter::new(config.storage_allow(), config.storage_deny())?),
+				snapshot_interval: config.snapshot_interval(),
+				snapshot_on_upgrade: config.snapshot_on_upgrade(),
+				postgres_sink: config.export_only().map(|only| !only),
+				sinks: sinks(&config)?,
 				..ArchiveBuilder::default()
 			}
 			.chain_data_db(db_path)
@@ -68,6 +86,19 @@ pub fn run_archive<D: ReadOnlyDB + 'static>(config: Config) -> Result<Box<dyn Ar
 				block_workers: config.block_workers(),
 				wasm_pages: config.wasm_pages(),
 				max_block_load: config.max_block_load(),
+				index_forks: config.index_forks(),
+				start_block: config.start_block(),
+				end_block: config.end_block(),
+				finalized_only: config.finalized_only(),
+				confirmation_depth: config.confirmation_depth(),
+				decode: config.decode(),
+				copy_insert: config.copy_insert(),
+				shutdown_timeout: config.shutdown_timeout(),
+				storage_filter: Some(StorageFilter::new(config.storage_allow(), config.storage_deny())?),
+				snapshot_interval: config.snapshot_interval(),
+				snapshot_on_upgrade: config.snapshot_on_upgrade(),
+				postgres_sink: config.export_only().map(|only| !only),
+				sinks: sinks(&config)?,
 				..ArchiveBuilder::default()
 			}
 			.chain_data_db(db_path)
@@ -82,6 +113,19 @@ pub fn run_archive<D: ReadOnlyDB + 'static>(config: Config) -> Result<Box<dyn Ar
 				block_workers: config.block_workers(),
 				wasm_pages: config.wasm_pages(),
 				max_block_load: config.max_block_load(),
+				index_forks: config.index_forks(),
+				start_block: config.start_block(),
+				end_block: config.end_block(),
+				finalized_only: config.finalized_only(),
+				confirmation_depth: config.confirmation_depth(),
+				decode: config.decode(),
+				copy_insert: config.copy_insert(),
+				shutdown_timeout: config.shutdown_timeout(),
+				storage_filter: Some(StorageFilter::new(config.storage_allow(), config.storage_deny())?),
+				snapshot_interval: config.snapshot_interval(),
+				snapshot_on_upgrade: config.snapshot_on_upgrade(),
+				postgres_sink: config.export_only().map(|only| !only),
+				sinks: sinks(&config)?,
 				..ArchiveBuilder::default()
 			}
 			.chain_data_db(db_path)
@@ -93,6 +137,16 @@ pub fn run_archive<D: ReadOnlyDB + 'static>(config: Config) -> Result<Box<dyn Ar
 	}
 }
 
+/// Sinks to write to in addition to, or instead of, Postgres
+fn sinks(config: &Config) -> Result<Vec<Arc<dyn ArchiveSink<Block>>>> {
+	let mut sinks: Vec<Arc<dyn ArchiveSink<Block>>> = Vec::new();
+	if let Some(dir) = config.export_dir() {
+		let format = config.export_format().unwrap_or("json").parse::<FileFormat>()?;
+		sinks.push(Arc::new(FileSink::new(dir, format)?));
+	}
+	Ok(sinks)
+}
+
 fn get_spec(chain: &str) -> Result<Box<dyn ChainSpec>> {
 	match chain.to_ascii_lowercase().as_str() {
 		"kusama" | "ksm" => {
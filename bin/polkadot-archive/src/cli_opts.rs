@@ -14,9 +14,10 @@
 // You should have received a copy of the GNU General Public License
 // along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::net::SocketAddr;
 use std::path::PathBuf;
 
-use clap::{load_yaml, App};
+use clap::{load_yaml, value_t, App};
 
 #[derive(Debug, Clone)]
 pub struct CliOpts {
@@ -24,6 +25,19 @@ pub struct CliOpts {
 	pub log_level: log::LevelFilter,
 	pub log_num: u64,
 	pub chain: String,
+	pub command: Option<Command>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Command {
+	/// Serve the archive over JSON-RPC instead of indexing
+	Rpc { addr: SocketAddr },
+	/// Serve the archive over GraphQL instead of indexing
+	Graphql { addr: SocketAddr },
+	/// Queue a range of blocks to be executed again
+	Reexecute { from: u64, to: u64 },
+	/// Check the archived storage of a range of blocks
+	Verify { from: u64, to: u64 },
 }
 
 impl CliOpts {
@@ -42,6 +56,24 @@ impl CliOpts {
 
 		let chain = matches.value_of("chain").unwrap_or("polkadot");
 
-		CliOpts { file: file.map(PathBuf::from), log_level, log_num, chain: chain.to_string() }
+		let command = match matches.subcommand() {
+			("rpc", Some(rpc)) => {
+				Some(Command::Rpc { addr: value_t!(rpc, "addr", SocketAddr).unwrap_or_else(|e| e.exit()) })
+			}
+			("graphql", Some(graphql)) => {
+				Some(Command::Graphql { addr: value_t!(graphql, "addr", SocketAddr).unwrap_or_else(|e| e.exit()) })
+			}
+			("reexecute", Some(reexecute)) => Some(Command::Reexecute {
+				from: value_t!(reexecute, "from", u64).unwrap_or_else(|e| e.exit()),
+				to: value_t!(reexecute, "to", u64).unwrap_or_else(|e| e.exit()),
+			}),
+			("verify", Some(verify)) => Some(Command::Verify {
+				from: value_t!(verify, "from", u64).unwrap_or_else(|e| e.exit()),
+				to: value_t!(verify, "to", u64).unwrap_or_else(|e| e.exit()),
+			}),
+			_ => None,
+		};
+
+		CliOpts { file: file.map(PathBuf::from), log_level, log_num, chain: chain.to_string(), command }
 	}
 }
@@ -14,7 +14,9 @@
 // You should have received a copy of the GNU General Public License
 // along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use anyhow::Result;
 use serde::Deserialize;
@@ -30,6 +32,22 @@ struct TomlConfig {
 	block_workers: Option<usize>,
 	wasm_pages: Option<u64>,
 	max_block_load: Option<u32>,
+	index_forks: Option<bool>,
+	start_block: Option<u64>,
+	end_block: Option<u64>,
+	finalized_only: Option<bool>,
+	confirmation_depth: Option<u64>,
+	decode: Option<bool>,
+	copy_insert: Option<bool>,
+	metrics_addr: Option<SocketAddr>,
+	shutdown_timeout: Option<u64>,
+	export_dir: Option<PathBuf>,
+	export_format: Option<String>,
+	export_only: Option<bool>,
+	storage_allow: Option<Vec<String>>,
+	storage_deny: Option<Vec<String>>,
+	snapshot_interval: Option<u64>,
+	snapshot_on_upgrade: Option<bool>,
 	db_host: Option<String>,
 	db_port: Option<String>,
 	db_user: Option<String>,
@@ -67,6 +85,22 @@ pub struct Config {
 	block_workers: Option<usize>,
 	wasm_pages: Option<u64>,
 	max_block_load: Option<u32>,
+	index_forks: Option<bool>,
+	start_block: Option<u64>,
+	end_block: Option<u64>,
+	finalized_only: Option<bool>,
+	confirmation_depth: Option<u64>,
+	decode: Option<bool>,
+	copy_insert: Option<bool>,
+	metrics_addr: Option<SocketAddr>,
+	shutdown_timeout: Option<Duration>,
+	export_dir: Option<PathBuf>,
+	export_format: Option<String>,
+	export_only: Option<bool>,
+	storage_allow: Option<Vec<String>>,
+	storage_deny: Option<Vec<String>>,
+	snapshot_interval: Option<u64>,
+	snapshot_on_upgrade: Option<bool>,
 }
 
 impl Config {
@@ -83,6 +117,22 @@ impl Config {
 			block_workers: toml_conf.as_ref().map(|c| c.block_workers).flatten(),
 			wasm_pages: toml_conf.as_ref().map(|c| c.wasm_pages).flatten(),
 			max_block_load: toml_conf.as_ref().map(|c| c.max_block_load).flatten(),
+			index_forks: toml_conf.as_ref().map(|c| c.index_forks).flatten(),
+			start_block: toml_conf.as_ref().map(|c| c.start_block).flatten(),
+			end_block: toml_conf.as_ref().map(|c| c.end_block).flatten(),
+			finalized_only: toml_conf.as_ref().map(|c| c.finalized_only).flatten(),
+			confirmation_depth: toml_conf.as_ref().map(|c| c.confirmation_depth).flatten(),
+			decode: toml_conf.as_ref().map(|c| c.decode).flatten(),
+			copy_insert: toml_conf.as_ref().map(|c| c.copy_insert).flatten(),
+			metrics_addr: toml_conf.as_ref().map(|c| c.metrics_addr).flatten(),
+			shutdown_timeout: toml_conf.as_ref().map(|c| c.shutdown_timeout).flatten().map(Duration::from_secs),
+			export_dir: toml_conf.as_ref().map(|c| c.export_dir.clone()).flatten(),
+			export_format: toml_conf.as_ref().map(|c| c.export_format.clone()).flatten(),
+			export_only: toml_conf.as_ref().map(|c| c.export_only).flatten(),
+			storage_allow: toml_conf.as_ref().map(|c| c.storage_allow.clone()).flatten(),
+			storage_deny: toml_conf.as_ref().map(|c| c.storage_deny.clone()).flatten(),
+			snapshot_interval: toml_conf.as_ref().map(|c| c.snapshot_interval).flatten(),
+			snapshot_on_upgrade: toml_conf.as_ref().map(|c| c.snapshot_on_upgrade).flatten(),
 		})
 	}
 
@@ -118,4 +168,68 @@ impl Config {
 	pub fn max_block_load(&self) -> Option<u32> {
 		self.max_block_load
 	}
+
+	pub fn index_forks(&self) -> Option<bool> {
+		self.index_forks
+	}
+
+	pub fn start_block(&self) -> Option<u64> {
+		self.start_block
+	}
+
+	pub fn end_block(&self) -> Option<u64> {
+		self.end_block
+	}
+
+	pub fn finalized_only(&self) -> Option<bool> {
+		self.finalized_only
+	}
+
+	pub fn confirmation_depth(&self) -> Option<u64> {
+		self.confirmation_depth
+	}
+
+	pub fn decode(&self) -> Option<bool> {
+		self.decode
+	}
+
+	pub fn copy_insert(&self) -> Option<bool> {
+		self.copy_insert
+	}
+
+	pub fn metrics_addr(&self) -> Option<SocketAddr> {
+		self.metrics_addr
+	}
+
+	pub fn shutdown_timeout(&self) -> Option<Duration> {
+		self.shutdown_timeout
+	}
+
+	pub fn export_dir(&self) -> Option<&Path> {
+		self.export_dir.as_deref()
+	}
+
+	pub fn export_format(&self) -> Option<&str> {
+		self.export_format.as_deref()
+	}
+
+	pub fn export_only(&self) -> Option<bool> {
+		self.export_only
+	}
+
+	pub fn storage_allow(&self) -> &[String] {
+		self.storage_allow.as_deref().unwrap_or_default()
+	}
+
+	pub fn storage_deny(&self) -> &[String] {
+		self.storage_deny.as_deref().unwrap_or_default()
+	}
+
+	pub fn snapshot_interval(&self) -> Option<u64> {
+		self.snapshot_interval
+	}
+
+	pub fn snapshot_on_upgrade(&self) -> Option<bool> {
+		self.snapshot_on_upgrade
+	}
 }
@@ -17,14 +17,40 @@ mod archive;
 mod cli_opts;
 mod config;
 
+use std::net::SocketAddr;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
 use substrate_archive_backend::SecondaryRocksDB;
 
+use crate::cli_opts::Command;
+
 pub fn main() -> anyhow::Result<()> {
 	let config = config::Config::new()?;
 	substrate_archive::init_logger(config.cli().log_level, log::LevelFilter::Debug)?;
+	match config.cli().command.clone() {
+		Some(Command::Rpc { addr }) => return rpc(&config, addr),
+		Some(Command::Graphql { addr }) => return graphql(&config, addr),
+		Some(Command::Reexecute { from, to }) => {
+			let archive = archive::run_archive::<SecondaryRocksDB>(config)?;
+			let queued = archive.reexecute(from..=to)?;
+			log::info!("Queued {} blocks for execution. They are executed while the archive runs", queued);
+			return Ok(());
+		}
+		Some(Command::Verify { from, to }) => {
+			let archive = archive::run_archive::<SecondaryRocksDB>(config)?;
+			let report = archive.verify(from..=to)?;
+			for (num, hash) in report.mismatched.iter() {
+				log::warn!("Block {} (0x{}) does not match its state root", num, hex::encode(hash));
+			}
+			return Ok(());
+		}
+		None => (),
+	}
+	#[cfg(feature = "metrics")]
+	if let Some(addr) = config.metrics_addr() {
+		substrate_archive::metrics::serve(addr)?;
+	}
 
 	let mut archive = archive::run_archive::<SecondaryRocksDB>(config)?;
 	archive.drive()?;
@@ -35,8 +61,41 @@ pub fn main() -> anyhow::Result<()> {
 		r.store(false, Ordering::SeqCst);
 	})
 	.expect("Error setting Ctrl-C handler");
-	while running.load(Ordering::SeqCst) {}
+	// an archive indexing a bounded range of blocks stops by itself once it is done
+	while running.load(Ordering::SeqCst) && !archive.is_stopped() {}
 	archive.boxed_shutdown()?;
 
 	Ok(())
 }
+
+/// Serve the archive over JSON-RPC from Postgres, without the chain database
+#[cfg(feature = "rpc")]
+fn rpc(config: &config::Config, addr: SocketAddr) -> anyhow::Result<()> {
+	substrate_archive::rpc::serve::<polkadot_service::Block>(addr, &pg_url(config)?)?;
+	Ok(())
+}
+
+#[cfg(not(feature = "rpc"))]
+fn rpc(_: &config::Config, _: SocketAddr) -> anyhow::Result<()> {
+	Err(anyhow::anyhow!("polkadot-archive was built without the `rpc` feature"))
+}
+
+/// Serve the archive over GraphQL from Postgres, without the chain database
+#[cfg(feature = "graphql")]
+fn graphql(config: &config::Config, addr: SocketAddr) -> anyhow::Result<()> {
+	substrate_archive::graphql::serve(addr, &pg_url(config)?)?;
+	Ok(())
+}
+
+#[cfg(not(feature = "graphql"))]
+fn graphql(_: &config::Config, _: SocketAddr) -> anyhow::Result<()> {
+	Err(anyhow::anyhow!("polkadot-archive was built without the `graphql` feature"))
+}
+
+#[cfg(any(feature = "rpc", feature = "graphql"))]
+fn pg_url(config: &config::Config) -> anyhow::Result<String> {
+	match config.psql_conf() {
+		Some(conf) => Ok(conf.url()),
+		None => Ok(std::env::var("DATABASE_URL")?),
+	}
+}
@@ -21,11 +21,14 @@ use sp_api::{ApiExt, ApiRef};
 use sp_block_builder::BlockBuilder as BlockBuilderApi;
 use sp_runtime::{
 	generic::BlockId,
-	traits::{Block as BlockT, Header, NumberFor},
+	traits::{Block as BlockT, Header, NumberFor, SaturatedConversion},
 };
 use sp_storage::{StorageData, StorageKey as StorageKeyWrapper};
 
-use substrate_archive_common::{types::Storage, Result};
+use substrate_archive_common::{
+	types::{ChildStorageChanges, Storage},
+	Result,
+};
 
 pub type StorageKey = Vec<u8>;
 pub type StorageValue = Vec<u8>;
@@ -47,11 +50,10 @@ pub struct BlockChanges<Block: BlockT> {
 impl<Block> From<BlockChanges<Block>> for Storage<Block>
 where
 	Block: BlockT,
-	NumberFor<Block>: Into<u32>,
 {
 	fn from(changes: BlockChanges<Block>) -> Storage<Block> {
 		let hash = changes.block_hash;
-		let num: u32 = changes.block_num.into();
+		let num = changes.block_num.saturated_into::<u64>();
 
 		Storage::new(
 			hash,
@@ -62,6 +64,17 @@ where
 				.into_iter()
 				.map(|s| (StorageKeyWrapper(s.0), s.1.map(StorageData)))
 				.collect::<Vec<(StorageKeyWrapper, Option<StorageData>)>>(),
+			changes
+				.child_storage
+				.into_iter()
+				.map(|(child_key, changes)| {
+					let changes = changes
+						.into_iter()
+						.map(|s| (StorageKeyWrapper(s.0), s.1.map(StorageData)))
+						.collect::<Vec<(StorageKeyWrapper, Option<StorageData>)>>();
+					(StorageKeyWrapper(child_key), changes)
+				})
+				.collect::<Vec<ChildStorageChanges>>(),
 		)
 	}
 }
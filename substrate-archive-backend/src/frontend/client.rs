@@ -47,7 +47,7 @@ pub trait GetRuntimeVersion<Block: BlockT>: Send + Sync {
 
 // This trait allows circumvents putting <R, C> on an object that just needs to get the metadata
 /// Trait to get the opaque metadata from the Runtime Api
-pub trait GetMetadata<Block: BlockT>: Send + Sync {
+pub trait GetMetadata<Block: BlockT>: GetRuntimeVersion<Block> + Send + Sync {
 	fn metadata(&self, id: &BlockId<Block>) -> Result<sp_core::OpaqueMetadata>;
 }
 
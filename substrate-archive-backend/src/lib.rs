@@ -35,7 +35,7 @@ pub use substrate_archive_common::util::init_logger;
 use self::frontend::{GetMetadata, GetRuntimeVersion};
 // re-exports
 pub use self::{
-	block_exec::BlockExecutor,
+	block_exec::{BlockExecutor, ChildStorageCollection, StorageCollection},
 	database::SecondaryRocksDB,
 	frontend::{runtime_api, TArchiveClient},
 	read_only_backend::ReadOnlyBackend,
@@ -36,16 +36,18 @@ use kvdb::DBValue;
 
 use sc_client_api::backend::StateBackend;
 use sp_blockchain::{Backend as _, HeaderBackend as _};
+use sp_core::storage::ChildInfo;
 use sp_runtime::{
 	generic::{BlockId, SignedBlock},
 	traits::{Block as BlockT, HashFor, Header as HeaderT},
 	Justification,
 };
 
-use substrate_archive_common::{ReadOnlyDB, Result};
+use substrate_archive_common::{ArchiveError, ReadOnlyDB, Result};
 
 pub use self::state_backend::TrieState;
 use self::state_backend::{DbState, StateVault};
+use crate::block_exec::{ChildStorageCollection, StorageCollection};
 use crate::util::columns;
 
 pub struct ReadOnlyBackend<Block: BlockT, D: ReadOnlyDB> {
@@ -118,6 +120,32 @@ where
 		}
 	}
 
+	/// call `f` with every key-value pair in the state of a block, in order of key,
+	/// without loading the whole state into memory. Child tries are not included.
+	/// Returns `None` if the state of the block is not in the database.
+	pub fn for_storage_pairs(&self, hash: Block::Hash, f: impl FnMut(&[u8], &[u8])) -> Option<()> {
+		self.state_at(hash).map(|state| state.for_key_values_with_prefix(&[], f))
+	}
+
+	/// Compute the state root that results from applying `changes` and `child_changes` to the state of `parent`.
+	/// Child tries are identified by their storage key without the `:child_storage:default:` prefix.
+	/// Returns `None` if the state of `parent` is not in the database.
+	pub fn storage_root_after(
+		&self,
+		parent: Block::Hash,
+		changes: &StorageCollection,
+		child_changes: &ChildStorageCollection,
+	) -> Option<Block::Hash> {
+		let state = self.state_at(parent)?;
+		let child_infos = child_changes.iter().map(|(key, _)| ChildInfo::new_default(key)).collect::<Vec<_>>();
+		let delta = changes.iter().map(|(k, v)| (k.as_slice(), v.as_deref()));
+		let child_deltas = child_infos
+			.iter()
+			.zip(child_changes.iter())
+			.map(|(info, (_, changes))| (info, changes.iter().map(|(k, v)| (k.as_slice(), v.as_deref()))));
+		Some(state.full_storage_root(delta, child_deltas).0)
+	}
+
 	/// Get a block from the canon chain
 	/// This also tries to catch up with the primary rocksdb instance
 	pub fn block(&self, id: &BlockId<Block>) -> Option<SignedBlock<Block>> {
@@ -132,15 +160,16 @@ where
 	/// The predicate exists to reduce database reads
 	pub fn iter_blocks<'a>(
 		&'a self,
-		fun: impl Fn(u32) -> bool + 'a,
+		fun: impl Fn(u64) -> bool + 'a,
 	) -> Result<impl Iterator<Item = SignedBlock<Block>> + 'a> {
 		let readable_db = self.db.clone();
 		self.db.catch_up_with_primary()?;
 		Ok(self.db.iter(super::util::columns::KEY_LOOKUP).take_while(|(_, value)| !value.is_empty()).filter_map(
 			move |(key, value)| {
 				let arr: &[u8; 4] = key[0..4].try_into().ok()?;
+				// the lookup key encodes the number as a `u32`
 				let num = u32::from_be_bytes(*arr);
-				if key.len() == 4 && fun(num) {
+				if key.len() == 4 && fun(u64::from(num)) {
 					let head: Option<Block::Header> = readable_db
 						.get(super::util::columns::HEADER, &value)
 						.map(|bytes| Decode::decode(&mut &bytes[..]).ok())
@@ -160,6 +189,77 @@ where
 			},
 		))
 	}
+
+	/// Iterate over all blocks that match the predicate `fun`, including
+	/// blocks on forks that are not (or not yet) part of the canonical chain.
+	/// Each block is returned alongside whether it is currently on the canonical chain.
+	pub fn iter_all_blocks<'a>(
+		&'a self,
+		fun: impl Fn(u64) -> bool + 'a,
+	) -> Result<impl Iterator<Item = (SignedBlock<Block>, bool)> + 'a> {
+		let readable_db = self.db.clone();
+		self.db.catch_up_with_primary()?;
+		// headers are keyed by their lookup key, `number ++ hash`, so this also visits non-canonical blocks
+		Ok(self.db.iter(columns::HEADER).filter_map(move |(key, value)| {
+			let arr: &[u8; 4] = key.get(0..4)?.try_into().ok()?;
+			let num = u32::from_be_bytes(*arr);
+			if key.len() > 4 && fun(u64::from(num)) {
+				let head: Option<Block::Header> = Decode::decode(&mut &value[..]).ok();
+				let body: Option<Vec<Block::Extrinsic>> =
+					readable_db.get(columns::BODY, &key).map(|bytes| Decode::decode(&mut &bytes[..]).ok()).flatten();
+				let justif: Option<Justification> = readable_db
+					.get(columns::JUSTIFICATION, &key)
+					.map(|bytes| Decode::decode(&mut &bytes[..]).ok())
+					.flatten();
+				let is_canonical =
+					readable_db.get(columns::KEY_LOOKUP, &key[0..4]).map(|canon| canon[..] == key[..]).unwrap_or(false);
+				construct_block(head, body, justif).map(|b| (b, is_canonical))
+			} else {
+				None
+			}
+		}))
+	}
+
+	/// Get the hash of the canonical block for each number in `range`.
+	/// Numbers without a canonical block are skipped.
+	pub fn canonical_hashes(&self, range: std::ops::RangeInclusive<u64>) -> Result<Vec<(u64, Block::Hash)>> {
+		self.db.catch_up_with_primary()?;
+		let mut hashes = Vec::new();
+		for num in range {
+			let lookup_key = match self.db.get(columns::KEY_LOOKUP, &super::util::number_index_key(num)?) {
+				Some(k) if k.len() > 4 => k,
+				_ => continue,
+			};
+			let hash =
+				Decode::decode(&mut &lookup_key[4..]).map_err(|_| ArchiveError::from("Could not decode block hash"))?;
+			hashes.push((num, hash));
+		}
+		Ok(hashes)
+	}
+
+	/// Get the justifications of the canonical blocks in `range`.
+	/// Blocks without a justification are skipped.
+	pub fn justifications(&self, range: std::ops::RangeInclusive<u64>) -> Result<Vec<(Block::Hash, Justification)>> {
+		self.db.catch_up_with_primary()?;
+		let mut justifications = Vec::new();
+		for num in range {
+			let lookup_key = match self.db.get(columns::KEY_LOOKUP, &super::util::number_index_key(num)?) {
+				Some(k) if k.len() > 4 => k,
+				_ => continue,
+			};
+			let justification: Option<Justification> = self
+				.db
+				.get(columns::JUSTIFICATION, &lookup_key)
+				.map(|bytes| Decode::decode(&mut &bytes[..]).ok())
+				.flatten();
+			if let Some(justification) = justification {
+				let hash = Decode::decode(&mut &lookup_key[4..])
+					.map_err(|_| ArchiveError::from("Could not decode block hash"))?;
+				justifications.push((hash, justification));
+			}
+		}
+		Ok(justifications)
+	}
 }
 
 struct DbGenesisStorage<Block: BlockT>(pub Block::Hash);
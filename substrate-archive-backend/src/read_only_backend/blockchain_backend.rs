@@ -77,12 +77,12 @@ impl<Block: BlockT, D: ReadOnlyDB> BlockchainBackend<Block> for ReadOnlyBackend<
 	/// in other words, that have no children, are chain heads.
 	/// Results must be ordered best (longest, highest) chain first.
 	fn leaves(&self) -> ChainResult<Vec<Block::Hash>> {
-		unimplemented!()
+		Ok(util::read_leaves::<Block, D>(&*self.db)?.into_iter().flat_map(|(_, hashes)| hashes).collect())
 	}
 
 	/// Return hashes of all blocks that are children of the block with `parent_hash`.
-	fn children(&self, _parent_hash: Block::Hash) -> ChainResult<Vec<Block::Hash>> {
-		unimplemented!()
+	fn children(&self, parent_hash: Block::Hash) -> ChainResult<Vec<Block::Hash>> {
+		util::read_children::<Block, D>(&*self.db, parent_hash)
 	}
 
 	fn best_containing(
@@ -104,20 +104,33 @@ impl<Block: BlockT, D: ReadOnlyDB> HeaderBackend<Block> for ReadOnlyBackend<Bloc
 	fn info(&self) -> Info<Block> {
 		// TODO: Remove expect
 		let meta = util::read_meta::<Block, D>(&*self.db, columns::HEADER).expect("Metadata could not be read");
-		log::warn!("Leaves are not counted on the Read Only Backend!");
+		let number_leaves = match util::read_leaves::<Block, D>(&*self.db) {
+			Ok(leaves) => leaves.iter().map(|(_, hashes)| hashes.len()).sum(),
+			Err(e) => {
+				log::warn!("{}", e);
+				0
+			}
+		};
 		Info {
 			best_hash: meta.best_hash,
 			best_number: meta.best_number,
 			genesis_hash: meta.genesis_hash,
 			finalized_hash: meta.finalized_hash,
 			finalized_number: meta.finalized_number,
-			number_leaves: 0,
+			number_leaves,
 		}
 	}
 
-	fn status(&self, _id: BlockId<Block>) -> ChainResult<BlockStatus> {
-		log::warn!("Read Only Backend does not track Block Status!");
-		Ok(BlockStatus::Unknown)
+	fn status(&self, id: BlockId<Block>) -> ChainResult<BlockStatus> {
+		let exists = match id {
+			BlockId::Hash(hash) => self.db.get(columns::KEY_LOOKUP, hash.as_ref()).is_some(),
+			BlockId::Number(n) => util::read_meta::<Block, D>(&*self.db, columns::HEADER)?.best_number >= n,
+		};
+		if exists {
+			Ok(BlockStatus::InChain)
+		} else {
+			Ok(BlockStatus::Unknown)
+		}
 	}
 
 	fn number(&self, hash: Block::Hash) -> ChainResult<Option<<<Block as BlockT>::Header as HeaderT>::Number>> {
@@ -148,4 +161,60 @@ impl<Block: BlockT, D: ReadOnlyDB> HeaderMetadata<Block> for ReadOnlyBackend<Blo
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+	use super::*;
+	use codec::Encode;
+	use hashbrown::HashMap;
+	use sp_core::H256;
+	use sp_runtime::testing::{Block as TestBlock, ExtrinsicWrapper};
+	use substrate_archive_common::KeyValuePair;
+
+	use crate::util::meta_keys;
+
+	type Block = TestBlock<ExtrinsicWrapper<u64>>;
+
+	/// In-memory stand-in for the RocksDB secondary instance
+	#[derive(Default)]
+	struct MockDb(HashMap<(u32, Vec<u8>), Vec<u8>>);
+
+	impl ReadOnlyDB for MockDb {
+		fn get(&self, col: u32, key: &[u8]) -> Option<Vec<u8>> {
+			self.0.get(&(col, key.to_vec())).cloned()
+		}
+
+		fn iter<'a>(&'a self, col: u32) -> Box<dyn Iterator<Item = KeyValuePair> + 'a> {
+			Box::new(
+				self.0
+					.iter()
+					.filter(move |((c, _), _)| *c == col)
+					.map(|((_, k), v)| (k.clone().into_boxed_slice(), v.clone().into_boxed_slice())),
+			)
+		}
+
+		fn catch_up_with_primary(&self) -> substrate_archive_common::Result<()> {
+			Ok(())
+		}
+
+		fn open_database(_: &str, _: usize, _: std::path::PathBuf) -> sp_blockchain::Result<Self> {
+			Ok(Self::default())
+		}
+	}
+
+	#[test]
+	fn should_read_leaves_and_children() {
+		let (a, b, c) = (H256::repeat_byte(1), H256::repeat_byte(2), H256::repeat_byte(3));
+		let mut db = MockDb::default();
+		// leaves are stored highest number first
+		let leaves: Vec<(u64, Vec<H256>)> = vec![(5, vec![a, b]), (4, vec![c])];
+		db.0.insert((columns::META, meta_keys::LEAF_PREFIX.to_vec()), leaves.encode());
+		let mut children_key = meta_keys::CHILDREN_PREFIX.to_vec();
+		children_key.extend(c.encode());
+		db.0.insert((columns::META, children_key), vec![a, b].encode());
+
+		let backend = ReadOnlyBackend::<Block, MockDb>::new(Arc::new(db), true);
+		assert_eq!(backend.leaves().unwrap(), vec![a, b, c]);
+		assert_eq!(backend.children(c).unwrap(), vec![a, b]);
+		assert!(backend.children(a).unwrap().is_empty());
+		assert_eq!(backend.info().number_leaves, 3);
+	}
+}
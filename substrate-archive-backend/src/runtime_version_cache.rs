@@ -33,7 +33,7 @@ use sp_state_machine::BasicExternalities;
 use sp_storage::well_known_keys;
 use sp_version::RuntimeVersion;
 
-use substrate_archive_common::{types::Block, util, ArchiveError, ReadOnlyDB, Result};
+use substrate_archive_common::{metrics, types::Block, util, ArchiveError, ReadOnlyDB, Result};
 
 use crate::read_only_backend::ReadOnlyBackend;
 
@@ -85,8 +85,10 @@ impl<B: BlockT, D: ReadOnlyDB + 'static> RuntimeVersionCache<B, D> {
 
 		let code_hash = util::make_hash(&code);
 		if self.versions.load().contains_key(&code_hash) {
+			metrics::runtime_cache_hit();
 			Ok(self.versions.load().get(&code_hash).cloned())
 		} else {
+			metrics::runtime_cache_miss();
 			log::debug!("Adding new runtime code hash to cache: {:#X?}", code_hash);
 			let mut ext = BasicExternalities::default();
 			ext.register_extension(CallInWasmExt::new(self.exec.clone()));
@@ -116,10 +118,7 @@ impl<B: BlockT, D: ReadOnlyDB + 'static> RuntimeVersionCache<B, D> {
 	///
 	/// # Panics
 	/// panics if our search fails to get the version for a block
-	pub fn find_versions_as_blocks(&self, blocks: Vec<SignedBlock<B>>) -> Result<Vec<Block<B>>>
-	where
-		NumberFor<B>: Into<u32>,
-	{
+	pub fn find_versions_as_blocks(&self, blocks: Vec<SignedBlock<B>>) -> Result<Vec<Block<B>>> {
 		let versions = self.find_versions(&blocks)?;
 		Ok(blocks
 			.into_iter()
@@ -18,12 +18,12 @@
 
 use std::convert::TryInto;
 
-use codec::Decode;
+use codec::{Decode, Encode};
 use kvdb::DBValue;
 
 use sp_runtime::{
 	generic::BlockId,
-	traits::{Block as BlockT, Header as HeaderT, UniqueSaturatedFrom, UniqueSaturatedInto, Zero},
+	traits::{Block as BlockT, Header as HeaderT, NumberFor, UniqueSaturatedFrom, UniqueSaturatedInto, Zero},
 };
 
 use substrate_archive_common::{ArchiveError, ReadOnlyDB, Result};
@@ -185,3 +185,33 @@ pub fn read_genesis_hash<Hash: Decode, D: ReadOnlyDB>(db: &D) -> sp_blockchain::
 		None => Ok(None),
 	}
 }
+
+/// Read the leaves of the block tree from the database.
+/// Leaves are grouped by block number, highest number first.
+pub fn read_leaves<Block: BlockT, D: ReadOnlyDB>(
+	db: &D,
+) -> sp_blockchain::Result<Vec<(NumberFor<Block>, Vec<Block::Hash>)>> {
+	match db.get(columns::META, meta_keys::LEAF_PREFIX) {
+		Some(leaves) => {
+			let mut leaves: Vec<(NumberFor<Block>, Vec<Block::Hash>)> = Decode::decode(&mut &leaves[..])
+				.map_err(|err| sp_blockchain::Error::Backend(format!("Error decoding leaves: {}", err)))?;
+			leaves.sort_by(|a, b| b.0.cmp(&a.0));
+			Ok(leaves)
+		}
+		None => Ok(Vec::new()),
+	}
+}
+
+/// Read the hashes of the children of the block with hash `parent_hash` from the database.
+pub fn read_children<Block: BlockT, D: ReadOnlyDB>(
+	db: &D,
+	parent_hash: Block::Hash,
+) -> sp_blockchain::Result<Vec<Block::Hash>> {
+	let mut key = meta_keys::CHILDREN_PREFIX.to_vec();
+	parent_hash.using_encoded(|s| key.extend(s));
+	match db.get(columns::META, &key) {
+		Some(children) => Decode::decode(&mut &children[..])
+			.map_err(|err| sp_blockchain::Error::Backend(format!("Error decoding children: {}", err))),
+		None => Ok(Vec::new()),
+	}
+}
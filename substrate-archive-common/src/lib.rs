@@ -16,6 +16,7 @@
 
 mod database;
 mod error;
+pub mod metrics;
 pub mod models;
 pub mod types;
 pub mod util;
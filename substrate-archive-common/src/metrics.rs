@@ -0,0 +1,123 @@
+// Copyright 2017-2019 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Prometheus metrics for the stages of the archive.
+//! Metrics are registered with the default prometheus registry.
+//! Without the `metrics` feature, every function in this module is a no-op.
+
+use std::time::Duration;
+
+#[cfg(feature = "metrics")]
+mod registry {
+	use once_cell::sync::Lazy;
+	use prometheus::{register_histogram, register_int_counter, register_int_gauge, Histogram, IntCounter, IntGauge};
+
+	pub static BLOCKS_CRAWLED: Lazy<IntCounter> = Lazy::new(|| {
+		register_int_counter!("archive_blocks_crawled_total", "Blocks collected from the backend")
+			.expect("metric is only registered once")
+	});
+
+	pub static BLOCKS_INSERTED: Lazy<IntCounter> = Lazy::new(|| {
+		register_int_counter!("archive_blocks_inserted_total", "Blocks inserted into the database")
+			.expect("metric is only registered once")
+	});
+
+	pub static STORAGE_INSERTED: Lazy<IntCounter> = Lazy::new(|| {
+		register_int_counter!("archive_storage_rows_inserted_total", "Storage rows inserted into the database")
+			.expect("metric is only registered once")
+	});
+
+	pub static BLOCK_EXECUTION: Lazy<Histogram> = Lazy::new(|| {
+		register_histogram!(
+			"archive_block_execution_seconds",
+			"Time taken to execute a block",
+			vec![0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0]
+		)
+		.expect("metric is only registered once")
+	});
+
+	pub static TASK_QUEUE_DEPTH: Lazy<IntGauge> = Lazy::new(|| {
+		register_int_gauge!("archive_task_queue_depth", "Blocks waiting to be executed in the background task queue")
+			.expect("metric is only registered once")
+	});
+
+	pub static RUNTIME_CACHE_HITS: Lazy<IntCounter> = Lazy::new(|| {
+		register_int_counter!("archive_runtime_version_cache_hits_total", "Runtime versions found in the cache")
+			.expect("metric is only registered once")
+	});
+
+	pub static RUNTIME_CACHE_MISSES: Lazy<IntCounter> = Lazy::new(|| {
+		register_int_counter!(
+			"archive_runtime_version_cache_misses_total",
+			"Runtime versions that had to be read from the runtime"
+		)
+		.expect("metric is only registered once")
+	});
+}
+
+/// `n` blocks were collected from the backend.
+pub fn blocks_crawled(_n: usize) {
+	#[cfg(feature = "metrics")]
+	registry::BLOCKS_CRAWLED.inc_by(_n as u64);
+}
+
+/// `n` blocks were inserted into the database.
+pub fn blocks_inserted(_n: usize) {
+	#[cfg(feature = "metrics")]
+	registry::BLOCKS_INSERTED.inc_by(_n as u64);
+}
+
+/// `n` storage rows were inserted into the database.
+pub fn storage_inserted(_n: usize) {
+	#[cfg(feature = "metrics")]
+	registry::STORAGE_INSERTED.inc_by(_n as u64);
+}
+
+/// A block took `elapsed` to execute.
+pub fn block_executed(_elapsed: Duration) {
+	#[cfg(feature = "metrics")]
+	registry::BLOCK_EXECUTION.observe(_elapsed.as_secs_f64());
+}
+
+/// There are `depth` blocks waiting in the task queue.
+pub fn task_queue_depth(_depth: u64) {
+	#[cfg(feature = "metrics")]
+	registry::TASK_QUEUE_DEPTH.set(_depth as i64);
+}
+
+/// A runtime version was found in the cache.
+pub fn runtime_cache_hit() {
+	#[cfg(feature = "metrics")]
+	registry::RUNTIME_CACHE_HITS.inc();
+}
+
+/// A runtime version was not in the cache.
+pub fn runtime_cache_miss() {
+	#[cfg(feature = "metrics")]
+	registry::RUNTIME_CACHE_MISSES.inc();
+}
+
+/// Encode every registered metric in the prometheus text format.
+#[cfg(feature = "metrics")]
+pub fn gather() -> String {
+	use prometheus::Encoder as _;
+	let mut buf = Vec::new();
+	let encoder = prometheus::TextEncoder::new();
+	if let Err(e) = encoder.encode(&prometheus::gather(), &mut buf) {
+		log::error!("Could not encode metrics: {}", e);
+	}
+	String::from_utf8(buf).unwrap_or_default()
+}
@@ -32,18 +32,20 @@ pub struct BlockModel {
 	pub id: i32,
 	pub parent_hash: Vec<u8>,
 	pub hash: Vec<u8>,
-	pub block_num: i32,
+	pub block_num: i64,
 	pub state_root: Vec<u8>,
 	pub extrinsics_root: Vec<u8>,
 	pub digest: Vec<u8>,
 	pub ext: Vec<u8>,
 	pub spec: i32,
+	pub is_canonical: bool,
+	pub justification: Option<Vec<u8>>,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct StorageModel<Block: BlockT> {
 	hash: Block::Hash,
-	block_num: u32,
+	block_num: u64,
 	full_storage: bool,
 	key: StorageKey,
 	data: Option<StorageData>,
@@ -52,7 +54,7 @@ pub struct StorageModel<Block: BlockT> {
 impl<Block: BlockT> StorageModel<Block> {
 	pub fn new(
 		hash: Block::Hash,
-		block_num: u32,
+		block_num: u64,
 		full_storage: bool,
 		key: StorageKey,
 		data: Option<StorageData>,
@@ -64,7 +66,7 @@ impl<Block: BlockT> StorageModel<Block> {
 		self.full_storage
 	}
 
-	pub fn block_num(&self) -> u32 {
+	pub fn block_num(&self) -> u64 {
 		self.block_num
 	}
 
@@ -81,6 +83,117 @@ impl<Block: BlockT> StorageModel<Block> {
 	}
 }
 
+/// A single change to a key in a child trie
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ChildStorageModel<Block: BlockT> {
+	hash: Block::Hash,
+	block_num: u64,
+	full_storage: bool,
+	child_key: StorageKey,
+	key: StorageKey,
+	data: Option<StorageData>,
+}
+
+impl<Block: BlockT> ChildStorageModel<Block> {
+	pub fn new(
+		hash: Block::Hash,
+		block_num: u64,
+		full_storage: bool,
+		child_key: StorageKey,
+		key: StorageKey,
+		data: Option<StorageData>,
+	) -> Self {
+		Self { hash, block_num, full_storage, child_key, key, data }
+	}
+
+	pub fn is_full(&self) -> bool {
+		self.full_storage
+	}
+
+	pub fn block_num(&self) -> u64 {
+		self.block_num
+	}
+
+	pub fn hash(&self) -> &Block::Hash {
+		&self.hash
+	}
+
+	/// The storage key of the child trie this change belongs to
+	pub fn child_key(&self) -> &StorageKey {
+		&self.child_key
+	}
+
+	pub fn key(&self) -> &StorageKey {
+		&self.key
+	}
+
+	pub fn data(&self) -> Option<&StorageData> {
+		self.data.as_ref()
+	}
+}
+
+/// A single extrinsic, decoded with the metadata of the runtime version of the block it is in
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ExtrinsicModel<Block: BlockT> {
+	/// hash of the block the extrinsic is in
+	pub hash: Block::Hash,
+	pub block_num: u64,
+	/// position of the extrinsic in the block
+	pub index: u32,
+	/// address of the account that signed the extrinsic. `None` for unsigned extrinsics.
+	pub signer: Option<serde_json::Value>,
+	pub nonce: Option<u64>,
+	pub tip: Option<u128>,
+	pub pallet: String,
+	pub call: String,
+	pub args: serde_json::Value,
+	/// whether the extrinsic was applied successfully. `None` if this could not be determined.
+	pub success: Option<bool>,
+}
+
+/// A single event, decoded with the metadata of the runtime version of the block it was emitted in
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct EventModel<Block: BlockT> {
+	/// hash of the block the event was emitted in
+	pub hash: Block::Hash,
+	pub block_num: u64,
+	/// position of the event in `System::Events`
+	pub index: u32,
+	/// index of the extrinsic which emitted the event.
+	/// `None` for events emitted while initializing or finalizing the block,
+	/// and if the extrinsics of the block could not be decoded.
+	pub extrinsic_index: Option<u32>,
+	/// phase of block execution the event was emitted in
+	pub phase: String,
+	pub pallet: String,
+	pub event: String,
+	pub fields: serde_json::Value,
+}
+
+/// A single item of the digest of a block header
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct DigestLogModel<Block: BlockT> {
+	/// hash of the block the digest belongs to
+	pub hash: Block::Hash,
+	pub block_num: u64,
+	/// position of the item in the digest
+	pub index: u32,
+	/// kind of digest item, e.g. `PreRuntime`, `Seal` or `RuntimeEnvironmentUpdated`
+	pub kind: String,
+	/// consensus engine of `PreRuntime`, `Consensus` and `Seal` items, e.g. `BABE`
+	pub engine: Option<String>,
+	/// the undecoded payload of the item. `None` for items without a payload
+	pub data: Option<Vec<u8>>,
+	/// slot the block was authored in, from a BABE or Aura `PreRuntime` item
+	pub slot: Option<u64>,
+	/// index of the block author in the authority set, from a BABE or Aura `PreRuntime` item
+	pub authority_index: Option<u32>,
+	/// authority id of the block author in the BABE or Aura authority set of the block
+	pub authority: Option<Vec<u8>>,
+	/// account of the block author in the session validators of the block
+	pub author: Option<Vec<u8>>,
+}
+
 impl<Block: BlockT> From<Storage<Block>> for Vec<StorageModel<Block>> {
 	fn from(original: Storage<Block>) -> Vec<StorageModel<Block>> {
 		let hash = *original.hash();
@@ -99,3 +212,26 @@ impl<Block: BlockT> From<BatchStorage<Block>> for Vec<StorageModel<Block>> {
 		original.inner.into_iter().flat_map(Vec::<StorageModel<Block>>::from).collect()
 	}
 }
+
+impl<Block: BlockT> From<&Storage<Block>> for Vec<ChildStorageModel<Block>> {
+	fn from(original: &Storage<Block>) -> Vec<ChildStorageModel<Block>> {
+		let hash = *original.hash();
+		let block_num = original.block_num();
+		// snapshots only cover the main trie, so child storage is always the changes of the block
+		original
+			.child_changes()
+			.iter()
+			.flat_map(|(child_key, changes)| {
+				changes.iter().map(move |(key, data)| {
+					ChildStorageModel::new(hash, block_num, false, child_key.clone(), key.clone(), data.clone())
+				})
+			})
+			.collect()
+	}
+}
+
+impl<Block: BlockT> From<&BatchStorage<Block>> for Vec<ChildStorageModel<Block>> {
+	fn from(original: &BatchStorage<Block>) -> Vec<ChildStorageModel<Block>> {
+		original.inner().iter().flat_map(Vec::<ChildStorageModel<Block>>::from).collect()
+	}
+}
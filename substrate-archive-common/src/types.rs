@@ -21,17 +21,28 @@ use xtra::Message;
 use sp_runtime::{generic::SignedBlock, traits::Block as BlockT};
 use sp_storage::{StorageData, StorageKey};
 
-use crate::error::Result;
+use crate::{
+	error::Result,
+	models::{DigestLogModel, EventModel, ExtrinsicModel},
+};
 
 #[derive(Clone, Debug, Encode, Decode)]
 pub struct Block<B: BlockT> {
 	pub inner: SignedBlock<B>,
 	pub spec: u32,
+	/// whether this block is part of the canonical chain
+	pub is_canonical: bool,
 }
 
 impl<B: BlockT> Block<B> {
 	pub fn new(block: SignedBlock<B>, spec: u32) -> Self {
-		Self { inner: block, spec }
+		Self { inner: block, spec, is_canonical: true }
+	}
+
+	/// Mark whether this block is part of the canonical chain
+	pub fn canonical(mut self, is_canonical: bool) -> Self {
+		self.is_canonical = is_canonical;
+		self
 	}
 }
 
@@ -39,15 +50,22 @@ impl<B: BlockT> Message for Block<B> {
 	type Result = ();
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Metadata {
 	version: u32,
 	meta: Vec<u8>,
+	runtime_version: Option<serde_json::Value>,
 }
 
 impl Metadata {
 	pub fn new(version: u32, meta: Vec<u8>) -> Self {
-		Self { version, meta }
+		Self { version, meta, runtime_version: None }
+	}
+
+	/// Attach the full runtime version, serialized the way `state_getRuntimeVersion` returns it
+	pub fn with_runtime_version(mut self, runtime_version: serde_json::Value) -> Self {
+		self.runtime_version = Some(runtime_version);
+		self
 	}
 
 	pub fn version(&self) -> u32 {
@@ -57,14 +75,18 @@ impl Metadata {
 	pub fn meta(&self) -> &[u8] {
 		self.meta.as_slice()
 	}
+
+	pub fn runtime_version(&self) -> Option<&serde_json::Value> {
+		self.runtime_version.as_ref()
+	}
 }
 
 impl Message for Metadata {
-	type Result = ();
+	type Result = Result<()>;
 }
 
 /// NewType for committing many blocks to the database at once
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct BatchBlock<B: BlockT> {
 	pub inner: Vec<Block<B>>,
 }
@@ -80,33 +102,39 @@ impl<B: BlockT> BatchBlock<B> {
 }
 
 impl<B: BlockT> Message for BatchBlock<B> {
-	type Result = ();
+	type Result = Result<()>;
 }
 
+/// Changes to the storage of a single child trie.
+/// The key is the storage key of the child trie (without the `:child_storage:default:` prefix).
+pub type ChildStorageChanges = (StorageKey, Vec<(StorageKey, Option<StorageData>)>);
+
 /// NewType for Storage Data
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Storage<Block: BlockT> {
 	hash: Block::Hash,
-	block_num: u32,
+	block_num: u64,
 	full_storage: bool,
 	pub changes: Vec<(StorageKey, Option<StorageData>)>,
+	pub child_changes: Vec<ChildStorageChanges>,
 }
 
 impl<Block: BlockT> Storage<Block> {
 	pub fn new(
 		hash: Block::Hash,
-		block_num: u32,
+		block_num: u64,
 		full_storage: bool,
 		changes: Vec<(StorageKey, Option<StorageData>)>,
+		child_changes: Vec<ChildStorageChanges>,
 	) -> Self {
-		Self { block_num, hash, full_storage, changes }
+		Self { block_num, hash, full_storage, changes, child_changes }
 	}
 
 	pub fn is_full(&self) -> bool {
 		self.full_storage
 	}
 
-	pub fn block_num(&self) -> u32 {
+	pub fn block_num(&self) -> u64 {
 		self.block_num
 	}
 
@@ -117,13 +145,31 @@ impl<Block: BlockT> Storage<Block> {
 	pub fn changes(&self) -> &[(StorageKey, Option<StorageData>)] {
 		self.changes.as_slice()
 	}
+
+	pub fn child_changes(&self) -> &[ChildStorageChanges] {
+		self.child_changes.as_slice()
+	}
+
+	/// Turn the changes of a block into the part of a full snapshot of its state that is not stored
+	/// as [`Storage::snapshot`] chunks: the keys the block deleted, so that the snapshot records their removal.
+	/// Child storage is not part of the snapshot and keeps only the changes of the block.
+	pub fn into_full(self) -> Self {
+		let changes = self.changes.into_iter().filter(|(_, value)| value.is_none()).collect();
+		Self { changes, full_storage: true, ..self }
+	}
+
+	/// A chunk of the key-value pairs in the state of a block, stored as part of a full snapshot of the state
+	pub fn snapshot(hash: Block::Hash, block_num: u64, pairs: Vec<(StorageKey, StorageData)>) -> Self {
+		let changes = pairs.into_iter().map(|(key, value)| (key, Some(value))).collect();
+		Self::new(hash, block_num, true, changes, Vec::new())
+	}
 }
 
 impl<Block: BlockT> Message for Storage<Block> {
 	type Result = ();
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct BatchStorage<B: BlockT> {
 	pub inner: Vec<Storage<B>>,
 }
@@ -139,7 +185,40 @@ impl<B: BlockT> BatchStorage<B> {
 }
 
 impl<B: BlockT> Message for BatchStorage<B> {
-	type Result = ();
+	type Result = Result<()>;
+}
+
+/// NewType for committing the decoded extrinsics, events and digest logs of many blocks to the database at once.
+/// Events reference extrinsics, so both are committed together.
+#[derive(Clone, Debug)]
+pub struct BatchDecoded<B: BlockT> {
+	pub extrinsics: Vec<ExtrinsicModel<B>>,
+	pub events: Vec<EventModel<B>>,
+	pub digest_logs: Vec<DigestLogModel<B>>,
+}
+
+impl<B: BlockT> BatchDecoded<B> {
+	pub fn new(
+		extrinsics: Vec<ExtrinsicModel<B>>,
+		events: Vec<EventModel<B>>,
+		digest_logs: Vec<DigestLogModel<B>>,
+	) -> Self {
+		Self { extrinsics, events, digest_logs }
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.extrinsics.is_empty() && self.events.is_empty() && self.digest_logs.is_empty()
+	}
+
+	pub fn extend(&mut self, other: BatchDecoded<B>) {
+		self.extrinsics.extend(other.extrinsics);
+		self.events.extend(other.events);
+		self.digest_logs.extend(other.digest_logs);
+	}
+}
+
+impl<B: BlockT> Message for BatchDecoded<B> {
+	type Result = Result<()>;
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -20,47 +20,151 @@ mod actor_pool;
 mod workers;
 
 use std::marker::PhantomData;
+use std::ops::RangeInclusive;
 use std::panic::AssertUnwindSafe;
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::{
+	atomic::{AtomicU64, Ordering},
+	Arc,
+};
+use std::time::{Duration, Instant};
 
-use coil::Job as _;
+use codec::Decode;
 use futures::{future::BoxFuture, FutureExt};
 use hashbrown::HashSet;
+use parking_lot::Mutex;
 use serde::de::DeserializeOwned;
+use sqlx::Connection as _;
 use xtra::{prelude::*, spawn::Smol, Disconnected};
 
 use sc_client_api::backend;
 use sp_api::{ApiExt, ConstructRuntimeApi};
 use sp_block_builder::BlockBuilder as BlockBuilderApi;
-use sp_runtime::traits::{Block as BlockT, Header as _, NumberFor};
+use sp_blockchain::{Backend as _, HeaderBackend as _};
+use sp_runtime::{
+	generic::BlockId,
+	traits::{Block as BlockT, NumberFor, SaturatedConversion},
+};
 
-use substrate_archive_backend::{ApiAccess, Meta, ReadOnlyBackend};
+use substrate_archive_backend::{ApiAccess, ChildStorageCollection, Meta, ReadOnlyBackend, StorageCollection};
 use substrate_archive_common::{types::Die, ReadOnlyDB, Result};
 
 pub use self::actor_pool::ActorPool;
 use self::workers::GetState;
-pub use self::workers::{BlocksIndexer, DatabaseActor, StorageAggregator};
+pub use self::workers::{BlocksIndexer, DatabaseActor, Executed, StorageAggregator};
 use crate::{
 	database::{queries, Channel, Listener},
+	decode::Decoder,
+	filter::StorageFilter,
+	sink::{ArchiveSink, PostgresIndex, PostgresSink, Sinks},
 	sql_block_builder::SqlBlockBuilder,
-	tasks::Environment,
-	traits::Archive,
+	tasks::{Environment, RunningTasks, Snapshots},
+	traits::{Archive, ShutdownReport, Status, VerificationReport},
 };
 
+/// Maximum number of blocks moved from the `_block_outbox` table into the task queue in one transaction
+const OUTBOX_BATCH: u32 = 512;
+/// Longest time a block may wait in the `_block_outbox` table if no notification about it is received
+const OUTBOX_INTERVAL: Duration = Duration::from_secs(5);
+/// Maximum number of blocks loaded at once while verifying storage
+const VERIFY_BATCH: u64 = 1000;
+/// Shortest time the indexing rate is measured over
+const RATE_WINDOW: Duration = Duration::from_secs(10);
+
 // TODO: Split this up into two objects
 // System should be a factory that produces objects that should be spawned
 
+/// Options that control how the chain is indexed
+#[derive(Clone, Debug)]
+pub struct SystemConfig {
+	/// number of threads to spawn for block execution
+	pub workers: usize,
+	/// url to the Postgres database
+	pub pg_url: String,
+	/// maximum amount of blocks to index at once
+	pub max_block_load: u32,
+	/// index blocks on forks as well as the canonical chain
+	pub index_forks: bool,
+	/// first block number to index
+	pub start_block: Option<u64>,
+	/// last block number to index. The archive stops once every block up to here is indexed and executed
+	pub end_block: Option<u64>,
+	/// only index finalized blocks
+	pub finalized_only: bool,
+	/// only index blocks at least this many blocks below the best block
+	pub confirmation_depth: Option<u64>,
+	/// decode extrinsics and events of executed blocks
+	pub decode: bool,
+	/// insert blocks and storage with binary `COPY` instead of `INSERT` statements
+	pub copy_insert: bool,
+	/// write storage and decoded data to Postgres, not only to the other sinks
+	pub postgres_sink: bool,
+	/// how long to wait for running tasks and buffered storage on shutdown
+	pub shutdown_timeout: Duration,
+	/// storage keys to index
+	pub storage_filter: StorageFilter,
+	/// when to store the full state of a block
+	pub snapshots: Snapshots,
+}
+
+/// Counters the actors update as they index the chain, read by `System::status`
+#[derive(Default)]
+pub struct Progress {
+	/// the last maximum block number of the `BlocksIndexer`
+	pub last_max: AtomicU64,
+	/// number of blocks whose storage has been inserted
+	pub blocks_stored: AtomicU64,
+	/// the indexing rate, measured as blocks are stored
+	rate: Mutex<RateSample>,
+}
+
+#[derive(Default)]
+struct RateSample {
+	/// start of the current measurement, and the number of blocks stored at that time
+	since: Option<(Instant, u64)>,
+	/// blocks stored per second during the last measurement
+	per_second: f64,
+}
+
+impl Progress {
+	/// Count `n` more blocks whose storage has been inserted
+	pub fn stored(&self, n: u64) {
+		let stored = self.blocks_stored.fetch_add(n, Ordering::Relaxed) + n;
+		let mut rate = self.rate.lock();
+		match rate.since {
+			Some((start, start_stored)) if start.elapsed() >= RATE_WINDOW => {
+				rate.per_second = stored.saturating_sub(start_stored) as f64 / start.elapsed().as_secs_f64();
+				rate.since = Some((Instant::now(), stored));
+			}
+			Some(_) => {}
+			None => rate.since = Some((Instant::now(), stored)),
+		}
+	}
+
+	/// Blocks stored per second
+	pub fn blocks_per_second(&self) -> f64 {
+		let rate = self.rate.lock();
+		match rate.since {
+			// nothing has been stored for a whole window, so the last measurement is outdated
+			Some((start, start_stored)) if start.elapsed() >= RATE_WINDOW => {
+				let stored = self.blocks_stored.load(Ordering::Relaxed);
+				stored.saturating_sub(start_stored) as f64 / start.elapsed().as_secs_f64()
+			}
+			_ => rate.per_second,
+		}
+	}
+}
+
 /// Context that every actor may use
 pub struct ActorContext<B: BlockT + Unpin, D: ReadOnlyDB + 'static>
 where
 	B::Hash: Unpin,
 {
 	backend: Arc<ReadOnlyBackend<B, D>>,
-	pg_url: String,
 	meta: Meta<B>,
-	workers: usize,
-	max_block_load: u32,
+	config: SystemConfig,
+	progress: Arc<Progress>,
+	/// sinks written to in addition to, or instead of, Postgres
+	sinks: Vec<Arc<dyn ArchiveSink<B>>>,
 }
 
 impl<B: BlockT + Unpin, D: ReadOnlyDB> Clone for ActorContext<B, D>
@@ -70,10 +174,10 @@ where
 	fn clone(&self) -> ActorContext<B, D> {
 		ActorContext {
 			backend: Arc::clone(&self.backend),
-			pg_url: self.pg_url.clone(),
 			meta: self.meta.clone(),
-			workers: self.workers,
-			max_block_load: self.max_block_load,
+			config: self.config.clone(),
+			progress: Arc::clone(&self.progress),
+			sinks: self.sinks.clone(),
 		}
 	}
 }
@@ -85,11 +189,10 @@ where
 	pub fn new(
 		backend: Arc<ReadOnlyBackend<B, D>>,
 		meta: Meta<B>,
-		workers: usize,
-		pg_url: String,
-		max_block_load: u32,
+		config: SystemConfig,
+		sinks: Vec<Arc<dyn ArchiveSink<B>>>,
 	) -> Self {
-		Self { backend, meta, workers, pg_url, max_block_load }
+		Self { backend, meta, config, progress: Arc::new(Progress::default()), sinks }
 	}
 
 	pub fn backend(&self) -> &Arc<ReadOnlyBackend<B, D>> {
@@ -97,19 +200,39 @@ where
 	}
 
 	pub fn pg_url(&self) -> &str {
-		self.pg_url.as_str()
+		self.config.pg_url.as_str()
+	}
+
+	pub fn config(&self) -> &SystemConfig {
+		&self.config
+	}
+
+	pub fn progress(&self) -> &Arc<Progress> {
+		&self.progress
+	}
+
+	pub fn sinks(&self) -> &[Arc<dyn ArchiveSink<B>>] {
+		self.sinks.as_slice()
 	}
+
 	pub fn meta(&self) -> &Meta<B> {
 		&self.meta
 	}
 }
 
+/// Services started alongside the actors, stopped on shutdown
+#[derive(Default)]
+struct Services {
+	listener: Option<Listener>,
+	relay: Option<smol::Task<()>>,
+}
+
 struct Actors<B: BlockT + Unpin, D: ReadOnlyDB + 'static>
 where
 	B::Hash: Unpin,
-	NumberFor<B>: Into<u32>,
 {
 	storage: Address<workers::StorageAggregator<B>>,
+	sinks: Sinks<B>,
 	blocks: Address<workers::BlocksIndexer<B, D>>,
 	metadata: Address<workers::MetadataActor<B>>,
 	db_pool: Address<ActorPool<DatabaseActor<B>>>,
@@ -122,13 +245,16 @@ where
 	D: ReadOnlyDB + 'static,
 	B: BlockT + Unpin,
 	B::Hash: Unpin,
-	NumberFor<B>: Into<u32>,
 {
 	start_tx: flume::Sender<()>,
 	kill_tx: flume::Sender<()>,
+	/// disconnected once the futures runtime has stopped
+	stopped_rx: flume::Receiver<()>,
 	context: ActorContext<B, D>,
+	/// connections for `status`
+	pool: sqlx::PgPool,
 	/// handle to the futures runtime indexing the running chain
-	handle: jod_thread::JoinHandle<Result<()>>,
+	handle: jod_thread::JoinHandle<Result<ShutdownReport>>,
 	_marker: PhantomData<(B, R, C, D)>,
 }
 
@@ -144,7 +270,7 @@ where
 		+ Sync
 		+ 'static,
 	C: ApiAccess<B, ReadOnlyBackend<B, D>, R> + 'static,
-	NumberFor<B>: Into<u32> + From<u32> + Unpin,
+	NumberFor<B>: Unpin,
 	B::Hash: From<primitive_types::H256> + Unpin,
 	B::Header: serde::de::DeserializeOwned,
 {
@@ -154,58 +280,133 @@ where
 	// TODO: Accept one `Config` Struct for which a builder is implemented on
 	// to make configuring this easier.
 	/// Initialize substrate archive.
-	/// Requires a substrate client, url to a running RPC node, and a list of keys to index from storage.
-	/// Optionally accepts a URL to the postgreSQL database. However, this can be defined as the
-	/// environment variable `DATABASE_URL` instead.
+	/// Requires a substrate client, the backend to read blocks from, the configuration
+	/// of the indexer, which includes the URL to the postgreSQL database, and any sinks
+	/// to write to in addition to Postgres.
 	pub fn new(
 		// one client per-threadpool. This way we don't have conflicting cache resources
 		// for WASM runtime-instances
 		client_api: Arc<C>,
 		backend: Arc<ReadOnlyBackend<B, D>>,
-		workers: usize,
-		pg_url: &str,
-		max_block_load: u32,
+		config: SystemConfig,
+		sinks: Vec<Arc<dyn ArchiveSink<B>>>,
 	) -> Result<Self> {
-		let context = ActorContext::new(backend, client_api.clone(), workers, pg_url.to_string(), max_block_load);
-		let (start_tx, kill_tx, handle) = Self::start(context.clone(), client_api);
+		let pool = sqlx::postgres::PgPoolOptions::new().max_connections(2).connect_lazy(config.pg_url.as_str())?;
+		let context = ActorContext::new(backend, client_api.clone(), config, sinks);
+		let (stopped_tx, stopped_rx) = flume::bounded(0);
+		let (start_tx, kill_tx, handle) = Self::start(context.clone(), client_api, stopped_tx);
 
-		Ok(Self { context, start_tx, kill_tx, handle, _marker: PhantomData })
+		Ok(Self { context, start_tx, kill_tx, stopped_rx, pool, handle, _marker: PhantomData })
 	}
 
 	fn drive(&self) {
 		self.start_tx.send(()).expect("Could not start actors");
 	}
 
-	/// Start the actors and begin driving their execution
+	/// Start the actors and begin driving their execution.
+	/// `stopped` is dropped once the actors have stopped.
 	pub fn start(
 		ctx: ActorContext<B, D>,
 		client: Arc<C>,
-	) -> (flume::Sender<()>, flume::Sender<()>, jod_thread::JoinHandle<Result<()>>) {
+		stopped: flume::Sender<()>,
+	) -> (flume::Sender<()>, flume::Sender<()>, jod_thread::JoinHandle<Result<ShutdownReport>>) {
 		let (tx_start, rx_start) = flume::bounded(1);
 		let (tx_kill, rx_kill) = flume::bounded(1);
 
 		let handle = jod_thread::spawn(move || {
-			// block until we receive the message to start
-			let _ = rx_start.recv();
-			smol::block_on(Self::main_loop(ctx, rx_kill, client))?;
-			Ok(())
+			let _stopped = stopped;
+			// block until we receive the message to start.
+			// if the archive is dropped without ever being driven, there is nothing to run
+			if rx_start.recv().is_err() {
+				return Ok(ShutdownReport::default());
+			}
+			smol::block_on(Self::main_loop(ctx, rx_kill, client))
 		});
 
 		(tx_start, tx_kill, handle)
 	}
 
-	async fn main_loop(ctx: ActorContext<B, D>, rx: flume::Receiver<()>, client: Arc<C>) -> Result<()> {
+	async fn main_loop(ctx: ActorContext<B, D>, rx: flume::Receiver<()>, client: Arc<C>) -> Result<ShutdownReport> {
 		let actors = Self::spawn_actors(ctx.clone()).await?;
+		let tasks = RunningTasks::default();
+		let mut services = Services::default();
+		let result = Self::run(&ctx, &actors, rx, client, tasks.clone(), &mut services).await;
+		if let Err(e) = &result {
+			log::error!("Indexing failed: {}", e);
+		}
+
+		let deadline = Instant::now() + ctx.config().shutdown_timeout;
+		log::info!("Shutting down, waiting up to {:?} for running tasks", ctx.config().shutdown_timeout);
+		// stop queueing new blocks before waiting on the tasks that are already running
+		tasks.stop();
+		if let Some(listener) = services.listener {
+			listener.kill_async().await;
+		}
+		if let Some(relay) = services.relay {
+			relay.cancel().await;
+		}
+		Self::stop_indexing(&actors).await;
+		let tasks_finished = Self::until(deadline, tasks.finished()).await.is_some();
+		let storage_flushed = Self::flush_and_kill(actors, deadline).await;
+		let pool = result?;
+
+		let mut conn = pool.acquire().await?;
+		let report = ShutdownReport {
+			tasks_finished,
+			storage_flushed,
+			pending_tasks: queries::count_pending_executions(&mut conn).await?,
+			outbox: queries::count_outbox(&mut conn).await?,
+		};
+		Self::log_report(&report);
+		Ok(report)
+	}
+
+	/// Execute blocks until killed through `rx`, or until a bounded range is finished.
+	/// Jobs are only picked up from the task queue while this runs.
+	/// Returns the pool of database connections.
+	async fn run(
+		ctx: &ActorContext<B, D>,
+		actors: &Actors<B, D>,
+		rx: flume::Receiver<()>,
+		client: Arc<C>,
+		tasks: RunningTasks,
+		services: &mut Services,
+	) -> Result<sqlx::PgPool> {
 		let pool = actors.db_pool.send(GetState::Pool.into()).await?.await?.pool();
-		let listener = Self::init_listeners(ctx.pg_url()).await?;
+		let (wake_tx, wake_rx) = flume::bounded(1);
+		services.listener = Some(Self::init_listeners(ctx.pg_url(), wake_tx).await?);
 		let mut conn = pool.acquire().await?;
+		// recorded before anything is executed, since verifying storage depends on how all of it was indexed
+		let mode = queries::StorageMode {
+			filtered: !ctx.config().storage_filter.is_empty(),
+			external: !ctx.config().postgres_sink,
+		};
+		queries::record_storage_mode(&mut *conn, mode).await?;
 		Self::restore_missing_storage(&mut *conn).await?;
-		let env = Environment::<B, R, C, D>::new(ctx.backend().clone(), client, actors.storage.clone());
+		services.relay = Some(smol::spawn(Self::relay_outbox(pool.clone(), wake_rx)));
+		let decoder = if ctx.config().decode {
+			let finalized = ctx.backend().last_finalized()?;
+			let chain = client.runtime_version_at(&BlockId::Hash(finalized))?.spec_name;
+			Some(Arc::new(Decoder::new(&chain, pool.clone())))
+		} else {
+			None
+		};
+		let filter = Arc::new(ctx.config().storage_filter.clone());
+		let env = Environment::<B, R, C, D>::new(
+			ctx.backend().clone(),
+			client,
+			actors.storage.clone(),
+			actors.sinks.clone(),
+			decoder,
+			filter,
+			ctx.config().snapshots,
+			tasks,
+		);
 		let env = AssertUnwindSafe(env);
 
 		let runner = coil::Runner::builder(env, crate::TaskExecutor, &pool)
 			.register_job::<crate::tasks::execute_block::Job<B, R, C, D>>()
-			.num_threads(ctx.workers)
+			.num_threads(ctx.config().workers)
 			.timeout(Duration::from_secs(20))
 			.max_tasks(64)
 			.build()?;
@@ -213,76 +414,292 @@ where
 		loop {
 			let tasks = runner.run_all_sync_tasks().fuse();
 			futures::pin_mut!(tasks);
-			futures::select! {
-				t = tasks => {
-					if t? == 0 {
-						smol::Timer::after(std::time::Duration::from_millis(256)).await;
-					}
-				},
-				_ = rx.recv_async() => break,
+			// returning drops `tasks` as soon as the archive is killed, so no new jobs are picked up
+			let ran = futures::select! {
+				t = tasks => t?,
+				_ = rx.recv_async() => return Ok(pool),
+			};
+			if ran == 0 {
+				if Self::range_finished(ctx.config().start_block, ctx.config().end_block, &pool).await? {
+					log::info!("Finished indexing the block range, shutting down");
+					return Ok(pool);
+				}
+				smol::Timer::after(std::time::Duration::from_millis(256)).await;
 			}
 		}
-		listener.kill_async().await;
-		Self::kill_actors(actors).await?;
-		Ok(())
+	}
+
+	/// Whether every block of a bounded range is in the database, and no block is left to execute.
+	/// Always `false` if no end block is configured.
+	async fn range_finished(start_block: Option<u64>, end_block: Option<u64>, pool: &sqlx::PgPool) -> Result<bool> {
+		let end = match end_block {
+			Some(end) => end,
+			None => return Ok(false),
+		};
+		let start = start_block.unwrap_or(0);
+		let mut conn = pool.acquire().await?;
+		Ok(queries::count_missing_blocks(&mut conn, start..=end).await? == 0
+			&& queries::count_outbox(&mut conn).await? == 0
+			&& queries::count_pending_executions(&mut conn).await? == 0)
+	}
+
+	/// Resolves to the output of `fut`, or to `None` if `deadline` passes first.
+	async fn until<F: std::future::Future>(deadline: Instant, fut: F) -> Option<F::Output> {
+		let timeout = smol::Timer::at(deadline).map(|_| None);
+		smol::future::or(fut.map(Some), timeout).await
+	}
+
+	fn log_report(report: &ShutdownReport) {
+		if !report.tasks_finished {
+			log::warn!("Shutdown deadline passed before running tasks finished, they will be retried on restart");
+		}
+		if !report.storage_flushed {
+			log::warn!("Shutdown deadline passed before buffered storage was inserted, it will be restored on restart");
+		}
+		log::info!(
+			"Shut down with {} blocks left to execute and {} blocks left in the outbox",
+			report.pending_tasks,
+			report.outbox
+		);
 	}
 
 	async fn spawn_actors(ctx: ActorContext<B, D>) -> Result<Actors<B, D>> {
-		let db = workers::DatabaseActor::<B>::new(ctx.pg_url().into()).await?;
+		let db =
+			workers::DatabaseActor::<B>::new(ctx.pg_url().into(), ctx.config().copy_insert, ctx.progress().clone())
+				.await?;
 		let db_pool = actor_pool::ActorPool::new(db, 4).create(None).spawn(&mut Smol::Global);
-		let storage = workers::StorageAggregator::new(db_pool.clone()).create(None).spawn(&mut Smol::Global);
-		let metadata = workers::MetadataActor::new(db_pool.clone(), ctx.meta().clone())
+		let postgres: Arc<dyn ArchiveSink<B>> = if ctx.config().postgres_sink {
+			Arc::new(PostgresSink::new(db_pool.clone()))
+		} else {
+			Arc::new(PostgresIndex::new(db_pool.clone()))
+		};
+		let sinks = Sinks::new(std::iter::once(postgres).chain(ctx.sinks().iter().cloned()).collect());
+		let storage = workers::StorageAggregator::new(sinks.clone()).create(None).spawn(&mut Smol::Global);
+		let metadata = workers::MetadataActor::new(db_pool.clone(), sinks.clone(), ctx.meta().clone())
 			.await?
 			.create(None)
 			.spawn(&mut Smol::Global);
 		let blocks =
 			workers::BlocksIndexer::new(ctx, db_pool.clone(), metadata.clone()).create(None).spawn(&mut Smol::Global);
-		Ok(Actors { storage, blocks, metadata, db_pool })
+		Ok(Actors { storage, sinks, blocks, metadata, db_pool })
 	}
 
-	async fn kill_actors(actors: Actors<B, D>) -> Result<()> {
-		let fut: Vec<BoxFuture<'_, Result<Result<()>, Disconnected>>> = vec![
-			Box::pin(actors.storage.send(Die)),
-			Box::pin(actors.blocks.send(Die)),
-			Box::pin(actors.metadata.send(Die)),
-		];
+	/// Stop the actors that crawl the backend for new blocks.
+	async fn stop_indexing(actors: &Actors<B, D>) {
+		let fut: Vec<BoxFuture<'_, Result<Result<()>, Disconnected>>> =
+			vec![Box::pin(actors.blocks.send(Die)), Box::pin(actors.metadata.send(Die))];
 		futures::future::join_all(fut).await;
-		let _ = actors.db_pool.send(Die.into()).await?.await;
-		Ok(())
 	}
 
-	async fn init_listeners(pg_url: &str) -> Result<Listener> {
-		Listener::builder(pg_url, move |notif, conn| {
-			async move {
-				let block = queries::get_full_block_by_id(conn, notif.id).await?;
-				let b: (B, u32) = SqlBlockBuilder::with_single(block)?;
-				crate::tasks::execute_block::<B, R, C, D>(b.0, PhantomData).enqueue(conn).await?;
-				Ok(())
+	/// Insert the storage still buffered by the `StorageAggregator` and stop the remaining actors.
+	/// Returns whether the storage was inserted before `deadline`.
+	async fn flush_and_kill(actors: Actors<B, D>, deadline: Instant) -> bool {
+		let flushed = match Self::until(deadline, actors.storage.send(Die)).await {
+			Some(Ok(Ok(()))) => true,
+			Some(Ok(Err(e))) => {
+				log::error!("Failed inserting buffered storage: {}", e);
+				false
 			}
-			.boxed()
+			Some(Err(_)) | None => false,
+		};
+		if let Ok(fut) = actors.db_pool.send(Die.into()).await {
+			let _ = fut.await;
+		}
+		flushed
+	}
+
+	/// Wakes up the outbox relay whenever a new block is inserted.
+	async fn init_listeners(pg_url: &str, wake: flume::Sender<()>) -> Result<Listener> {
+		Listener::builder(pg_url, move |_, _| {
+			// if the channel is full the relay is already going to run
+			let _ = wake.try_send(());
+			async { Ok(()) }.boxed()
 		})
 		.listen_on(Channel::Blocks)
 		.spawn()
 		.await
 	}
 
+	/// Moves blocks from the `_block_outbox` table into the task queue.
+	/// Runs whenever the listener is notified of a new block, and at least once every `OUTBOX_INTERVAL`,
+	/// so a lost notification only delays the execution of a block.
+	async fn relay_outbox(pool: sqlx::PgPool, wake: flume::Receiver<()>) {
+		loop {
+			match Self::drain_outbox(&pool).await {
+				// there may be more blocks waiting
+				Ok(n) if n == OUTBOX_BATCH as usize => continue,
+				Ok(_) => (),
+				Err(e) => log::error!("Could not queue blocks from the outbox: {}", e),
+			}
+			#[cfg(feature = "metrics")]
+			Self::report_queue_depth(&pool).await;
+			let timeout = smol::Timer::after(OUTBOX_INTERVAL);
+			futures::select! {
+				_ = FutureExt::fuse(timeout) => {},
+				r = wake.recv_async() => {
+					// the listener has stopped, fall back to polling
+					if r.is_err() {
+						smol::Timer::after(OUTBOX_INTERVAL).await;
+					}
+				},
+			}
+		}
+	}
+
+	/// Update the task queue depth metric.
+	#[cfg(feature = "metrics")]
+	async fn report_queue_depth(pool: &sqlx::PgPool) {
+		let depth = match pool.acquire().await {
+			Ok(mut conn) => queries::count_pending_executions(&mut conn).await,
+			Err(e) => Err(e.into()),
+		};
+		match depth {
+			Ok(depth) => substrate_archive_common::metrics::task_queue_depth(depth),
+			Err(e) => log::warn!("Could not count pending block executions: {}", e),
+		}
+	}
+
+	/// Queues an `execute_block` task for blocks in the `_block_outbox` table, and removes them from the outbox
+	/// in the same transaction.
+	/// Returns the number of blocks queued.
+	async fn drain_outbox(pool: &sqlx::PgPool) -> Result<usize> {
+		let mut tx = pool.begin().await?;
+		let blocks = queries::outbox_blocks(&mut *tx, OUTBOX_BATCH).await?;
+		if blocks.is_empty() {
+			return Ok(0);
+		}
+		let hashes: Vec<Vec<u8>> = blocks.iter().map(|b| b.hash.clone()).collect();
+		let jobs: Vec<crate::tasks::execute_block::Job<B, R, C, D>> = SqlBlockBuilder::with_vec(blocks)?
+			.into_iter()
+			.map(|b| crate::tasks::execute_block::<B, R, C, D>(b.inner.block, PhantomData))
+			.collect();
+		let len = jobs.len();
+		coil::JobExt::enqueue_batch(jobs, &mut *tx).await?;
+		queries::remove_from_outbox(&hashes, &mut *tx).await?;
+		tx.commit().await?;
+		log::debug!("Queued {} blocks for execution", len);
+		Ok(len)
+	}
+
+	/// Deletes what executing the blocks in `range` produced, and queues the blocks for execution again
+	/// in the same transaction.
+	/// Returns the number of blocks queued.
+	async fn requeue_range(pg_url: &str, range: RangeInclusive<u64>) -> Result<u64> {
+		let mut conn = sqlx::PgConnection::connect(pg_url).await?;
+		let mut tx = conn.begin().await?;
+		let blocks = queries::blocks_in_range(&mut *tx, range.clone()).await?;
+		let deleted = queries::delete_execution_results(&mut *tx, range.clone()).await?;
+		let jobs: Vec<crate::tasks::execute_block::Job<B, R, C, D>> = SqlBlockBuilder::with_vec(blocks)?
+			.into_iter()
+			.map(|b| crate::tasks::execute_block::<B, R, C, D>(b.inner.block, PhantomData))
+			.collect();
+		let len = jobs.len();
+		if !jobs.is_empty() {
+			coil::JobExt::enqueue_batch(jobs, &mut *tx).await?;
+		}
+		tx.commit().await?;
+		log::info!(
+			"Deleted {} rows and queued {} blocks between {} and {} for execution",
+			deleted,
+			len,
+			range.start(),
+			range.end()
+		);
+		Ok(len as u64)
+	}
+
+	/// Applies the archived storage of each block in `range` to the state of its parent with `storage_root`,
+	/// and compares the storage root to the state root in the block's header.
+	/// Blocks and their storage are loaded `VERIFY_BATCH` blocks at a time.
+	/// Fails if storage has ever been indexed through a storage filter, or only to sinks other than Postgres.
+	async fn verify_range(
+		conn: &mut sqlx::PgConnection,
+		range: RangeInclusive<u64>,
+		storage_root: impl Fn(B::Hash, &StorageCollection, &ChildStorageCollection) -> Option<B::Hash>,
+	) -> Result<VerificationReport> {
+		let mode = queries::storage_mode(conn).await?;
+		if mode.filtered {
+			return Err(
+				"storage cannot be verified, since it was indexed with a storage filter and is incomplete".into()
+			);
+		}
+		if mode.external {
+			return Err("storage cannot be verified, since it was written to sinks other than Postgres".into());
+		}
+		let mut report = VerificationReport::default();
+		let (mut from, end) = range.into_inner();
+		loop {
+			let to = from.saturating_add(VERIFY_BATCH - 1).min(end);
+			let mut storage = queries::storage_in_range(conn, from..=to).await?;
+			let mut child_storage = queries::child_storage_in_range(conn, from..=to).await?;
+			let (mut verified, mut mismatched) = (Vec::new(), Vec::new());
+			for block in queries::blocks_in_range(conn, from..=to).await? {
+				let changes = match storage.remove(&block.hash) {
+					Some(changes) => changes,
+					None => {
+						report.skipped += 1;
+						continue;
+					}
+				};
+				let child_changes = child_storage.remove(&block.hash).unwrap_or_default();
+				let parent = Decode::decode(&mut block.parent_hash.as_slice())?;
+				let computed = match storage_root(parent, &changes, &child_changes) {
+					Some(root) => root,
+					None => {
+						report.skipped += 1;
+						continue;
+					}
+				};
+				let block_num = block.block_num as u64;
+				if computed.as_ref() == block.state_root.as_slice() {
+					verified.push(block.hash);
+				} else {
+					log::warn!(
+						"Storage of block {} ({}) does not produce its state root",
+						block_num,
+						hex::encode(&block.hash)
+					);
+					mismatched.push(queries::StorageRootMismatch {
+						hash: block.hash,
+						block_num,
+						expected: block.state_root,
+						computed: computed.as_ref().to_vec(),
+					});
+				}
+			}
+			if !verified.is_empty() {
+				queries::remove_storage_root_mismatches(conn, &verified).await?;
+			}
+			if !mismatched.is_empty() {
+				queries::insert_storage_root_mismatches(conn, &mismatched).await?;
+			}
+			report.verified += verified.len() as u64;
+			report.mismatched.extend(mismatched.into_iter().map(|m| (m.block_num, m.hash)));
+			if to >= end {
+				break;
+			}
+			from = to + 1;
+		}
+		log::info!(
+			"Verified storage: {} blocks matched, {} mismatched, {} skipped",
+			report.verified,
+			report.mismatched.len(),
+			report.skipped
+		);
+		Ok(report)
+	}
+
 	/// Checks if any blocks that should be executed are missing
 	/// from the task queue.
 	/// If any are found, they are re-queued.
 	async fn restore_missing_storage(conn: &mut sqlx::PgConnection) -> Result<()> {
-		let blocks: HashSet<u32> = queries::get_all_blocks::<B>(conn)
+		// blocks are compared by hash, since there may be more than one block per height when indexing forks
+		let blocks: HashSet<Vec<u8>> = queries::get_all_blocks::<B>(conn)
 			.await?
-			.map(|b| Ok((*b?.header().number()).into()))
+			.map(|b| Ok(b?.hash().as_ref().to_vec()))
 			.collect::<Result<_>>()?;
 		let mut missing_storage_blocks = queries::blocks_storage_intersection(conn).await?;
-		let difference: HashSet<u32> = missing_storage_blocks
-			.iter()
-			.map(|b| b.block_num as u32)
-			.collect::<HashSet<u32>>()
-			.difference(&blocks)
-			.copied()
-			.collect();
-		missing_storage_blocks.retain(|b| difference.contains(&(b.block_num as u32)));
+		missing_storage_blocks.retain(|b| !blocks.contains(&b.hash));
 		let jobs: Vec<crate::tasks::execute_block::Job<B, R, C, D>> =
 			SqlBlockBuilder::with_vec(missing_storage_blocks)?
 				.into_iter()
@@ -309,7 +726,7 @@ where
 		+ Sync
 		+ 'static,
 	C: ApiAccess<B, ReadOnlyBackend<B, D>, R> + 'static,
-	NumberFor<B>: Into<u32> + From<u32> + Unpin,
+	NumberFor<B>: Unpin,
 	B::Hash: From<primitive_types::H256> + Unpin,
 	B::Header: serde::de::DeserializeOwned,
 {
@@ -319,24 +736,247 @@ where
 	}
 
 	async fn block_until_stopped(&self) {
-		loop {
-			smol::Timer::after(std::time::Duration::from_secs(1)).await;
-		}
+		// nothing is ever sent, the channel only disconnects
+		let _ = self.stopped_rx.recv_async().await;
+	}
+
+	fn is_stopped(&self) -> bool {
+		self.stopped_rx.is_disconnected()
 	}
 
-	fn shutdown(self) -> Result<()> {
+	fn shutdown(self) -> Result<ShutdownReport> {
 		let _ = self.kill_tx.send(());
-		self.handle.join()?;
-		Ok(())
+		self.handle.join()
 	}
 
-	fn boxed_shutdown(self: Box<Self>) -> Result<()> {
+	fn boxed_shutdown(self: Box<Self>) -> Result<ShutdownReport> {
 		let _ = self.kill_tx.send(());
-		self.handle.join()?;
-		Ok(())
+		self.handle.join()
 	}
 
 	fn context(&self) -> Result<super::actors::ActorContext<B, D>> {
 		Ok(self.context.clone())
 	}
+
+	async fn status(&self) -> Result<Status> {
+		let info = self.context.backend().info();
+		let mut conn = self.pool.acquire().await?;
+		let max_indexed_block = queries::max_block(&mut conn).await?;
+		let pending_tasks = queries::count_pending_executions(&mut conn).await?;
+		let missing_storage = queries::count_missing_storage(&mut conn).await?;
+
+		let progress = self.context.progress();
+
+		Ok(Status {
+			best_block: info.best_number.saturated_into(),
+			finalized_block: info.finalized_number.saturated_into(),
+			max_indexed_block,
+			last_max: progress.last_max.load(Ordering::Relaxed),
+			pending_tasks,
+			missing_storage,
+			blocks_per_second: progress.blocks_per_second(),
+		})
+	}
+
+	fn reexecute(&self, range: RangeInclusive<u64>) -> Result<u64> {
+		if range.start() > range.end() {
+			return Err(format!("invalid range {} to {}", range.start(), range.end()).into());
+		}
+		smol::block_on(Self::requeue_range(self.context.pg_url(), range))
+	}
+
+	fn verify(&self, range: RangeInclusive<u64>) -> Result<VerificationReport> {
+		if range.start() > range.end() {
+			return Err(format!("invalid range {} to {}", range.start(), range.end()).into());
+		}
+		let backend = self.context.backend();
+		smol::block_on(async {
+			let mut conn = sqlx::PgConnection::connect(self.context.pg_url()).await?;
+			Self::verify_range(&mut conn, range, |parent, changes, child_changes| {
+				backend.storage_root_after(parent, changes, child_changes)
+			})
+			.await
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use codec::Encode;
+	use polkadot_service::{kusama_runtime::RuntimeApi, Block, KusamaExecutor};
+	use sp_core::H256;
+	use sp_runtime::generic::Digest;
+	use substrate_archive_backend::{SecondaryRocksDB, TArchiveClient};
+
+	type KusamaSystem = System<
+		Block,
+		RuntimeApi,
+		TArchiveClient<Block, RuntimeApi, KusamaExecutor, SecondaryRocksDB>,
+		SecondaryRocksDB,
+	>;
+
+	async fn insert_block(conn: &mut sqlx::PgConnection, num: i64, hash: u8, is_canonical: bool) {
+		sqlx::query(
+			"INSERT INTO blocks (parent_hash, hash, block_num, state_root, extrinsics_root, digest, ext, spec, is_canonical)
+            VALUES ($1, $2, $3, $4, $4, $5, $6, 0, $7)",
+		)
+		.bind(vec![0u8; 32])
+		.bind(vec![hash; 32])
+		.bind(num)
+		.bind(vec![0u8; 32])
+		.bind(Digest::<primitive_types::H256>::default().encode())
+		.bind(Vec::<<Block as BlockT>::Extrinsic>::new().encode())
+		.bind(is_canonical)
+		.execute(&mut *conn)
+		.await
+		.unwrap();
+		sqlx::query("INSERT INTO storage (block_num, hash, is_full, key, storage) VALUES ($1, $2, false, $3, $3)")
+			.bind(num)
+			.bind(vec![hash; 32])
+			.bind(vec![hash])
+			.execute(&mut *conn)
+			.await
+			.unwrap();
+		sqlx::query("INSERT INTO digest_logs (hash, block_num, index, kind) VALUES ($1, $2, 0, 'Seal')")
+			.bind(vec![hash; 32])
+			.bind(num)
+			.execute(&mut *conn)
+			.await
+			.unwrap();
+		sqlx::query(
+			"INSERT INTO storage_root_mismatches (hash, block_num, expected, computed) VALUES ($1, $2, $1, $1)",
+		)
+		.bind(vec![hash; 32])
+		.bind(num)
+		.execute(&mut *conn)
+		.await
+		.unwrap();
+	}
+
+	async fn count(conn: &mut sqlx::PgConnection, query: &str) -> i64 {
+		sqlx::query_as::<_, (i64,)>(query).fetch_one(&mut *conn).await.unwrap().0
+	}
+
+	#[test]
+	fn should_requeue_blocks_in_range() {
+		crate::initialize();
+		let _guard = crate::TestGuard::lock();
+		smol::block_on(async {
+			let mut conn = sqlx::PgConnection::connect(&crate::DATABASE_URL).await.unwrap();
+			insert_block(&mut conn, 1, 1, true).await;
+			insert_block(&mut conn, 2, 2, true).await;
+			// a block on a fork is executed again as well
+			insert_block(&mut conn, 2, 22, false).await;
+			insert_block(&mut conn, 3, 3, true).await;
+			insert_block(&mut conn, 4, 4, true).await;
+
+			let queued = KusamaSystem::requeue_range(&crate::DATABASE_URL, 2..=3).await.unwrap();
+			assert_eq!(queued, 3);
+			let jobs = "SELECT COUNT(*) FROM _background_tasks WHERE job_type = 'execute_block'";
+			assert_eq!(count(&mut conn, jobs).await, 3);
+			for table in &["storage", "digest_logs", "storage_root_mismatches"] {
+				let inside = format!("SELECT COUNT(*) FROM {} WHERE block_num BETWEEN 2 AND 3", table);
+				assert_eq!(count(&mut conn, &inside).await, 0, "{} of the range is deleted", table);
+				let outside = format!("SELECT COUNT(*) FROM {} WHERE block_num IN (1, 4)", table);
+				assert_eq!(count(&mut conn, &outside).await, 2, "{} outside of the range is kept", table);
+			}
+		});
+	}
+
+	#[test]
+	fn should_finish_bounded_range() {
+		crate::initialize();
+		let _guard = crate::TestGuard::lock();
+		smol::block_on(async {
+			let pool = sqlx::PgPool::connect(&crate::DATABASE_URL).await.unwrap();
+			let mut conn = pool.acquire().await.unwrap();
+			// block 1 is missing
+			for num in 2..=4 {
+				insert_block(&mut conn, num, num as u8, true).await;
+			}
+			// an unbounded range never finishes
+			assert!(!KusamaSystem::range_finished(Some(2), None, &pool).await.unwrap());
+			// blocks in the outbox are not queued for execution yet
+			assert!(!KusamaSystem::range_finished(Some(2), Some(4), &pool).await.unwrap());
+
+			sqlx::query("DELETE FROM _block_outbox").execute(&mut conn).await.unwrap();
+			assert!(KusamaSystem::range_finished(Some(2), Some(4), &pool).await.unwrap());
+			// missing blocks are only counted inside of the range
+			assert!(KusamaSystem::range_finished(Some(3), Some(3), &pool).await.unwrap());
+			assert!(!KusamaSystem::range_finished(None, Some(4), &pool).await.unwrap());
+			assert!(!KusamaSystem::range_finished(Some(2), Some(5), &pool).await.unwrap());
+
+			sqlx::query("INSERT INTO _background_tasks (job_type, is_async, data) VALUES ('execute_block', false, '')")
+				.execute(&mut conn)
+				.await
+				.unwrap();
+			// blocks are left to execute
+			assert!(!KusamaSystem::range_finished(Some(2), Some(4), &pool).await.unwrap());
+		});
+	}
+
+	/// Pretends the state root after applying `changes` is all zeroes if the block changed key `[1]`,
+	/// and all `0xff` otherwise. Blocks that changed key `[3]` have no parent state.
+	fn fake_storage_root(_: H256, changes: &StorageCollection, _: &ChildStorageCollection) -> Option<H256> {
+		match changes[0].0.as_slice() {
+			[1] => Some(H256::zero()),
+			[3] => None,
+			_ => Some(H256::repeat_byte(0xff)),
+		}
+	}
+
+	#[test]
+	fn should_verify_storage_in_range() {
+		crate::initialize();
+		let _guard = crate::TestGuard::lock();
+		smol::block_on(async {
+			let mut conn = sqlx::PgConnection::connect(&crate::DATABASE_URL).await.unwrap();
+			// every block is inserted with a zero state root and a recorded mismatch
+			for num in 1..=5 {
+				insert_block(&mut conn, num, num as u8, true).await;
+			}
+			sqlx::query("DELETE FROM storage WHERE block_num = 4").execute(&mut conn).await.unwrap();
+
+			let report = KusamaSystem::verify_range(&mut conn, 1..=4, fake_storage_root).await.unwrap();
+			assert_eq!(report.verified, 1);
+			assert_eq!(report.mismatched, vec![(2, vec![2; 32])]);
+			// block 3 has no parent state, block 4 has no storage
+			assert_eq!(report.skipped, 2);
+
+			let mismatches = "SELECT COUNT(*) FROM storage_root_mismatches";
+			// the mismatch of block 1 is forgotten, block 5 is out of the range
+			assert_eq!(count(&mut conn, mismatches).await, 4);
+			let (computed,): (Vec<u8>,) =
+				sqlx::query_as("SELECT computed FROM storage_root_mismatches WHERE block_num = 2")
+					.fetch_one(&mut conn)
+					.await
+					.unwrap();
+			assert_eq!(computed, vec![0xff; 32]);
+		});
+	}
+
+	#[test]
+	fn should_refuse_to_verify_incomplete_storage() {
+		crate::initialize();
+		let _guard = crate::TestGuard::lock();
+		smol::block_on(async {
+			let mut conn = sqlx::PgConnection::connect(&crate::DATABASE_URL).await.unwrap();
+			insert_block(&mut conn, 1, 1, true).await;
+			queries::record_storage_mode(&mut conn, Default::default()).await.unwrap();
+			assert!(KusamaSystem::verify_range(&mut conn, 1..=1, fake_storage_root).await.is_ok());
+
+			let filtered = queries::StorageMode { filtered: true, external: false };
+			queries::record_storage_mode(&mut conn, filtered).await.unwrap();
+			// a later run without a filter does not make the storage of earlier runs complete
+			queries::record_storage_mode(&mut conn, Default::default()).await.unwrap();
+			assert_eq!(queries::storage_mode(&mut conn).await.unwrap(), filtered);
+			assert!(KusamaSystem::verify_range(&mut conn, 1..=1, fake_storage_root).await.is_err());
+
+			sqlx::query("TRUNCATE TABLE _storage_mode").execute(&mut conn).await.unwrap();
+			let external = queries::StorageMode { filtered: false, external: true };
+			queries::record_storage_mode(&mut conn, external).await.unwrap();
+			assert!(KusamaSystem::verify_range(&mut conn, 1..=1, fake_storage_root).await.is_err());
+		});
+	}
 }
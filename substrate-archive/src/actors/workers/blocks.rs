@@ -13,16 +13,20 @@
 // You should have received a copy of the GNU General Public License
 // along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::sync::Arc;
+use std::sync::{atomic::Ordering, Arc};
 
+use hashbrown::{HashMap, HashSet};
+use itertools::{Either, Itertools};
 use xtra::prelude::*;
 
+use sp_blockchain::HeaderBackend as _;
 use sp_runtime::{
 	generic::SignedBlock,
-	traits::{Block as BlockT, Header as _, NumberFor},
+	traits::{Block as BlockT, Header as _, SaturatedConversion},
 };
 use substrate_archive_backend::{ReadOnlyBackend, RuntimeVersionCache};
 use substrate_archive_common::{
+	metrics,
 	types::{BatchBlock, Block, Die},
 	ArchiveError, ReadOnlyDB, Result,
 };
@@ -34,18 +38,59 @@ use crate::{
 			database::{DatabaseActor, GetState},
 			metadata::MetadataActor,
 		},
-		ActorContext,
+		ActorContext, Progress,
 	},
 	database::queries,
 };
 
+/// How many blocks below the last max are checked for reorgs, if they are not finalized yet
+const MAX_REORG_DEPTH: u64 = 256;
+
+/// The highest block number that is not checked for reorgs: blocks up to the last finalized block
+/// and blocks more than `MAX_REORG_DEPTH` below the last max. Blocks below the start of the indexed range
+/// are never indexed, so they are not checked either.
+fn reorg_floor(last_max: u64, last_finalized: u64, start_block: u64) -> u64 {
+	let floor = std::cmp::max(last_finalized, last_max.saturating_sub(MAX_REORG_DEPTH));
+	std::cmp::max(floor, start_block.saturating_sub(1))
+}
+
+/// The blocks of the canonical chain that replaced an archived block with the same number.
+/// Block numbers that have not been archived are not replacements.
+fn replacements<H: AsRef<[u8]>>(canonical: Vec<(u64, H)>, archived: &HashMap<u64, Vec<u8>>) -> Vec<(u64, H)> {
+	canonical
+		.into_iter()
+		.filter(|(n, hash)| archived.get(n).map(|a| a.as_slice() != hash.as_ref()).unwrap_or(false))
+		.collect()
+}
+
+/// The lowest of the end block, the finalized block if only finalized blocks are indexed,
+/// and the block `confirmation_depth` blocks below the best block.
+/// `None` if none of them bounds the indexed blocks.
+fn newest_indexable(
+	end_block: Option<u64>,
+	finalized_only: bool,
+	confirmation_depth: Option<u64>,
+	finalized: u64,
+	best: u64,
+) -> Option<u64> {
+	let finalized = Some(finalized).filter(|_| finalized_only);
+	let confirmed = confirmation_depth.map(|depth| best.saturating_sub(depth));
+	[end_block, finalized, confirmed].iter().flatten().copied().min()
+}
+
+/// The highest block number to collect in one crawl: `max_block_load` blocks past the last max,
+/// but never past the newest indexable block.
+fn crawl_limit(last_max: u64, max_block_load: u32, newest_indexable: Option<u64>) -> u64 {
+	let max = last_max.saturating_add(u64::from(max_block_load));
+	newest_indexable.map(|newest| std::cmp::min(max, newest)).unwrap_or(max)
+}
+
 type DatabaseAct<B> = Address<ActorPool<DatabaseActor<B>>>;
 type MetadataAct<B> = Address<MetadataActor<B>>;
 
 pub struct BlocksIndexer<B: BlockT, D>
 where
 	D: ReadOnlyDB,
-	NumberFor<B>: Into<u32>,
 	B: Unpin,
 	B::Hash: Unpin,
 {
@@ -55,30 +100,61 @@ where
 	meta: MetadataAct<B>,
 	rt_cache: Arc<RuntimeVersionCache<B, D>>,
 	/// the last maximum block number from which we are sure every block before then is indexed
-	last_max: u32,
+	last_max: u64,
 	/// the maximum amount of blocks to index at once
 	max_block_load: u32,
+	/// the first block number to index
+	start_block: u64,
+	/// the last block number to index, if indexing a bounded range
+	end_block: Option<u64>,
+	/// whether to only index finalized blocks
+	finalized_only: bool,
+	/// how far below the best block to stay, if at all
+	confirmation_depth: Option<u64>,
+	/// whether to index blocks on forks that are not part of the canonical chain
+	index_forks: bool,
+	/// the last finalized block number seen while crawling. Blocks up to here can no longer be reorged.
+	last_finalized: u64,
+	/// the best block at the last check for reorgs
+	last_best: Option<B::Hash>,
+	/// the block number up to which justifications of finalized blocks have been re-read
+	justified_until: u64,
+	/// blocks above `last_finalized` that have already been sent to the database. Only used when indexing forks.
+	unfinalized: HashSet<(u64, B::Hash)>,
+	progress: Arc<Progress>,
 }
 
 impl<B: BlockT + Unpin, D: ReadOnlyDB + 'static> BlocksIndexer<B, D>
 where
 	B::Hash: Unpin,
-	NumberFor<B>: Into<u32>,
 {
 	pub fn new(ctx: ActorContext<B, D>, db: DatabaseAct<B>, meta: MetadataAct<B>) -> Self {
 		Self {
 			rt_cache: Arc::new(RuntimeVersionCache::new(ctx.backend.clone())),
-			last_max: 0,
+			last_max: ctx.config().start_block.map(|s| s.saturating_sub(1)).unwrap_or(0),
 			backend: ctx.backend().clone(),
 			db,
 			meta,
-			max_block_load: ctx.max_block_load,
+			max_block_load: ctx.config().max_block_load,
+			start_block: ctx.config().start_block.unwrap_or(0),
+			end_block: ctx.config().end_block,
+			finalized_only: ctx.config().finalized_only,
+			confirmation_depth: ctx.config().confirmation_depth,
+			index_forks: ctx.config().index_forks,
+			last_finalized: 0,
+			last_best: None,
+			justified_until: ctx.config().start_block.map(|s| s.saturating_sub(1)).unwrap_or(0),
+			unfinalized: HashSet::new(),
+			progress: ctx.progress().clone(),
 		}
 	}
 
 	/// A async wrapper around the backend fn `iter_blocks` which
 	/// runs in a `spawn_blocking` async task (its own thread)
-	async fn collect_blocks(&self, fun: impl Fn(u32) -> bool + Send + 'static) -> Result<Vec<Block<B>>> {
+	async fn collect_blocks(&self, fun: impl Fn(u64) -> bool + Send + 'static) -> Result<Vec<Block<B>>> {
+		if self.index_forks {
+			return self.collect_all_blocks(fun).await;
+		}
 		let backend = self.backend.clone();
 		let now = std::time::Instant::now();
 		let gather_blocks = move || -> Result<Vec<SignedBlock<B>>> {
@@ -86,16 +162,70 @@ where
 		};
 		let blocks = smol::unblock(gather_blocks).await?;
 		log::info!("Took {:?} to load {} blocks", now.elapsed(), blocks.len());
+		metrics::blocks_crawled(blocks.len());
 		let cache = self.rt_cache.clone();
 		let blocks = smol::unblock(move || cache.find_versions_as_blocks(blocks)).await?;
 		Ok(blocks)
 	}
 
+	/// A async wrapper around the backend fn `iter_all_blocks`.
+	/// Collects blocks on forks as well as canonical blocks.
+	async fn collect_all_blocks(&self, fun: impl Fn(u64) -> bool + Send + 'static) -> Result<Vec<Block<B>>> {
+		let backend = self.backend.clone();
+		let now = std::time::Instant::now();
+		let gather_blocks = move || -> Result<(Vec<SignedBlock<B>>, Vec<SignedBlock<B>>)> {
+			Ok(backend.iter_all_blocks(|n| fun(n))?.partition_map(|(b, is_canonical)| {
+				if is_canonical {
+					Either::Left(b)
+				} else {
+					Either::Right(b)
+				}
+			}))
+		};
+		let (canonical, forks) = smol::unblock(gather_blocks).await?;
+		log::info!(
+			"Took {:?} to load {} blocks ({} on forks)",
+			now.elapsed(),
+			canonical.len() + forks.len(),
+			forks.len()
+		);
+		metrics::blocks_crawled(canonical.len() + forks.len());
+		let cache = self.rt_cache.clone();
+		smol::unblock(move || {
+			let mut blocks = cache.find_versions_as_blocks(canonical)?;
+			// blocks on forks are not sorted by number, so the runtime version is looked up for each of them
+			for b in forks.into_iter() {
+				let version = cache.get(b.block.hash())?.ok_or_else(|| ArchiveError::from("Version not found"))?;
+				blocks.push(Block::new(b, version.spec_version).canonical(false));
+			}
+			Ok(blocks)
+		})
+		.await
+	}
+
 	/// Collect blocks according to the predicate `fun` and send those blocks to
 	///  the metadata actor.
-	async fn collect_and_send(&self, fun: impl Fn(u32) -> bool + Send + 'static) -> Result<()> {
-		self.meta.send(BatchBlock::new(self.collect_blocks(fun).await?)).await?;
-		Ok(())
+	async fn collect_and_send(&self, fun: impl Fn(u64) -> bool + Send + 'static) -> Result<()> {
+		self.meta.send(BatchBlock::new(self.collect_blocks(fun).await?)).await?
+	}
+
+	/// The highest block number that may be indexed, according to the end of the indexed range,
+	/// the last finalized block and the confirmation depth.
+	/// `None` if every block in the backend may be indexed.
+	fn newest_indexable(&self) -> Option<u64> {
+		let info = self.backend.info();
+		newest_indexable(
+			self.end_block,
+			self.finalized_only,
+			self.confirmation_depth,
+			info.finalized_number.saturated_into(),
+			info.best_number.saturated_into(),
+		)
+	}
+
+	/// The highest block number to collect in one crawl.
+	fn max_to_collect(&self) -> u64 {
+		crawl_limit(self.last_max, self.max_block_load, self.newest_indexable())
 	}
 
 	/// First run of indexing
@@ -112,41 +242,162 @@ where
 		};
 
 		let mut missing_blocks = 0;
-		let mut min = self.last_max;
+		let mut min = self.start_block;
+		let end = self.newest_indexable().unwrap_or(u64::MAX);
 		loop {
-			let batch = queries::missing_blocks_min_max(&mut conn, min, self.max_block_load).await?;
+			let mut batch = queries::missing_blocks_min_max(&mut conn, min, self.max_block_load).await?;
+			batch.retain(|n| *n <= end);
 			if !batch.is_empty() {
 				missing_blocks += batch.len();
-				min += self.max_block_load;
+				min += u64::from(self.max_block_load);
 				self.collect_and_send(move |n| batch.contains(&n)).await?;
 			} else {
 				break;
 			}
 		}
 
-		self.last_max = cur_max;
+		// blocks below the start of the indexed range are never collected
+		self.last_max = std::cmp::max(cur_max, self.last_max);
+		if self.index_forks {
+			let finalized: u64 = self.backend.info().finalized_number.saturated_into();
+			self.last_finalized = std::cmp::min(finalized, self.last_max);
+		}
 		log::info!("{} missing blocks", missing_blocks);
 
 		Ok(())
 	}
 
-	/// Crawl up to `max_block_load` blocks that are greater than the last max
+	/// Crawl up to `max_block_load` blocks that are greater than the last max,
+	/// and update the justifications of blocks finalized since the last crawl
 	async fn crawl(&mut self) -> Result<Vec<Block<B>>> {
+		let blocks = if self.index_forks { self.crawl_forks().await? } else { self.crawl_canonical().await? };
+		self.update_justifications().await?;
+		Ok(blocks)
+	}
+
+	/// Crawl up to `max_block_load` canonical blocks that are greater than the last max,
+	/// and the blocks that replaced reorged ones
+	async fn crawl_canonical(&mut self) -> Result<Vec<Block<B>>> {
+		let mut blocks = self.collect_reorged().await?;
 		let copied_last_max = self.last_max;
-		let max_to_collect = copied_last_max + self.max_block_load;
-		let blocks = self.collect_blocks(move |n| n > copied_last_max && n <= max_to_collect).await?;
+		let max_to_collect = self.max_to_collect();
+		if max_to_collect > copied_last_max {
+			blocks.extend(self.collect_blocks(move |n| n > copied_last_max && n <= max_to_collect).await?);
+		}
 		self.last_max = blocks
 			.iter()
-			.map(|b| (*b.inner.block.header().number()).into())
+			.map(|b| (*b.inner.block.header().number()).saturated_into::<u64>())
 			.fold(self.last_max, |ac, e| if e > ac { e } else { ac });
 		Ok(blocks)
 	}
+
+	/// Compare the hashes of indexed blocks that are not finalized yet to the canonical chain of the backend.
+	/// Blocks that were replaced by a reorg are marked as not canonical, and the blocks that replaced them
+	/// are collected to be indexed.
+	/// Only checks when the best block has changed since the last check.
+	async fn collect_reorged(&mut self) -> Result<Vec<Block<B>>> {
+		let info = self.backend.info();
+		if self.last_best == Some(info.best_hash) {
+			return Ok(Vec::new());
+		}
+		let floor = reorg_floor(self.last_max, self.last_finalized, self.start_block);
+		if self.last_max <= floor {
+			self.last_best = Some(info.best_hash);
+			self.last_finalized = std::cmp::min(info.finalized_number.saturated_into(), self.last_max);
+			return Ok(Vec::new());
+		}
+
+		let backend = self.backend.clone();
+		let range = floor + 1..=self.last_max;
+		let canonical = smol::unblock(move || backend.canonical_hashes(range)).await?;
+		let mut conn = self.db.send(GetState::Conn.into()).await?.await?.conn();
+		let archived: HashMap<u64, Vec<u8>> =
+			queries::canonical_hashes(&mut conn, floor + 1..=self.last_max).await?.into_iter().collect();
+		let replaced = replacements(canonical, &archived);
+
+		let blocks = if replaced.is_empty() {
+			Vec::new()
+		} else {
+			let changed = queries::canonicalize::<B>(replaced.as_slice(), &mut conn).await?;
+			log::warn!("Reorg replaced {} blocks, marked {} blocks as not canonical", replaced.len(), changed);
+			let nums: HashSet<u64> = replaced.iter().map(|(n, _)| *n).collect();
+			self.collect_blocks(move |n| nums.contains(&n)).await?
+		};
+		self.last_best = Some(info.best_hash);
+		self.last_finalized = std::cmp::min(info.finalized_number.saturated_into(), self.last_max);
+		Ok(blocks)
+	}
+
+	/// Re-read the justifications of blocks that were indexed before they were finalized,
+	/// and update them in the database. Reads up to `max_block_load` blocks at a time.
+	async fn update_justifications(&mut self) -> Result<()> {
+		let finalized = std::cmp::min(self.last_finalized, self.last_max);
+		if finalized <= self.justified_until {
+			return Ok(());
+		}
+		let end = std::cmp::min(finalized, self.justified_until + u64::from(self.max_block_load));
+		let backend = self.backend.clone();
+		let range = self.justified_until + 1..=end;
+		let justifications = smol::unblock(move || backend.justifications(range)).await?;
+		if !justifications.is_empty() {
+			let mut conn = self.db.send(GetState::Conn.into()).await?.await?.conn();
+			let updated = queries::update_justifications::<B>(justifications.as_slice(), &mut conn).await?;
+			if updated > 0 {
+				log::info!("Updated justifications of {} blocks", updated);
+			}
+		}
+		self.justified_until = end;
+		Ok(())
+	}
+
+	/// Crawl up to `max_block_load` blocks that are greater than the last max,
+	/// including blocks on forks.
+	/// Blocks that are not yet finalized are visited again on every crawl, so that
+	/// forks imported later are picked up and the `is_canonical` flag follows the chain.
+	async fn crawl_forks(&mut self) -> Result<Vec<Block<B>>> {
+		let finalized: u64 = self.backend.info().finalized_number.saturated_into();
+		let floor = std::cmp::min(self.last_max, self.last_finalized);
+		let floor = std::cmp::max(floor, self.start_block.saturating_sub(1));
+		let max_to_collect = self.max_to_collect();
+		let blocks = self.collect_all_blocks(move |n| n > floor && n <= max_to_collect).await?;
+		let new_max = blocks
+			.iter()
+			.map(|b| (*b.inner.block.header().number()).saturated_into::<u64>())
+			.fold(self.last_max, std::cmp::max);
+
+		// update blocks which are already in the database and may have switched chains
+		if new_max > floor {
+			let backend = self.backend.clone();
+			let canonical = smol::unblock(move || backend.canonical_hashes(floor + 1..=new_max)).await?;
+			let mut conn = self.db.send(GetState::Conn.into()).await?.await?.conn();
+			let changed = queries::canonicalize::<B>(canonical.as_slice(), &mut conn).await?;
+			if changed > 0 {
+				log::info!("Updated canonical status of {} blocks", changed);
+			}
+		}
+
+		let unfinalized = &mut self.unfinalized;
+		let blocks = blocks
+			.into_iter()
+			.filter(|b| {
+				let key = ((*b.inner.block.header().number()).saturated_into::<u64>(), b.inner.block.hash());
+				if key.0 <= finalized {
+					!unfinalized.contains(&key)
+				} else {
+					unfinalized.insert(key)
+				}
+			})
+			.collect();
+		self.unfinalized.retain(|(n, _)| *n > finalized);
+		self.last_max = new_max;
+		self.last_finalized = finalized;
+		Ok(blocks)
+	}
 }
 
 #[async_trait::async_trait]
 impl<B: BlockT, D: ReadOnlyDB + 'static> Actor for BlocksIndexer<B, D>
 where
-	NumberFor<B>: Into<u32>,
 	B: Unpin,
 	B::Hash: Unpin,
 {
@@ -177,18 +428,19 @@ impl Message for Crawl {
 #[async_trait::async_trait]
 impl<B: BlockT + Unpin, D: ReadOnlyDB + 'static> Handler<Crawl> for BlocksIndexer<B, D>
 where
-	NumberFor<B>: Into<u32>,
 	B::Hash: Unpin,
 {
 	async fn handle(&mut self, _: Crawl, ctx: &mut Context<Self>) {
 		match self.crawl().await {
 			Err(e) => log::error!("{}", e.to_string()),
-			Ok(b) => {
-				if !b.is_empty() && self.meta.send(BatchBlock::new(b)).await.is_err() {
-					ctx.stop();
-				}
-			}
+			Ok(b) if !b.is_empty() => match self.meta.send(BatchBlock::new(b)).await {
+				Ok(Err(e)) => log::error!("{}", e.to_string()),
+				Err(_) => ctx.stop(),
+				Ok(Ok(())) => {}
+			},
+			Ok(_) => {}
 		}
+		self.progress.last_max.store(self.last_max, Ordering::Relaxed);
 	}
 }
 
@@ -200,7 +452,6 @@ impl Message for ReIndex {
 #[async_trait::async_trait]
 impl<B: BlockT + Unpin, D: ReadOnlyDB + 'static> Handler<ReIndex> for BlocksIndexer<B, D>
 where
-	NumberFor<B>: Into<u32>,
 	B::Hash: Unpin,
 {
 	async fn handle(&mut self, _: ReIndex, ctx: &mut Context<Self>) {
@@ -210,13 +461,13 @@ where
 			Ok(()) => {}
 			Err(e) => log::error!("{}", e.to_string()),
 		}
+		self.progress.last_max.store(self.last_max, Ordering::Relaxed);
 	}
 }
 
 #[async_trait::async_trait]
 impl<B: BlockT + Unpin, D: ReadOnlyDB + 'static> Handler<Die> for BlocksIndexer<B, D>
 where
-	NumberFor<B>: Into<u32>,
 	B::Hash: Unpin,
 {
 	async fn handle(&mut self, _: Die, ctx: &mut Context<Self>) -> Result<()> {
@@ -224,3 +475,60 @@ where
 		Ok(())
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn should_check_reorgs_up_to_max_depth() {
+		// the last `MAX_REORG_DEPTH` blocks are checked
+		assert_eq!(reorg_floor(1000, 0, 0), 1000 - MAX_REORG_DEPTH);
+		assert_eq!(reorg_floor(MAX_REORG_DEPTH, 0, 0), 0);
+		assert_eq!(reorg_floor(MAX_REORG_DEPTH + 1, 0, 0), 1);
+		assert_eq!(reorg_floor(10, 0, 0), 0);
+		// finalized blocks cannot be reorged
+		assert_eq!(reorg_floor(1000, 900, 0), 900);
+		assert_eq!(reorg_floor(1000, 1000, 0), 1000);
+		// neither can blocks below the indexed range
+		assert_eq!(reorg_floor(1000, 0, 800), 799);
+		assert_eq!(reorg_floor(1000, 0, 500), 1000 - MAX_REORG_DEPTH);
+	}
+
+	#[test]
+	fn should_bound_indexable_blocks() {
+		assert_eq!(newest_indexable(None, false, None, 90, 100), None);
+		assert_eq!(newest_indexable(Some(50), false, None, 90, 100), Some(50));
+		assert_eq!(newest_indexable(Some(500), false, None, 90, 100), Some(500));
+		assert_eq!(newest_indexable(None, true, None, 90, 100), Some(90));
+		assert_eq!(newest_indexable(None, false, Some(5), 90, 100), Some(95));
+		// the lowest bound wins
+		assert_eq!(newest_indexable(Some(80), true, Some(5), 90, 100), Some(80));
+		assert_eq!(newest_indexable(None, true, Some(5), 90, 100), Some(90));
+		assert_eq!(newest_indexable(None, true, Some(20), 90, 100), Some(80));
+		// a confirmation depth deeper than the chain saturates at genesis
+		assert_eq!(newest_indexable(None, false, Some(200), 90, 100), Some(0));
+		assert_eq!(newest_indexable(None, false, Some(u64::MAX), 0, 0), Some(0));
+	}
+
+	#[test]
+	fn should_clamp_blocks_to_collect() {
+		assert_eq!(crawl_limit(100, 50, None), 150);
+		// never past the newest indexable block
+		assert_eq!(crawl_limit(100, 50, Some(120)), 120);
+		assert_eq!(crawl_limit(100, 50, Some(150)), 150);
+		assert_eq!(crawl_limit(100, 50, Some(1000)), 150);
+		// the newest indexable block may be behind the last max, e.g. after lowering the end block
+		assert_eq!(crawl_limit(100, 50, Some(90)), 90);
+		assert_eq!(crawl_limit(u64::MAX - 10, 50, None), u64::MAX);
+	}
+
+	#[test]
+	fn should_find_replaced_blocks() {
+		let archived: HashMap<u64, Vec<u8>> = vec![(1, vec![1]), (2, vec![2]), (3, vec![3])].into_iter().collect();
+		let canonical = vec![(1, vec![1]), (2, vec![22]), (3, vec![33]), (4, vec![4])];
+		// block 4 has not been archived yet, so it is collected by the next crawl instead
+		assert_eq!(replacements(canonical, &archived), vec![(2, vec![22]), (3, vec![33])]);
+		assert!(replacements(vec![(1, vec![1])], &archived).is_empty());
+	}
+}
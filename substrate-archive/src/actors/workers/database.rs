@@ -15,46 +15,52 @@
 // along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
 
 use std::marker::PhantomData;
+use std::sync::Arc;
 use std::time::Duration;
 
 use xtra::prelude::*;
 
-use sp_runtime::traits::{Block as BlockT, NumberFor};
+use sp_runtime::traits::Block as BlockT;
 
 use substrate_archive_common::{
-	models::StorageModel,
-	types::{BatchBlock, BatchStorage, Block, Die, Metadata, Storage},
+	metrics,
+	models::{ChildStorageModel, StorageModel},
+	types::{BatchBlock, BatchDecoded, BatchStorage, Block, Die, Metadata, Storage},
 	Result,
 };
 
-use crate::database::{queries, Database, DbConn};
+use crate::{
+	actors::Progress,
+	database::{queries, Database, DbConn},
+};
 
 #[derive(Clone)]
 pub struct DatabaseActor<B: BlockT> {
 	db: Database,
+	/// insert blocks and storage with `COPY`
+	copy_insert: bool,
+	progress: Arc<Progress>,
 	_marker: PhantomData<B>,
 }
 
 impl<B: BlockT> DatabaseActor<B> {
-	pub async fn new(url: String) -> Result<Self> {
-		Ok(Self { db: Database::new(url).await?, _marker: PhantomData })
+	pub async fn new(url: String, copy_insert: bool, progress: Arc<Progress>) -> Result<Self> {
+		Ok(Self { db: Database::new(url, copy_insert).await?, copy_insert, progress, _marker: PhantomData })
 	}
 
 	#[allow(unused)]
 	pub fn with_db(db: Database) -> Self {
-		Self { db, _marker: PhantomData }
+		Self { db, copy_insert: false, progress: Arc::new(Progress::default()), _marker: PhantomData }
 	}
 
-	async fn block_handler(&self, blk: Block<B>) -> Result<()>
-	where
-		NumberFor<B>: Into<u32>,
-	{
+	async fn block_handler(&self, blk: Block<B>) -> Result<()> {
 		let mut conn = self.db.conn().await?;
 		while !queries::check_if_meta_exists(blk.spec, &mut conn).await? {
 			smol::Timer::after(Duration::from_millis(20)).await;
 		}
 		std::mem::drop(conn);
 		self.db.insert(blk).await?;
+		metrics::blocks_inserted(1);
 		Ok(())
 	}
 
@@ -66,17 +72,20 @@ impl<B: BlockT> DatabaseActor<B> {
 		Ok(specs.is_subset(&versions))
 	}
 
-	async fn batch_block_handler(&self, blks: BatchBlock<B>) -> Result<()>
-	where
-		NumberFor<B>: Into<u32>,
-	{
+	async fn batch_block_handler(&self, blks: BatchBlock<B>) -> Result<()> {
 		let mut conn = self.db.conn().await?;
 		while !Self::db_contains_metadata(blks.inner(), &mut conn).await? {
 			log::info!("Doesn't contain metadata");
 			smol::Timer::after(Duration::from_millis(50)).await;
 		}
 		std::mem::drop(conn);
-		self.db.insert(blks).await?;
+		let len = blks.inner().len();
+		if self.copy_insert {
+			self.db.copy(blks).await?;
+		} else {
+			self.db.insert(blks).await?;
+		}
+		metrics::blocks_inserted(len);
 		Ok(())
 	}
 
@@ -85,9 +94,15 @@ impl<B: BlockT> DatabaseActor<B> {
 		while !queries::has_block::<B>(*storage.hash(), &mut conn).await? {
 			smol::Timer::after(Duration::from_millis(10)).await;
 		}
+		let child_storage = Vec::<ChildStorageModel<B>>::from(&storage);
 		let storage = Vec::<StorageModel<B>>::from(storage);
 		std::mem::drop(conn);
+		let rows = storage.len();
 		self.db.insert(storage).await?;
+		metrics::storage_inserted(rows);
+		if !child_storage.is_empty() {
+			self.db.insert(child_storage).await?;
+		}
 		Ok(())
 	}
 
@@ -102,8 +117,26 @@ impl<B: BlockT> DatabaseActor<B> {
 		}
 		// we drop the connection early so that the insert() has the use of all db connections
 		std::mem::drop(conn);
+		let child_storage = Vec::<ChildStorageModel<B>>::from(&storages);
 		let storage = Vec::<StorageModel<B>>::from(storages);
-		self.db.insert(storage).await?;
+		let rows = storage.len();
+		if self.copy_insert {
+			self.db.copy(storage).await?;
+		} else {
+			self.db.insert(storage).await?;
+		}
+		metrics::storage_inserted(rows);
+		if !child_storage.is_empty() {
+			self.db.insert(child_storage).await?;
+		}
+		Ok(())
+	}
+
+	async fn batch_decoded_handler(&self, decoded: BatchDecoded<B>) -> Result<()> {
+		// events reference the extrinsics that emitted them, so extrinsics are inserted first
+		self.db.insert(decoded.extrinsics).await?;
+		self.db.insert(decoded.events).await?;
+		self.db.insert(decoded.digest_logs).await?;
 		Ok(())
 	}
 }
@@ -114,7 +147,6 @@ impl<B: BlockT> Actor for DatabaseActor<B> {}
 impl<B> Handler<Block<B>> for DatabaseActor<B>
 where
 	B: BlockT,
-	NumberFor<B>: Into<u32>,
 {
 	async fn handle(&mut self, blk: Block<B>, _: &mut Context<Self>) {
 		if let Err(e) = self.block_handler(blk).await {
@@ -127,48 +159,86 @@ where
 impl<B> Handler<BatchBlock<B>> for DatabaseActor<B>
 where
 	B: BlockT,
-	NumberFor<B>: Into<u32>,
 {
-	async fn handle(&mut self, blks: BatchBlock<B>, _: &mut Context<Self>) {
+	async fn handle(&mut self, blks: BatchBlock<B>, _: &mut Context<Self>) -> Result<()> {
 		let len = blks.inner.len();
 		let now = std::time::Instant::now();
-		if let Err(e) = self.batch_block_handler(blks).await {
-			log::error!("{}", e.to_string());
-		}
+		self.batch_block_handler(blks).await?;
 		if len > 1000 {
 			log::info!("took {:?} to insert {} blocks", now.elapsed(), len);
 		} else {
 			log::debug!("took {:?} to insert {} blocks", now.elapsed(), len);
 		}
+		Ok(())
 	}
 }
 
 #[async_trait::async_trait]
 impl<B: BlockT> Handler<Metadata> for DatabaseActor<B> {
-	async fn handle(&mut self, meta: Metadata, _ctx: &mut Context<Self>) {
-		if let Err(e) = self.db.insert(meta).await {
-			log::error!("{}", e.to_string());
-		}
+	async fn handle(&mut self, meta: Metadata, _ctx: &mut Context<Self>) -> Result<()> {
+		self.db.insert(meta).await?;
+		Ok(())
 	}
 }
 
 #[async_trait::async_trait]
 impl<B: BlockT> Handler<Storage<B>> for DatabaseActor<B> {
 	async fn handle(&mut self, storage: Storage<B>, _ctx: &mut Context<Self>) {
-		if let Err(e) = self.storage_handler(storage).await {
-			log::error!("{}", e.to_string())
+		match self.storage_handler(storage).await {
+			Ok(()) => {
+				self.progress.stored(1);
+			}
+			Err(e) => log::error!("{}", e.to_string()),
 		}
 	}
 }
 
 #[async_trait::async_trait]
 impl<B: BlockT> Handler<BatchStorage<B>> for DatabaseActor<B> {
-	async fn handle(&mut self, storages: BatchStorage<B>, _ctx: &mut Context<Self>) {
+	async fn handle(&mut self, storages: BatchStorage<B>, _ctx: &mut Context<Self>) -> Result<()> {
 		let now = std::time::Instant::now();
-		if let Err(e) = self.batch_storage_handler(storages).await {
-			log::error!("{}", e.to_string());
-		}
+		let len = storages.inner().len() as u64;
+		self.batch_storage_handler(storages).await?;
+		self.progress.stored(len);
 		log::debug!("took {:?} to insert storage", now.elapsed());
+		Ok(())
+	}
+}
+
+#[async_trait::async_trait]
+impl<B: BlockT> Handler<BatchDecoded<B>> for DatabaseActor<B> {
+	async fn handle(&mut self, decoded: BatchDecoded<B>, _ctx: &mut Context<Self>) -> Result<()> {
+		let now = std::time::Instant::now();
+		let len = decoded.extrinsics.len();
+		self.batch_decoded_handler(decoded).await?;
+		log::debug!("took {:?} to insert {} extrinsics", now.elapsed(), len);
+		Ok(())
+	}
+}
+
+/// Record blocks as executed without inserting their storage into Postgres,
+/// because the storage has been written to other sinks.
+#[derive(Debug)]
+pub struct Executed<B: BlockT> {
+	pub blocks: Vec<(u64, B::Hash)>,
+}
+
+impl<B: BlockT> Message for Executed<B> {
+	type Result = Result<()>;
+}
+
+#[async_trait::async_trait]
+impl<B: BlockT> Handler<Executed<B>> for DatabaseActor<B> {
+	async fn handle(&mut self, executed: Executed<B>, _ctx: &mut Context<Self>) -> Result<()> {
+		let mut conn = self.db.conn().await?;
+		let nums = executed.blocks.iter().map(|(num, _)| *num).collect::<Vec<_>>();
+		let len = nums.iter().collect::<hashbrown::HashSet<_>>().len();
+		while queries::has_blocks::<B>(nums.as_slice(), &mut conn).await?.len() != len {
+			smol::Timer::after(Duration::from_millis(50)).await;
+		}
+		queries::insert_executed::<B>(&mut conn, executed.blocks.as_slice()).await?;
+		self.progress.stored(executed.blocks.len() as u64);
+		Ok(())
 	}
 }
 
@@ -239,7 +309,6 @@ impl<B: BlockT> Handler<GetState> for DatabaseActor<B> {
 #[async_trait::async_trait]
 impl<B: BlockT + Unpin> Handler<Die> for DatabaseActor<B>
 where
-	NumberFor<B>: Into<u32>,
 	B::Hash: Unpin,
 {
 	async fn handle(&mut self, _: Die, ctx: &mut Context<Self>) -> Result<()> {
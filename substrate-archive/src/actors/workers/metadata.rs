@@ -18,7 +18,7 @@ use xtra::prelude::*;
 
 use sp_runtime::{
 	generic::BlockId,
-	traits::{Block as BlockT, Header as _, NumberFor},
+	traits::{Block as BlockT, Header as _},
 };
 use substrate_archive_backend::Meta;
 use substrate_archive_common::{
@@ -32,20 +32,21 @@ use crate::{
 		workers::database::{DatabaseActor, GetState},
 	},
 	database::{queries, DbConn},
+	sink::Sinks,
 };
 
 /// Actor to fetch metadata about a block/blocks from RPC
 /// Accepts workers to decode blocks and a URL for the RPC
 pub struct MetadataActor<B: BlockT> {
 	conn: DbConn,
-	addr: Address<ActorPool<DatabaseActor<B>>>,
+	sinks: Sinks<B>,
 	meta: Meta<B>,
 }
 
 impl<B: BlockT + Unpin> MetadataActor<B> {
-	pub async fn new(addr: Address<ActorPool<DatabaseActor<B>>>, meta: Meta<B>) -> Result<Self> {
+	pub async fn new(addr: Address<ActorPool<DatabaseActor<B>>>, sinks: Sinks<B>, meta: Meta<B>) -> Result<Self> {
 		let conn = addr.send(GetState::Conn.into()).await?.await?.conn();
-		Ok(Self { conn, addr, meta })
+		Ok(Self { conn, sinks, meta })
 	}
 
 	// checks if the metadata exists in the database
@@ -54,33 +55,31 @@ impl<B: BlockT + Unpin> MetadataActor<B> {
 		if !queries::check_if_meta_exists(ver, &mut self.conn).await? {
 			let meta = self.meta.clone();
 			log::info!("Getting metadata for hash {}, version {}", hex::encode(hash.as_ref()), ver);
-			let meta = smol::unblock(move || meta.metadata(&BlockId::hash(hash))).await?;
+			let (meta, version) = smol::unblock(move || -> Result<_> {
+				let id = BlockId::hash(hash);
+				Ok((meta.metadata(&id)?, meta.runtime_version(&id)?))
+			})
+			.await?;
 			let meta: sp_core::Bytes = meta.into();
-			let meta = Metadata::new(ver, meta.0);
-			self.addr.send(meta.into()).await?.await;
+			let meta = Metadata::new(ver, meta.0).with_runtime_version(serde_json::to_value(&version)?);
+			self.sinks.write_metadata(meta).await?;
 		}
 		Ok(())
 	}
 
-	async fn block_handler(&mut self, blk: Block<B>) -> Result<()>
-	where
-		NumberFor<B>: Into<u32>,
-	{
+	async fn block_handler(&mut self, blk: Block<B>) -> Result<()> {
 		let hash = blk.inner.block.header().hash();
 		self.meta_checker(blk.spec, hash).await?;
-		self.addr.send(blk.into()).await?.await;
+		self.sinks.write_blocks(BatchBlock::new(vec![blk])).await?;
 		Ok(())
 	}
 
-	async fn batch_block_handler(&mut self, blks: BatchBlock<B>) -> Result<()>
-	where
-		NumberFor<B>: Into<u32>,
-	{
+	async fn batch_block_handler(&mut self, blks: BatchBlock<B>) -> Result<()> {
 		let versions = blks.inner().iter().unique_by(|b| b.spec).collect::<Vec<&Block<B>>>();
 		for b in versions.iter() {
 			self.meta_checker(b.spec, b.inner.block.hash()).await?;
 		}
-		self.addr.send(blks.into()).await?.await;
+		self.sinks.write_blocks(blks).await?;
 		Ok(())
 	}
 }
@@ -91,7 +90,6 @@ impl<B: BlockT> Actor for MetadataActor<B> {}
 impl<B> Handler<Block<B>> for MetadataActor<B>
 where
 	B: BlockT + Unpin,
-	NumberFor<B>: Into<u32>,
 {
 	async fn handle(&mut self, blk: Block<B>, _: &mut Context<Self>) {
 		if let Err(e) = self.block_handler(blk).await {
@@ -104,19 +102,15 @@ where
 impl<B> Handler<BatchBlock<B>> for MetadataActor<B>
 where
 	B: BlockT + Unpin,
-	NumberFor<B>: Into<u32>,
 {
-	async fn handle(&mut self, blks: BatchBlock<B>, _: &mut Context<Self>) {
-		if let Err(e) = self.batch_block_handler(blks).await {
-			log::error!("{}", e.to_string());
-		}
+	async fn handle(&mut self, blks: BatchBlock<B>, _: &mut Context<Self>) -> Result<()> {
+		self.batch_block_handler(blks).await
 	}
 }
 
 #[async_trait::async_trait]
 impl<B: BlockT + Unpin> Handler<Die> for MetadataActor<B>
 where
-	NumberFor<B>: Into<u32>,
 	B::Hash: Unpin,
 {
 	async fn handle(&mut self, _: Die, ctx: &mut Context<Self>) -> Result<()> {
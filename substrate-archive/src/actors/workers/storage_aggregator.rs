@@ -14,40 +14,54 @@
 // You should have received a copy of the GNU General Public License
 // along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
 
-//! Module that accepts individual storage entries and wraps them up into batch requests for
-//! Postgres
+//! Module that accepts individual storage entries and wraps them up into batches for
+//! the sinks. Decoded extrinsics and events are batched the same way.
 
 use xtra::prelude::*;
 
 use sp_runtime::traits::Block as BlockT;
 
 use substrate_archive_common::{
-	types::{BatchStorage, Die, Storage},
+	types::{BatchDecoded, BatchStorage, Die, Storage},
 	Result,
 };
 
-use crate::actors::{actor_pool::ActorPool, workers::database::DatabaseActor};
+use crate::sink::Sinks;
 
 pub struct StorageAggregator<B: BlockT + Unpin> {
-	db: Address<ActorPool<DatabaseActor<B>>>,
+	sinks: Sinks<B>,
 	storage: Vec<Storage<B>>,
+	decoded: BatchDecoded<B>,
 }
 
 impl<B: BlockT + Unpin> StorageAggregator<B>
 where
 	B::Hash: Unpin,
 {
-	pub fn new(db: Address<ActorPool<DatabaseActor<B>>>) -> Self {
-		Self { db, storage: Vec::with_capacity(500) }
+	pub fn new(sinks: Sinks<B>) -> Self {
+		Self { sinks, storage: Vec::with_capacity(500), decoded: BatchDecoded::new(Vec::new(), Vec::new(), Vec::new()) }
 	}
 
 	async fn handle_storage(&mut self, ctx: &mut Context<Self>) -> Result<()> {
 		let storage = std::mem::take(&mut self.storage);
 		if !storage.is_empty() {
 			log::info!("Indexing {} blocks of storage entries", storage.len());
-			let send_result = self.db.send(BatchStorage::new(storage).into()).await?;
-			// handle_while the actual insert is happening, not the send
-			ctx.handle_while(self, send_result).await;
+			let sinks = self.sinks.clone();
+			let write = async move { sinks.write_storage(BatchStorage::new(storage)).await };
+			// keep accepting storage while the batch is written
+			ctx.handle_while(self, write).await?;
+		}
+		let decoded = std::mem::replace(&mut self.decoded, BatchDecoded::new(Vec::new(), Vec::new(), Vec::new()));
+		if !decoded.is_empty() {
+			log::debug!(
+				"Indexing {} extrinsics, {} events and {} digest logs",
+				decoded.extrinsics.len(),
+				decoded.events.len(),
+				decoded.digest_logs.len()
+			);
+			let sinks = self.sinks.clone();
+			let write = async move { sinks.write_decoded(decoded).await };
+			ctx.handle_while(self, write).await?;
 		}
 		Ok(())
 	}
@@ -70,24 +84,6 @@ where
 		})
 		.detach();
 	}
-
-	async fn stopped(&mut self) {
-		let len = self.storage.len();
-		let storage = std::mem::take(&mut self.storage);
-		// insert any storage left in queue
-		let task = self.db.send(BatchStorage::new(storage).into()).await;
-
-		match task {
-			Err(e) => {
-				log::info!("{} storage entries will be missing, {:?}", len, e);
-			}
-			Ok(v) => {
-				log::info!("waiting for last storage insert...");
-				v.await;
-				log::info!("storage inserted");
-			}
-		}
-	}
 }
 
 struct SendStorage;
@@ -117,13 +113,40 @@ where
 	}
 }
 
+#[async_trait::async_trait]
+impl<B: BlockT + Unpin> Handler<BatchDecoded<B>> for StorageAggregator<B>
+where
+	B::Hash: Unpin,
+{
+	async fn handle(&mut self, d: BatchDecoded<B>, _: &mut Context<Self>) -> Result<()> {
+		self.decoded.extend(d);
+		Ok(())
+	}
+}
+
 #[async_trait::async_trait]
 impl<B: BlockT + Unpin> Handler<Die> for StorageAggregator<B>
 where
 	B::Hash: Unpin,
 {
+	/// Inserts any storage left in the queue and flushes the sinks before stopping.
+	/// Resolves once the inserts have finished.
 	async fn handle(&mut self, _: Die, ctx: &mut Context<Self>) -> Result<()> {
+		log::info!("waiting for last storage insert...");
+		// storage may still arrive while the previous batch is being inserted
+		let result = loop {
+			if self.storage.is_empty() && self.decoded.is_empty() {
+				break Ok(());
+			}
+			if let Err(e) = self.handle_storage(ctx).await {
+				break Err(e);
+			}
+		};
+		let result = result.and(self.sinks.flush().await);
 		ctx.stop();
-		Ok(())
+		if result.is_ok() {
+			log::info!("storage inserted");
+		}
+		result
 	}
 }
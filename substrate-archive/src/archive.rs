@@ -14,7 +14,7 @@
 // You should have received a copy of the GNU General Public License
 // along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::{marker::PhantomData, path::PathBuf, sync::Arc};
+use std::{marker::PhantomData, path::PathBuf, sync::Arc, time::Duration};
 
 use serde::de::DeserializeOwned;
 
@@ -32,7 +32,13 @@ use sp_runtime::{
 use substrate_archive_backend::{runtime_api, ReadOnlyBackend, TArchiveClient};
 use substrate_archive_common::{util, ReadOnlyDB, Result};
 
-use crate::{actors::System, traits};
+use crate::{
+	actors::{System, SystemConfig},
+	filter::StorageFilter,
+	sink::ArchiveSink,
+	tasks::Snapshots,
+	traits,
+};
 
 const CHAIN_DATA_VAR: &str = "CHAIN_DATA_DB";
 const POSTGRES_VAR: &str = "DATABASE_URL";
@@ -40,7 +46,10 @@ const POSTGRES_VAR: &str = "DATABASE_URL";
 /// The recommended open file descriptor limit to be configured for the process.
 const RECOMMENDED_OPEN_FILE_DESCRIPTOR_LIMIT: u64 = 10_000;
 
-pub struct Builder<B, R, D, DB> {
+/// How long to wait for running tasks on shutdown, if not configured.
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct Builder<B: BlockT, R, D, DB> {
 	/// Path to the rocksdb database
 	pub chain_data_path: Option<String>,
 	/// url to the Postgres Database
@@ -56,9 +65,35 @@ pub struct Builder<B, R, D, DB> {
 	pub _marker: PhantomData<(B, R, D, DB)>,
 	/// maximum amount of blocks to index at once
 	pub max_block_load: Option<u32>,
+	/// index blocks on forks as well as the canonical chain
+	pub index_forks: Option<bool>,
+	/// first block number to index
+	pub start_block: Option<u64>,
+	/// last block number to index
+	pub end_block: Option<u64>,
+	/// only index finalized blocks
+	pub finalized_only: Option<bool>,
+	/// only index blocks this many blocks below the best block
+	pub confirmation_depth: Option<u64>,
+	/// decode extrinsics, events and header digests into the `extrinsics`, `events` and `digest_logs` tables
+	pub decode: Option<bool>,
+	/// insert blocks and storage with binary `COPY`
+	pub copy_insert: Option<bool>,
+	/// how long to wait for running tasks and buffered storage on shutdown
+	pub shutdown_timeout: Option<Duration>,
+	/// storage keys to index
+	pub storage_filter: Option<StorageFilter>,
+	/// store the full state every `snapshot_interval` blocks
+	pub snapshot_interval: Option<u64>,
+	/// store the full state at every runtime upgrade
+	pub snapshot_on_upgrade: Option<bool>,
+	/// write storage and decoded data to Postgres
+	pub postgres_sink: Option<bool>,
+	/// sinks to write to in addition to, or instead of, Postgres
+	pub sinks: Vec<Arc<dyn ArchiveSink<B>>>,
 }
 
-impl<B, R, D, DB> Default for Builder<B, R, D, DB> {
+impl<B: BlockT, R, D, DB> Default for Builder<B, R, D, DB> {
 	fn default() -> Self {
 		Self {
 			chain_data_path: None,
@@ -69,11 +104,24 @@ impl<B, R, D, DB> Default for Builder<B, R, D, DB> {
 			chain_spec: None,
 			_marker: PhantomData,
 			max_block_load: None,
+			index_forks: None,
+			start_block: None,
+			end_block: None,
+			finalized_only: None,
+			confirmation_depth: None,
+			decode: None,
+			copy_insert: None,
+			shutdown_timeout: None,
+			storage_filter: None,
+			snapshot_interval: None,
+			snapshot_on_upgrade: None,
+			postgres_sink: None,
+			sinks: Vec::new(),
 		}
 	}
 }
 
-impl<B, R, D, DB> Builder<B, R, D, DB> {
+impl<B: BlockT, R, D, DB> Builder<B, R, D, DB> {
 	/// Set the chain data backend path to use for this instance.
 	///
 	/// # Default
@@ -137,6 +185,135 @@ impl<B, R, D, DB> Builder<B, R, D, DB> {
 		self.max_block_load = Some(max_block_load);
 		self
 	}
+
+	/// Index every imported block, including blocks on forks which are not part of the canonical chain.
+	/// Blocks are marked canonical or non-canonical in the `blocks` table,
+	/// and this is kept up-to-date as the chain is finalized.
+	///
+	/// # Default
+	/// Defaults to false, only the canonical chain is indexed
+	pub fn index_forks(mut self, index_forks: bool) -> Self {
+		self.index_forks = Some(index_forks);
+		self
+	}
+
+	/// Only index blocks from this block number onwards.
+	///
+	/// # Default
+	/// Defaults to indexing from genesis
+	pub fn start_block(mut self, start_block: u64) -> Self {
+		self.start_block = Some(start_block);
+		self
+	}
+
+	/// Only index blocks up to and including this block number.
+	/// The archive stops by itself once every block up to here has been indexed and executed,
+	/// and its storage has been inserted.
+	///
+	/// # Default
+	/// Defaults to indexing the chain without an end, following new blocks as they are imported
+	pub fn end_block(mut self, end_block: u64) -> Self {
+		self.end_block = Some(end_block);
+		self
+	}
+
+	/// Only index blocks that are finalized, so that no indexed block is ever replaced by a reorg.
+	///
+	/// # Default
+	/// Defaults to false, blocks are indexed as soon as they are imported
+	pub fn finalized_only(mut self, finalized_only: bool) -> Self {
+		self.finalized_only = Some(finalized_only);
+		self
+	}
+
+	/// Only index blocks that are at least `depth` blocks below the best block.
+	/// Useful for chains without finality, or to index closer to the head than `finalized_only` allows.
+	/// If combined with `finalized_only`, the lower of the two limits applies.
+	///
+	/// # Default
+	/// Defaults to indexing up to the best block
+	pub fn confirmation_depth(mut self, depth: u64) -> Self {
+		self.confirmation_depth = Some(depth);
+		self
+	}
+
+	/// Decode the extrinsics and events of every executed block with the metadata of its runtime version,
+	/// and store them in the `extrinsics` and `events` tables.
+	/// The items of the header digest are decoded into the `digest_logs` table, along with the slot and the author
+	/// of the block from its BABE or Aura pre-runtime digest. The author is the account in `Session::Validators`
+	/// at the block, and its authority id in `Babe::Authorities` or `Aura::Authorities` is stored as well.
+	///
+	/// # Default
+	/// Defaults to false
+	pub fn decode(mut self, decode: bool) -> Self {
+		self.decode = Some(decode);
+		self
+	}
+
+	/// Insert blocks and storage by streaming them to Postgres with binary `COPY`,
+	/// rather than with `INSERT` statements. This is considerably faster during the initial sync.
+	///
+	/// # Default
+	/// Defaults to false
+	pub fn copy_insert(mut self, copy_insert: bool) -> Self {
+		self.copy_insert = Some(copy_insert);
+		self
+	}
+
+	/// How long shutting down may wait for blocks that are being executed, and for storage that
+	/// has not been inserted yet. Blocks that are not finished in time are executed again on restart.
+	///
+	/// # Default
+	/// Defaults to 30 seconds
+	pub fn shutdown_timeout(mut self, timeout: Duration) -> Self {
+		self.shutdown_timeout = Some(timeout);
+		self
+	}
+
+	/// Only index the storage keys that pass `filter`.
+	///
+	/// # Default
+	/// Defaults to indexing every storage key
+	pub fn storage_filter(mut self, filter: StorageFilter) -> Self {
+		self.storage_filter = Some(filter);
+		self
+	}
+
+	/// Store the full state of every block whose number is a multiple of `interval`, marked with `is_full`,
+	/// instead of only the changes the block made. Queries of past state can start from the nearest snapshot.
+	///
+	/// # Default
+	/// Defaults to no snapshots
+	pub fn snapshot_interval(mut self, interval: u64) -> Self {
+		self.snapshot_interval = Some(interval);
+		self
+	}
+
+	/// Store the full state of every block that upgrades the runtime.
+	///
+	/// # Default
+	/// Defaults to false
+	pub fn snapshot_on_upgrade(mut self, on_upgrade: bool) -> Self {
+		self.snapshot_on_upgrade = Some(on_upgrade);
+		self
+	}
+
+	/// Write blocks, storage, metadata and decoded data to `sink`.
+	/// May be called more than once to write to several sinks.
+	pub fn sink<S: ArchiveSink<B> + 'static>(mut self, sink: S) -> Self {
+		self.sinks.push(Arc::new(sink));
+		self
+	}
+
+	/// Write storage and decoded data to Postgres. If false, they are only written to the sinks added with `sink`.
+	/// Blocks and metadata are still written to Postgres, the archive needs them to know which blocks are left to execute.
+	///
+	/// # Default
+	/// Defaults to true
+	pub fn postgres_sink(mut self, postgres_sink: bool) -> Self {
+		self.postgres_sink = Some(postgres_sink);
+		self
+	}
 }
 
 fn parse_urls(chain_data_path: Option<String>, pg_url: Option<String>) -> (String, String) {
@@ -190,7 +367,7 @@ where
 		+ 'static,
 	D: NativeExecutionDispatch + 'static,
 	<R::RuntimeApi as sp_api::ApiExt<B>>::StateBackend: sp_api::StateBackend<BlakeTwo256>,
-	NumberFor<B>: Into<u32> + From<u32> + Unpin,
+	NumberFor<B>: Unpin,
 	B::Hash: From<primitive_types::H256> + Unpin,
 	B::Header: serde::de::DeserializeOwned,
 {
@@ -201,12 +378,21 @@ where
 	/// Panics if one of chain_data_db or pg_url is not passed to the builder
 	/// and their respective environment variables are not set.
 	pub fn build(self) -> Result<impl traits::Archive<B, DB>> {
+		if let (Some(start), Some(end)) = (self.start_block, self.end_block) {
+			if start > end {
+				return Err(format!("start block {} is after end block {}", start, end).into());
+			}
+		}
+		if self.postgres_sink == Some(false) && self.sinks.is_empty() {
+			return Err(
+				"Postgres is not a sink and no other sink was added, storage would not be written anywhere".into()
+			);
+		}
 		let num_cpus = num_cpus::get();
 		let (chain_path, pg_url) = parse_urls(self.chain_data_path, self.pg_url);
 		let cache_size = self.cache_size.unwrap_or(128);
 		let block_workers = self.block_workers.unwrap_or(num_cpus);
 		let wasm_pages = self.wasm_pages.unwrap_or(64 * num_cpus as u64);
-		let max_block_load = self.max_block_load.unwrap_or(100_000);
 		let db_path = create_database_path(self.chain_spec)?;
 		smol::block_on(crate::migrations::migrate(&pg_url))?;
 		let db = Arc::new(DB::open_database(chain_path.as_str(), cache_size, db_path)?);
@@ -215,7 +401,27 @@ where
 		let backend = Arc::new(ReadOnlyBackend::new(db, true));
 		Self::startup_info(&*client, &*backend)?;
 
-		let ctx = System::<_, R, _, _>::new(client, backend, block_workers, pg_url.as_str(), max_block_load)?;
+		let config = SystemConfig {
+			workers: block_workers,
+			pg_url,
+			max_block_load: self.max_block_load.unwrap_or(100_000),
+			index_forks: self.index_forks.unwrap_or(false),
+			start_block: self.start_block,
+			end_block: self.end_block,
+			finalized_only: self.finalized_only.unwrap_or(false),
+			confirmation_depth: self.confirmation_depth,
+			decode: self.decode.unwrap_or(false),
+			copy_insert: self.copy_insert.unwrap_or(false),
+			postgres_sink: self.postgres_sink.unwrap_or(true),
+			shutdown_timeout: self.shutdown_timeout.unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT),
+			storage_filter: self.storage_filter.unwrap_or_default(),
+			snapshots: Snapshots {
+				// a snapshot every 0 blocks means no snapshots
+				interval: self.snapshot_interval.filter(|n| *n > 0),
+				on_upgrade: self.snapshot_on_upgrade.unwrap_or(false),
+			},
+		};
+		let ctx = System::<_, R, _, _>::new(client, backend, config, self.sinks)?;
 		Ok(ctx)
 	}
 
@@ -18,6 +18,7 @@
 //! Handles inserting of data into the database
 
 mod batch;
+mod copy;
 pub mod listener;
 pub mod queries;
 
@@ -28,11 +29,16 @@ use codec::Encode;
 use sqlx::prelude::*;
 use sqlx::{postgres::PgPoolOptions, PgPool, Postgres};
 
-use sp_runtime::traits::{Block as BlockT, Header as _, NumberFor};
+use sp_runtime::traits::{Block as BlockT, Header as _, SaturatedConversion};
 
-use substrate_archive_common::{models::StorageModel, types::*, Result};
+use substrate_archive_common::{
+	models::{ChildStorageModel, DigestLogModel, EventModel, ExtrinsicModel, StorageModel},
+	types::*,
+	ArchiveError, Result,
+};
 
 use self::batch::Batch;
+pub use self::copy::{CopyClients, CopyInsert};
 pub use self::listener::*;
 
 pub type DbReturn = Result<u64>;
@@ -49,25 +55,26 @@ pub trait Insert: Send {
 pub struct Database {
 	/// pool of database connections
 	pool: PgPool,
-	url: String,
+	/// clients for `COPY`, which SQLx does not support. `None` if inserting with `COPY` is disabled
+	copy_clients: Option<CopyClients>,
 }
 
 impl Database {
-	/// Connect to the database
-	pub async fn new(url: String) -> Result<Self> {
+	/// Connect to the database. Connections for `COPY` are only set up if `copy_insert` is true.
+	pub async fn new(url: String, copy_insert: bool) -> Result<Self> {
 		let pool = PgPoolOptions::new()
 			.min_connections(4)
 			.max_connections(28)
 			.idle_timeout(Duration::from_millis(3600)) // kill connections after 3.6 seconds of idle
 			.connect(url.as_str())
 			.await?;
-		Ok(Self { pool, url })
+		Self::with_pool(url, pool, copy_insert)
 	}
 
 	/// Start the database with a pre-defined pool
-	#[allow(unused)]
-	pub fn with_pool(url: String, pool: PgPool) -> Self {
-		Self { pool, url }
+	pub fn with_pool(url: String, pool: PgPool, copy_insert: bool) -> Result<Self> {
+		let copy_clients = if copy_insert { Some(CopyClients::new(url.as_str())?) } else { None };
+		Ok(Self { pool, copy_clients })
 	}
 
 	#[allow(unused)]
@@ -77,6 +84,13 @@ impl Database {
 		Ok(res)
 	}
 
+	/// Insert `data` with binary `COPY`.
+	/// `COPY` is not supported by SQLx, so this uses a separate, blocking connection on its own thread.
+	pub async fn copy(&self, data: impl CopyInsert + 'static) -> Result<u64> {
+		let clients = self.copy_clients.clone().ok_or_else(|| ArchiveError::from("Inserting with COPY is disabled"))?;
+		smol::unblock(move || clients.with_client(|client| data.copy(client))).await
+	}
+
 	pub async fn conn(&self) -> Result<DbConn> {
 		self.pool.acquire().await.map_err(Into::into)
 	}
@@ -90,7 +104,6 @@ impl Database {
 impl<B> Insert for Block<B>
 where
 	B: BlockT,
-	NumberFor<B>: Into<u32>,
 {
 	async fn insert(mut self, conn: &mut DbConn) -> DbReturn {
 		log::info!("Inserting single block");
@@ -101,13 +114,15 @@ where
 		);
 		let query = sqlx::query(
 			r#"
-            INSERT INTO blocks (parent_hash, hash, block_num, state_root, extrinsics_root, digest, ext, spec) VALUES($1, $2, $3, $4, $5, $6, $7, $8)
-            ON CONFLICT DO NOTHING
+            INSERT INTO blocks (parent_hash, hash, block_num, state_root, extrinsics_root, digest, ext, spec, is_canonical, justification) VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+            ON CONFLICT (hash) DO UPDATE SET
+                is_canonical = EXCLUDED.is_canonical,
+                justification = COALESCE(EXCLUDED.justification, blocks.justification)
         "#,
 		);
 		let parent_hash = self.inner.block.header().parent_hash().as_ref();
 		let hash = self.inner.block.header().hash();
-		let block_num: u32 = (*self.inner.block.header().number()).into();
+		let block_num = (*self.inner.block.header().number()).saturated_into::<u64>() as i64;
 		let state_root = self.inner.block.header().state_root().as_ref();
 		let extrinsics_root = self.inner.block.header().extrinsics_root().as_ref();
 		let digest = self.inner.block.header().digest().encode();
@@ -122,6 +137,8 @@ where
 			.bind(digest.as_slice())
 			.bind(extrinsics.as_slice())
 			.bind(self.spec)
+			.bind(self.is_canonical)
+			.bind(self.inner.justification.as_deref())
 			.execute(conn)
 			.await
 			.map(|d| d.rows_affected())
@@ -133,28 +150,29 @@ where
 impl<B> Insert for BatchBlock<B>
 where
 	B: BlockT,
-	NumberFor<B>: Into<u32>,
 {
 	async fn insert(mut self, conn: &mut DbConn) -> DbReturn {
 		let mut batch = Batch::new(
 			"blocks",
 			r#"
             INSERT INTO "blocks" (
-                parent_hash, hash, block_num, state_root, extrinsics_root, digest, ext, spec
+                parent_hash, hash, block_num, state_root, extrinsics_root, digest, ext, spec, is_canonical, justification
             ) VALUES
             "#,
 			r#"
-            ON CONFLICT DO NOTHING
+            ON CONFLICT (hash) DO UPDATE SET
+                is_canonical = EXCLUDED.is_canonical,
+                justification = COALESCE(EXCLUDED.justification, blocks.justification)
             "#,
 		);
 		for b in self.inner.into_iter() {
-			batch.reserve(8)?;
+			batch.reserve(10)?;
 			if batch.current_num_arguments() > 0 {
 				batch.append(",");
 			}
 			let parent_hash = b.inner.block.header().parent_hash().as_ref();
 			let hash = b.inner.block.header().hash();
-			let block_num: u32 = (*b.inner.block.header().number()).into();
+			let block_num = (*b.inner.block.header().number()).saturated_into::<u64>() as i64;
 			let state_root = b.inner.block.header().state_root().as_ref();
 			let extrinsics_root = b.inner.block.header().extrinsics_root().as_ref();
 			let digest = b.inner.block.header().digest().encode();
@@ -175,6 +193,10 @@ where
 			batch.bind(extrinsics.as_slice())?;
 			batch.append(",");
 			batch.bind(b.spec)?;
+			batch.append(",");
+			batch.bind(b.is_canonical)?;
+			batch.append(",");
+			batch.bind(b.inner.justification.as_deref())?;
 			batch.append(")");
 		}
 		Ok(batch.execute(conn).await?)
@@ -197,7 +219,7 @@ impl<B: BlockT> Insert for StorageModel<B> {
                     is_full = EXCLUDED.is_full
             "#,
 		)
-		.bind(self.block_num())
+		.bind(self.block_num() as i64)
 		.bind(self.hash().as_ref())
 		.bind(self.is_full())
 		.bind(self.key().0.as_slice())
@@ -234,12 +256,55 @@ impl<B: BlockT> Insert for Vec<StorageModel<B>> {
 				batch.append(",");
 			}
 			batch.append("(");
-			batch.bind(s.block_num())?;
+			batch.bind(s.block_num() as i64)?;
+			batch.append(",");
+			batch.bind(s.hash().as_ref())?;
+			batch.append(",");
+			batch.bind(s.is_full())?;
+			batch.append(",");
+			batch.bind(s.key().0.as_slice())?;
+			batch.append(",");
+			batch.bind(s.data().map(|d| d.0.as_slice()))?;
+			batch.append(")");
+		}
+		Ok(batch.execute(conn).await?)
+	}
+}
+
+#[async_trait]
+impl<B: BlockT> Insert for Vec<ChildStorageModel<B>> {
+	async fn insert(mut self, conn: &mut DbConn) -> DbReturn {
+		let mut batch = Batch::new(
+			"child_storage",
+			r#"
+            INSERT INTO "child_storage" (
+                block_num, hash, is_full, child_key, key, storage
+            ) VALUES
+            "#,
+			r#"
+            ON CONFLICT (hash, child_key, key, md5(storage)) DO UPDATE SET
+                hash = EXCLUDED.hash,
+                child_key = EXCLUDED.child_key,
+                key = EXCLUDED.key,
+                storage = EXCLUDED.storage,
+                is_full = EXCLUDED.is_full
+            "#,
+		);
+
+		for s in self.into_iter() {
+			batch.reserve(6)?;
+			if batch.current_num_arguments() > 0 {
+				batch.append(",");
+			}
+			batch.append("(");
+			batch.bind(s.block_num() as i64)?;
 			batch.append(",");
 			batch.bind(s.hash().as_ref())?;
 			batch.append(",");
 			batch.bind(s.is_full())?;
 			batch.append(",");
+			batch.bind(s.child_key().0.as_slice())?;
+			batch.append(",");
 			batch.bind(s.key().0.as_slice())?;
 			batch.append(",");
 			batch.bind(s.data().map(|d| d.0.as_slice()))?;
@@ -249,19 +314,174 @@ impl<B: BlockT> Insert for Vec<StorageModel<B>> {
 	}
 }
 
+#[async_trait]
+impl<B: BlockT> Insert for Vec<ExtrinsicModel<B>> {
+	async fn insert(mut self, conn: &mut DbConn) -> DbReturn {
+		let mut batch = Batch::new(
+			"extrinsics",
+			r#"
+            INSERT INTO "extrinsics" (
+                hash, block_num, index, signer, nonce, tip, pallet, call, args, success
+            ) VALUES
+            "#,
+			r#"
+            ON CONFLICT (hash, index) DO UPDATE SET
+                signer = EXCLUDED.signer,
+                nonce = EXCLUDED.nonce,
+                tip = EXCLUDED.tip,
+                pallet = EXCLUDED.pallet,
+                call = EXCLUDED.call,
+                args = EXCLUDED.args,
+                success = EXCLUDED.success
+            "#,
+		);
+
+		for e in self.into_iter() {
+			batch.reserve(10)?;
+			if batch.current_num_arguments() > 0 {
+				batch.append(",");
+			}
+			batch.append("(");
+			batch.bind(e.hash.as_ref())?;
+			batch.append(",");
+			batch.bind(e.block_num as i64)?;
+			batch.append(",");
+			batch.bind(e.index)?;
+			batch.append(",");
+			batch.bind(e.signer)?;
+			batch.append(",");
+			batch.bind(e.nonce.map(|n| n as i64))?;
+			batch.append(",");
+			// u128 has no postgres equivalent, so the tip is sent as text
+			batch.bind(e.tip.map(|t| t.to_string()))?;
+			batch.append("::numeric,");
+			batch.bind(e.pallet)?;
+			batch.append(",");
+			batch.bind(e.call)?;
+			batch.append(",");
+			batch.bind(e.args)?;
+			batch.append(",");
+			batch.bind(e.success)?;
+			batch.append(")");
+		}
+		Ok(batch.execute(conn).await?)
+	}
+}
+
+#[async_trait]
+impl<B: BlockT> Insert for Vec<EventModel<B>> {
+	async fn insert(mut self, conn: &mut DbConn) -> DbReturn {
+		let mut batch = Batch::new(
+			"events",
+			r#"
+            INSERT INTO "events" (
+                hash, block_num, index, extrinsic_index, phase, pallet, event, fields
+            ) VALUES
+            "#,
+			r#"
+            ON CONFLICT (hash, index) DO UPDATE SET
+                extrinsic_index = EXCLUDED.extrinsic_index,
+                phase = EXCLUDED.phase,
+                pallet = EXCLUDED.pallet,
+                event = EXCLUDED.event,
+                fields = EXCLUDED.fields
+            "#,
+		);
+
+		for e in self.into_iter() {
+			batch.reserve(8)?;
+			if batch.current_num_arguments() > 0 {
+				batch.append(",");
+			}
+			batch.append("(");
+			batch.bind(e.hash.as_ref())?;
+			batch.append(",");
+			batch.bind(e.block_num as i64)?;
+			batch.append(",");
+			batch.bind(e.index)?;
+			batch.append(",");
+			batch.bind(e.extrinsic_index)?;
+			batch.append(",");
+			batch.bind(e.phase)?;
+			batch.append(",");
+			batch.bind(e.pallet)?;
+			batch.append(",");
+			batch.bind(e.event)?;
+			batch.append(",");
+			batch.bind(e.fields)?;
+			batch.append(")");
+		}
+		Ok(batch.execute(conn).await?)
+	}
+}
+
+#[async_trait]
+impl<B: BlockT> Insert for Vec<DigestLogModel<B>> {
+	async fn insert(mut self, conn: &mut DbConn) -> DbReturn {
+		let mut batch = Batch::new(
+			"digest_logs",
+			r#"
+            INSERT INTO "digest_logs" (
+                hash, block_num, index, kind, engine, data, slot, authority_index, authority, author
+            ) VALUES
+            "#,
+			r#"
+            ON CONFLICT (hash, index) DO UPDATE SET
+                kind = EXCLUDED.kind,
+                engine = EXCLUDED.engine,
+                data = EXCLUDED.data,
+                slot = EXCLUDED.slot,
+                authority_index = EXCLUDED.authority_index,
+                authority = EXCLUDED.authority,
+                author = EXCLUDED.author
+            "#,
+		);
+
+		for d in self.into_iter() {
+			batch.reserve(10)?;
+			if batch.current_num_arguments() > 0 {
+				batch.append(",");
+			}
+			batch.append("(");
+			batch.bind(d.hash.as_ref())?;
+			batch.append(",");
+			batch.bind(d.block_num as i64)?;
+			batch.append(",");
+			batch.bind(d.index)?;
+			batch.append(",");
+			batch.bind(d.kind)?;
+			batch.append(",");
+			batch.bind(d.engine)?;
+			batch.append(",");
+			batch.bind(d.data)?;
+			batch.append(",");
+			batch.bind(d.slot.map(|s| s as i64))?;
+			batch.append(",");
+			batch.bind(d.authority_index)?;
+			batch.append(",");
+			batch.bind(d.authority)?;
+			batch.append(",");
+			batch.bind(d.author)?;
+			batch.append(")");
+		}
+		Ok(batch.execute(conn).await?)
+	}
+}
+
 #[async_trait]
 impl Insert for Metadata {
 	async fn insert(mut self, conn: &mut DbConn) -> DbReturn {
 		log::debug!("Inserting Metadata");
 		sqlx::query(
 			r#"
-            INSERT INTO metadata (version, meta)
-            VALUES($1, $2)
+            INSERT INTO metadata (version, meta, runtime_version)
+            VALUES($1, $2, $3)
             ON CONFLICT DO NOTHING
         "#,
 		)
 		.bind(self.version())
 		.bind(self.meta())
+		.bind(self.runtime_version())
 		.execute(conn)
 		.await
 		.map(|d| d.rows_affected())
@@ -0,0 +1,192 @@
+// Copyright 2017-2019 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Bulk inserts with the binary `COPY` protocol.
+//! SQLx does not support `COPY`, so these use a blocking `postgres` client.
+//! `COPY` cannot resolve conflicts, so rows are streamed into a temporary staging table
+//! and moved into their destination with a single `INSERT ... SELECT ... ON CONFLICT`.
+
+use std::sync::Arc;
+
+use codec::Encode;
+use parking_lot::Mutex;
+use postgres::{
+	binary_copy::BinaryCopyInWriter,
+	types::{ToSql, Type},
+	Client, Config,
+};
+use tokio_postgres_rustls::MakeRustlsConnect;
+
+use sp_runtime::traits::{Block as BlockT, Header as _, SaturatedConversion};
+
+use substrate_archive_common::{models::StorageModel, types::BatchBlock, ArchiveError, Result};
+
+/// Blocking `postgres` clients for `COPY`, kept open between batches.
+#[derive(Clone)]
+pub struct CopyClients {
+	config: Config,
+	tls: MakeRustlsConnect,
+	idle: Arc<Mutex<Vec<Client>>>,
+}
+
+impl CopyClients {
+	/// Clients for the database at `url`. Whether they use TLS depends on the `sslmode` of `url`.
+	pub fn new(url: &str) -> Result<Self> {
+		let config = url.parse::<Config>().map_err(pg_error)?;
+		let mut tls = rustls::ClientConfig::new();
+		tls.root_store.add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+		Ok(Self { config, tls: MakeRustlsConnect::new(tls), idle: Arc::new(Mutex::new(Vec::new())) })
+	}
+
+	/// Run `fun` with an idle client, connecting a new one if none is idle.
+	/// The client is kept for the next call unless its connection was closed.
+	pub fn with_client<T>(&self, fun: impl FnOnce(&mut Client) -> Result<T>) -> Result<T> {
+		let idle = self.idle.lock().pop();
+		let mut client = match idle {
+			Some(client) if !client.is_closed() => client,
+			_ => self.config.connect(self.tls.clone()).map_err(pg_error)?,
+		};
+		let res = fun(&mut client);
+		if !client.is_closed() {
+			self.idle.lock().push(client);
+		}
+		res
+	}
+}
+
+/// `postgres` errors are only returned by `COPY`, so they are not part of `ArchiveError`
+fn pg_error(e: postgres::Error) -> ArchiveError {
+	ArchiveError::from(format!("postgres error: {}", e))
+}
+
+pub trait CopyInsert: Send {
+	/// Copy the rows into the database.
+	/// Returns the number of rows inserted or updated.
+	fn copy(self, client: &mut Client) -> Result<u64>
+	where
+		Self: Sized;
+}
+
+/// Stream rows into a temporary table with the `columns` of `table`, and move them into `table` with `upsert`.
+/// Everything happens in one transaction, so either all rows are inserted or none are.
+fn stage<F>(client: &mut Client, table: &str, columns: &str, types: &[Type], upsert: &str, write: F) -> Result<u64>
+where
+	F: FnOnce(&mut BinaryCopyInWriter<'_>) -> Result<()>,
+{
+	let mut tx = client.transaction().map_err(pg_error)?;
+	tx.batch_execute(&format!(
+		"CREATE TEMP TABLE staging_{table} ON COMMIT DROP AS SELECT {columns} FROM {table} WITH NO DATA",
+		table = table,
+		columns = columns
+	))
+	.map_err(pg_error)?;
+	let sink = tx.copy_in(&format!("COPY staging_{} ({}) FROM STDIN BINARY", table, columns)).map_err(pg_error)?;
+	let mut writer = BinaryCopyInWriter::new(sink, types);
+	write(&mut writer)?;
+	writer.finish().map_err(pg_error)?;
+	let rows = tx.execute(upsert, &[]).map_err(pg_error)?;
+	tx.commit().map_err(pg_error)?;
+	Ok(rows)
+}
+
+impl<B: BlockT> CopyInsert for BatchBlock<B> {
+	fn copy(self, client: &mut Client) -> Result<u64> {
+		stage(
+			client,
+			"blocks",
+			"parent_hash, hash, block_num, state_root, extrinsics_root, digest, ext, spec, is_canonical, justification",
+			&[
+				Type::BYTEA,
+				Type::BYTEA,
+				Type::INT8,
+				Type::BYTEA,
+				Type::BYTEA,
+				Type::BYTEA,
+				Type::BYTEA,
+				Type::INT4,
+				Type::BOOL,
+				Type::BYTEA,
+			],
+			r#"
+            INSERT INTO blocks (
+                parent_hash, hash, block_num, state_root, extrinsics_root, digest, ext, spec, is_canonical, justification
+            )
+            SELECT * FROM staging_blocks
+            ON CONFLICT (hash) DO UPDATE SET
+                is_canonical = EXCLUDED.is_canonical,
+                justification = COALESCE(EXCLUDED.justification, blocks.justification)
+            "#,
+			|writer| {
+				for b in self.inner.into_iter() {
+					let header = b.inner.block.header();
+					let hash = header.hash();
+					let block_num = (*header.number()).saturated_into::<u64>() as i64;
+					let digest = header.digest().encode();
+					let extrinsics = b.inner.block.extrinsics().encode();
+					let spec = b.spec as i32;
+					let justification = b.inner.justification.as_deref();
+					let row: [&(dyn ToSql + Sync); 10] = [
+						&header.parent_hash().as_ref(),
+						&hash.as_ref(),
+						&block_num,
+						&header.state_root().as_ref(),
+						&header.extrinsics_root().as_ref(),
+						&digest,
+						&extrinsics,
+						&spec,
+						&b.is_canonical,
+						&justification,
+					];
+					writer.write(&row).map_err(pg_error)?;
+				}
+				Ok(())
+			},
+		)
+	}
+}
+
+impl<B: BlockT> CopyInsert for Vec<StorageModel<B>> {
+	fn copy(self, client: &mut Client) -> Result<u64> {
+		stage(
+			client,
+			"storage",
+			"block_num, hash, is_full, key, storage",
+			&[Type::INT8, Type::BYTEA, Type::BOOL, Type::BYTEA, Type::BYTEA],
+			r#"
+            INSERT INTO storage (block_num, hash, is_full, key, storage)
+            SELECT * FROM staging_storage
+            ON CONFLICT (hash, key, md5(storage)) DO UPDATE SET
+                hash = EXCLUDED.hash,
+                key = EXCLUDED.key,
+                storage = EXCLUDED.storage,
+                is_full = EXCLUDED.is_full
+            "#,
+			|writer| {
+				for s in self.into_iter() {
+					let row: [&(dyn ToSql + Sync); 5] = [
+						&(s.block_num() as i64),
+						&s.hash().as_ref(),
+						&s.is_full(),
+						&s.key().0.as_slice(),
+						&s.data().map(|d| d.0.as_slice()),
+					];
+					writer.write(&row).map_err(pg_error)?;
+				}
+				Ok(())
+			},
+		)
+	}
+}
@@ -122,7 +122,7 @@ where
 		let mut listener = PgListener::connect(&self.pg_url).await?;
 		let channels = self.channels.iter().map(String::from).collect::<Vec<String>>();
 		listener.listen_all(channels.iter().map(|s| s.as_ref())).await?;
-		let mut conn = PgConnection::connect(&self.pg_url).await.unwrap();
+		let mut conn = PgConnection::connect(&self.pg_url).await?;
 
 		let fut = async move {
 			let mut listener = listener.into_stream();
@@ -163,7 +163,10 @@ where
 				_ = FutureExt::fuse(timeout) => {},
 				notifs = listener.collect::<Vec<_>>().fuse() => {
 					for msg in notifs {
-						self.handle_listen_event(msg.unwrap(), &mut conn).await;
+						match msg {
+							Ok(v) => self.handle_listen_event(v, &mut conn).await,
+							Err(e) => log::error!("{:?}", e),
+						}
 					}
 				}
 			}
@@ -176,8 +179,16 @@ where
 
 	/// Handle a listen event from Postgres
 	async fn handle_listen_event(&self, notif: PgNotification, conn: &mut PgConnection) {
-		let payload: Notif = serde_json::from_str(notif.payload()).unwrap();
-		(self.task)(payload, conn).await.unwrap();
+		let payload: Notif = match serde_json::from_str(notif.payload()) {
+			Ok(p) => p,
+			Err(e) => {
+				log::error!("Could not parse notification {}: {:?}", notif.payload(), e);
+				return;
+			}
+		};
+		if let Err(e) = (self.task)(payload, conn).await {
+			log::error!("Listener task failed: {:?}", e);
+		}
 	}
 }
 
@@ -17,14 +17,15 @@
 //! Common Sql queries on Archive Database abstracted into rust functions
 
 use std::convert::TryFrom;
+use std::ops::RangeInclusive;
 
-use hashbrown::HashSet;
+use hashbrown::{HashMap, HashSet};
 use serde::{de::DeserializeOwned, Deserialize};
 use sqlx::PgConnection;
 
 use sp_runtime::traits::Block as BlockT;
 
-use substrate_archive_common::{models::BlockModel, Result};
+use substrate_archive_common::{models::BlockModel, ArchiveError, Result};
 
 /// Return type of queries that `SELECT version`
 struct Version {
@@ -33,12 +34,12 @@ struct Version {
 
 /// Return type of queries that `SELECT missing_num ... FROM ... GENERATE_SERIES(a, z)`
 pub struct Series {
-	missing_num: Option<i32>,
+	missing_num: Option<i64>,
 }
 
-/// Return type of queries that `SELECT MAX(int)`
+/// Return type of queries that `SELECT MAX(bigint)`
 struct Max {
-	max: Option<i32>,
+	max: Option<i64>,
 }
 
 /// Return type of queries that `SELECT EXISTS`
@@ -48,7 +49,12 @@ struct DoesExist {
 
 // Return type of queries that `SELECT block_num`
 struct BlockNum {
-	block_num: i32,
+	block_num: i64,
+}
+
+// Return type of queries that `SELECT COUNT(*)`
+struct Count {
+	count: Option<i64>,
 }
 
 // Return type of queries that `SELECT data`
@@ -56,15 +62,69 @@ struct Bytes {
 	data: Vec<u8>,
 }
 
+/// Return type of queries that `SELECT version, runtime_version`
+struct RuntimeVersion {
+	version: i32,
+	runtime_version: Option<serde_json::Value>,
+}
+
+/// Return type of queries that `SELECT storage`
+struct StorageValue {
+	storage: Option<Vec<u8>>,
+}
+
+/// Return type of queries that `SELECT block_num, hash, storage`
+struct StorageRow {
+	block_num: i64,
+	hash: Vec<u8>,
+	storage: Option<Vec<u8>>,
+}
+
+/// Return type of queries that `SELECT block_num, hash`
+struct NumHash {
+	block_num: i64,
+	hash: Vec<u8>,
+}
+
+/// Return type of queries that `SELECT key, storage`
+struct KeyValue {
+	key: Vec<u8>,
+	storage: Option<Vec<u8>>,
+}
+
+/// Return type of queries that `SELECT hash, key, storage`
+struct BlockKeyValue {
+	hash: Vec<u8>,
+	key: Vec<u8>,
+	storage: Option<Vec<u8>>,
+}
+
+/// Return type of queries that `SELECT hash, child_key, key, storage`
+struct ChildKeyValue {
+	hash: Vec<u8>,
+	child_key: Vec<u8>,
+	key: Vec<u8>,
+	storage: Option<Vec<u8>>,
+}
+
+/// A value of a storage key, and the block it was set in
+#[derive(Debug, Clone, PartialEq)]
+pub struct StorageChange {
+	pub block_num: u64,
+	pub hash: Vec<u8>,
+	/// `None` if the key was removed, or had no value at the start of the range
+	pub value: Option<Vec<u8>>,
+}
+
 /// Get missing blocks from the relational database between numbers `min` and
 /// MAX(block_num). LIMIT result to length `max_block_load`. The highest effective
-/// value for `min` is i32::MAX.
+/// value for `min` is i64::MAX.
 pub(crate) async fn missing_blocks_min_max(
 	conn: &mut PgConnection,
-	min: u32,
+	min: u64,
 	max_block_load: u32,
-) -> Result<HashSet<u32>> {
-	let min = i32::try_from(min).unwrap_or(i32::MAX);
+) -> Result<HashSet<u64>> {
+	let min = i64::try_from(min).unwrap_or(i64::MAX);
 	let max_block_load = i64::try_from(max_block_load).unwrap_or(i64::MAX);
 	// Remove after launchbadge/sqlx#594 is fixed
 	#[allow(clippy::toplevel_ref_arg)]
@@ -84,18 +144,18 @@ pub(crate) async fn missing_blocks_min_max(
 	.fetch_all(conn)
 	.await?
 	.iter()
-	.map(|t| t.missing_num.unwrap() as u32)
+	.map(|t| t.missing_num.unwrap() as u64)
 	.collect())
 }
 
 /// Get the maximum block number from the relational database
-pub(crate) async fn max_block(conn: &mut PgConnection) -> Result<Option<u32>> {
+pub(crate) async fn max_block(conn: &mut PgConnection) -> Result<Option<u64>> {
 	let max = sqlx::query_as!(Max, "SELECT MAX(block_num) FROM blocks").fetch_one(conn).await?;
-	Ok(max.max.map(|v| v as u32))
+	Ok(max.max.map(|v| v as u64))
 }
 
 /// Will get blocks such that they exist in the `blocks` table but they
-/// do not exist in the `storage` table
+/// have not been executed, and are not waiting in the `_block_outbox` table
 /// blocks are ordered by spec version
 ///
 /// # Returns full blocks
@@ -105,7 +165,9 @@ pub(crate) async fn blocks_storage_intersection(conn: &mut sqlx::PgConnection) -
 		BlockModel,
 		"SELECT *
         FROM blocks
-        WHERE NOT EXISTS (SELECT * FROM storage WHERE storage.block_num = blocks.block_num)
+        WHERE NOT EXISTS (SELECT * FROM storage WHERE storage.hash = blocks.hash)
+        AND NOT EXISTS (SELECT * FROM executed_blocks WHERE executed_blocks.hash = blocks.hash)
+        AND NOT EXISTS (SELECT * FROM _block_outbox WHERE _block_outbox.hash = blocks.hash)
         AND blocks.block_num != 0
         ORDER BY blocks.spec",
 	)
@@ -114,34 +176,97 @@ pub(crate) async fn blocks_storage_intersection(conn: &mut sqlx::PgConnection) -
 	.map_err(Into::into)
 }
 
-/// Get a block by id from the relational database
-pub(crate) async fn get_full_block_by_id(conn: &mut sqlx::PgConnection, id: i32) -> Result<BlockModel> {
+/// Count the blocks that exist in the `blocks` table but have not been executed
+pub(crate) async fn count_missing_storage(conn: &mut PgConnection) -> Result<u64> {
+	#[allow(clippy::toplevel_ref_arg)]
+	let count = sqlx::query_as!(
+		Count,
+		"SELECT COUNT(*)
+        FROM blocks
+        WHERE NOT EXISTS (SELECT * FROM storage WHERE storage.hash = blocks.hash)
+        AND NOT EXISTS (SELECT * FROM executed_blocks WHERE executed_blocks.hash = blocks.hash)
+        AND blocks.block_num != 0",
+	)
+	.fetch_one(conn)
+	.await?;
+	Ok(count.count.unwrap_or(0) as u64)
+}
+
+/// Count the blocks waiting to be executed in the background task queue
+pub(crate) async fn count_pending_executions(conn: &mut PgConnection) -> Result<u64> {
+	#[allow(clippy::toplevel_ref_arg)]
+	let count = sqlx::query_as!(Count, "SELECT COUNT(*) FROM _background_tasks WHERE job_type = 'execute_block'",)
+		.fetch_one(conn)
+		.await?;
+	Ok(count.count.unwrap_or(0) as u64)
+}
+
+/// Count the blocks in the `_block_outbox` table that have not been queued for execution yet
+pub(crate) async fn count_outbox(conn: &mut PgConnection) -> Result<u64> {
+	#[allow(clippy::toplevel_ref_arg)]
+	let count = sqlx::query_as!(Count, "SELECT COUNT(*) FROM _block_outbox",).fetch_one(conn).await?;
+	Ok(count.count.unwrap_or(0) as u64)
+}
+
+/// Count the block numbers in `range` that have no block in the database.
+pub(crate) async fn count_missing_blocks(conn: &mut PgConnection, range: RangeInclusive<u64>) -> Result<u64> {
+	let from = i64::try_from(*range.start()).unwrap_or(i64::MAX);
+	let to = i64::try_from(*range.end()).unwrap_or(i64::MAX);
+	#[allow(clippy::toplevel_ref_arg)]
+	let count = sqlx::query_as!(
+		Count,
+		"SELECT COUNT(*)
+        FROM GENERATE_SERIES($1::bigint, $2::bigint) AS missing_num
+        WHERE NOT EXISTS (SELECT id FROM blocks WHERE block_num = missing_num)
+        ",
+		from,
+		to
+	)
+	.fetch_one(conn)
+	.await?;
+	Ok(count.count.unwrap_or(0) as u64)
+}
+
+/// Get up to `limit` blocks waiting in the `_block_outbox` table, oldest first.
+/// The outbox rows are locked until the end of the transaction,
+/// and rows locked by another transaction are skipped.
+pub(crate) async fn outbox_blocks(conn: &mut PgConnection, limit: u32) -> Result<Vec<BlockModel>> {
+	let limit = i64::from(limit);
 	#[allow(clippy::toplevel_ref_arg)]
 	sqlx::query_as!(
 		BlockModel,
 		"
-        SELECT id, parent_hash, hash, block_num, state_root, extrinsics_root, digest, ext, spec
-        FROM blocks
-        WHERE id = $1
+        SELECT id, parent_hash, blocks.hash, block_num, state_root, extrinsics_root, digest, ext, spec, is_canonical, justification
+        FROM _block_outbox
+        INNER JOIN blocks ON blocks.hash = _block_outbox.hash
+        ORDER BY _block_outbox.created_at ASC
+        LIMIT $1
+        FOR UPDATE OF _block_outbox SKIP LOCKED
         ",
-		id
+		limit
 	)
-	.fetch_one(conn)
+	.fetch_all(conn)
 	.await
 	.map_err(Into::into)
 }
 
-/// Get a block by block number from the relational database
+/// Remove the blocks identified by `hashes` from the `_block_outbox` table
+pub(crate) async fn remove_from_outbox(hashes: &[Vec<u8>], conn: &mut PgConnection) -> Result<u64> {
+	#[allow(clippy::toplevel_ref_arg)]
+	Ok(sqlx::query!("DELETE FROM _block_outbox WHERE hash = ANY ($1)", hashes).execute(conn).await?.rows_affected())
+}
+
+/// Get the canonical block by block number from the relational database
 #[cfg(test)]
-pub(crate) async fn get_full_block_by_num(conn: &mut sqlx::PgConnection, block_num: u32) -> Result<BlockModel> {
-	let safe_block_num = i32::try_from(block_num).unwrap_or(i32::MAX);
+pub(crate) async fn get_full_block_by_num(conn: &mut sqlx::PgConnection, block_num: u64) -> Result<BlockModel> {
+	let safe_block_num = i64::try_from(block_num).unwrap_or(i64::MAX);
 	#[allow(clippy::toplevel_ref_arg)]
 	sqlx::query_as!(
 		BlockModel,
 		"
-        SELECT id, parent_hash, hash, block_num, state_root, extrinsics_root, digest, ext, spec
+        SELECT id, parent_hash, hash, block_num, state_root, extrinsics_root, digest, ext, spec, is_canonical, justification
         FROM blocks
-        WHERE block_num = $1
+        WHERE block_num = $1 AND is_canonical
         ",
 		safe_block_num
 	)
@@ -150,6 +275,306 @@ pub(crate) async fn get_full_block_by_num(conn: &mut sqlx::PgConnection, block_n
 	.map_err(Into::into)
 }
 
+/// Get a block by its hash from the relational database
+pub async fn get_full_block_by_hash(conn: &mut PgConnection, hash: &[u8]) -> Result<Option<BlockModel>> {
+	#[allow(clippy::toplevel_ref_arg)]
+	sqlx::query_as!(
+		BlockModel,
+		"
+        SELECT id, parent_hash, hash, block_num, state_root, extrinsics_root, digest, ext, spec, is_canonical, justification
+        FROM blocks
+        WHERE hash = $1
+        ",
+		hash
+	)
+	.fetch_optional(conn)
+	.await
+	.map_err(Into::into)
+}
+
+/// Get a block by its row id from the relational database
+pub async fn get_full_block_by_id(conn: &mut PgConnection, id: i32) -> Result<Option<BlockModel>> {
+	#[allow(clippy::toplevel_ref_arg)]
+	sqlx::query_as!(
+		BlockModel,
+		"
+        SELECT id, parent_hash, hash, block_num, state_root, extrinsics_root, digest, ext, spec, is_canonical, justification
+        FROM blocks
+        WHERE id = $1
+        ",
+		id
+	)
+	.fetch_optional(conn)
+	.await
+	.map_err(Into::into)
+}
+
+/// Get every block, canonical or not, with a number in `range`
+pub(crate) async fn blocks_in_range(conn: &mut PgConnection, range: RangeInclusive<u64>) -> Result<Vec<BlockModel>> {
+	let start = i64::try_from(*range.start()).unwrap_or(i64::MAX);
+	let end = i64::try_from(*range.end()).unwrap_or(i64::MAX);
+	#[allow(clippy::toplevel_ref_arg)]
+	sqlx::query_as!(
+		BlockModel,
+		"
+        SELECT id, parent_hash, hash, block_num, state_root, extrinsics_root, digest, ext, spec, is_canonical, justification
+        FROM blocks
+        WHERE block_num BETWEEN $1 AND $2
+        ORDER BY block_num
+        ",
+		start,
+		end
+	)
+	.fetch_all(conn)
+	.await
+	.map_err(Into::into)
+}
+
+/// Delete everything that executing the blocks in `range` produced: storage, child storage, events, digest logs,
+/// storage root mismatches found by verifying the storage, and the record of their execution.
+/// Returns the number of rows deleted.
+pub(crate) async fn delete_execution_results(conn: &mut PgConnection, range: RangeInclusive<u64>) -> Result<u64> {
+	let start = i64::try_from(*range.start()).unwrap_or(i64::MAX);
+	let end = i64::try_from(*range.end()).unwrap_or(i64::MAX);
+	#[allow(clippy::toplevel_ref_arg)]
+	let storage = sqlx::query!("DELETE FROM storage WHERE block_num BETWEEN $1 AND $2", start, end)
+		.execute(&mut *conn)
+		.await?
+		.rows_affected();
+	#[allow(clippy::toplevel_ref_arg)]
+	let child_storage = sqlx::query!("DELETE FROM child_storage WHERE block_num BETWEEN $1 AND $2", start, end)
+		.execute(&mut *conn)
+		.await?
+		.rows_affected();
+	#[allow(clippy::toplevel_ref_arg)]
+	let events = sqlx::query!("DELETE FROM events WHERE block_num BETWEEN $1 AND $2", start, end)
+		.execute(&mut *conn)
+		.await?
+		.rows_affected();
+	#[allow(clippy::toplevel_ref_arg)]
+	let digest_logs = sqlx::query!("DELETE FROM digest_logs WHERE block_num BETWEEN $1 AND $2", start, end)
+		.execute(&mut *conn)
+		.await?
+		.rows_affected();
+	#[allow(clippy::toplevel_ref_arg)]
+	let mismatches = sqlx::query!("DELETE FROM storage_root_mismatches WHERE block_num BETWEEN $1 AND $2", start, end)
+		.execute(&mut *conn)
+		.await?
+		.rows_affected();
+	#[allow(clippy::toplevel_ref_arg)]
+	let executed = sqlx::query!("DELETE FROM executed_blocks WHERE block_num BETWEEN $1 AND $2", start, end)
+		.execute(&mut *conn)
+		.await?
+		.rows_affected();
+	Ok(storage + child_storage + events + digest_logs + mismatches + executed)
+}
+
+/// Changes to the storage of a block: keys and their new values, or `None` if the key was removed
+pub(crate) type StorageChanges = Vec<(Vec<u8>, Option<Vec<u8>>)>;
+
+/// Get the storage archived for the blocks in `range`, by block hash
+pub(crate) async fn storage_in_range(
+	conn: &mut PgConnection,
+	range: RangeInclusive<u64>,
+) -> Result<HashMap<Vec<u8>, StorageChanges>> {
+	let start = i64::try_from(*range.start()).unwrap_or(i64::MAX);
+	let end = i64::try_from(*range.end()).unwrap_or(i64::MAX);
+	#[allow(clippy::toplevel_ref_arg)]
+	let rows = sqlx::query_as!(
+		BlockKeyValue,
+		"SELECT hash, key, storage FROM storage WHERE block_num BETWEEN $1 AND $2",
+		start,
+		end
+	)
+	.fetch_all(conn)
+	.await?;
+	let mut blocks: HashMap<Vec<u8>, StorageChanges> = HashMap::new();
+	for row in rows.into_iter() {
+		blocks.entry(row.hash).or_default().push((row.key, row.storage));
+	}
+	Ok(blocks)
+}
+
+/// Get the child storage archived for the blocks in `range`, by block hash, grouped by child trie
+pub(crate) async fn child_storage_in_range(
+	conn: &mut PgConnection,
+	range: RangeInclusive<u64>,
+) -> Result<HashMap<Vec<u8>, Vec<(Vec<u8>, StorageChanges)>>> {
+	let start = i64::try_from(*range.start()).unwrap_or(i64::MAX);
+	let end = i64::try_from(*range.end()).unwrap_or(i64::MAX);
+	#[allow(clippy::toplevel_ref_arg)]
+	let rows = sqlx::query_as!(
+		ChildKeyValue,
+		"SELECT hash, child_key, key, storage FROM child_storage
+        WHERE block_num BETWEEN $1 AND $2
+        ORDER BY hash, child_key",
+		start,
+		end
+	)
+	.fetch_all(conn)
+	.await?;
+	let mut blocks: HashMap<Vec<u8>, Vec<(Vec<u8>, StorageChanges)>> = HashMap::new();
+	for row in rows.into_iter() {
+		let tries = blocks.entry(row.hash).or_default();
+		match tries.last_mut() {
+			Some((child_key, changes)) if *child_key == row.child_key => changes.push((row.key, row.storage)),
+			_ => tries.push((row.child_key, vec![(row.key, row.storage)])),
+		}
+	}
+	Ok(blocks)
+}
+
+/// A block whose archived storage, applied to the state of its parent, does not produce the state root in its header
+pub(crate) struct StorageRootMismatch {
+	pub hash: Vec<u8>,
+	pub block_num: u64,
+	/// state root in the block header
+	pub expected: Vec<u8>,
+	/// state root computed from the archived storage
+	pub computed: Vec<u8>,
+}
+
+/// Record blocks whose archived storage does not produce the state root in their header
+pub(crate) async fn insert_storage_root_mismatches(
+	conn: &mut PgConnection,
+	mismatches: &[StorageRootMismatch],
+) -> Result<()> {
+	let mut hashes = Vec::with_capacity(mismatches.len());
+	let mut nums = Vec::with_capacity(mismatches.len());
+	let mut expected = Vec::with_capacity(mismatches.len());
+	let mut computed = Vec::with_capacity(mismatches.len());
+	for m in mismatches {
+		hashes.push(m.hash.clone());
+		nums.push(i64::try_from(m.block_num).unwrap_or(i64::MAX));
+		expected.push(m.expected.clone());
+		computed.push(m.computed.clone());
+	}
+	#[allow(clippy::toplevel_ref_arg)]
+	sqlx::query!(
+		"INSERT INTO storage_root_mismatches (hash, block_num, expected, computed)
+        SELECT * FROM UNNEST($1::bytea[], $2::bigint[], $3::bytea[], $4::bytea[])
+        ON CONFLICT (hash) DO UPDATE SET computed = EXCLUDED.computed, checked_at = now()",
+		&hashes,
+		&nums,
+		&expected,
+		&computed
+	)
+	.execute(conn)
+	.await?;
+	Ok(())
+}
+
+/// Forget the mismatches recorded for blocks `hashes`, once their storage has been verified
+pub(crate) async fn remove_storage_root_mismatches(conn: &mut PgConnection, hashes: &[Vec<u8>]) -> Result<()> {
+	#[allow(clippy::toplevel_ref_arg)]
+	sqlx::query!("DELETE FROM storage_root_mismatches WHERE hash = ANY($1)", &hashes.to_vec()).execute(conn).await?;
+	Ok(())
+}
+
+/// How storage has been indexed, over every run of the archive on this database
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(crate) struct StorageMode {
+	/// storage was indexed through a storage filter
+	pub filtered: bool,
+	/// storage was only written to sinks other than Postgres
+	pub external: bool,
+}
+
+/// Record the storage mode of a run. Modes accumulate, since storage indexed in an earlier run stays incomplete.
+pub(crate) async fn record_storage_mode(conn: &mut PgConnection, mode: StorageMode) -> Result<()> {
+	#[allow(clippy::toplevel_ref_arg)]
+	sqlx::query!(
+		"INSERT INTO _storage_mode (id, filtered, external) VALUES (true, $1, $2)
+        ON CONFLICT (id) DO UPDATE
+        SET filtered = _storage_mode.filtered OR EXCLUDED.filtered, external = _storage_mode.external OR EXCLUDED.external",
+		mode.filtered,
+		mode.external
+	)
+	.execute(conn)
+	.await?;
+	Ok(())
+}
+
+/// How storage has been indexed. The default mode if nothing has been indexed yet.
+pub(crate) async fn storage_mode(conn: &mut PgConnection) -> Result<StorageMode> {
+	#[allow(clippy::toplevel_ref_arg)]
+	let mode = sqlx::query_as!(StorageMode, "SELECT filtered, external FROM _storage_mode").fetch_optional(conn).await?;
+	Ok(mode.unwrap_or_default())
+}
+
+/// Get the hash of the canonical block `block_num`
+pub async fn canonical_hash(conn: &mut PgConnection, block_num: u64) -> Result<Option<Vec<u8>>> {
+	let block_num = i64::try_from(block_num).unwrap_or(i64::MAX);
+	#[allow(clippy::toplevel_ref_arg)]
+	let hash = sqlx::query_as!(Bytes, "SELECT hash AS data FROM blocks WHERE block_num = $1 AND is_canonical", block_num)
+		.fetch_optional(conn)
+		.await?;
+	Ok(hash.map(|h| h.data))
+}
+
+/// Get the number and hash of every canonical block in `range`
+pub(crate) async fn canonical_hashes(
+	conn: &mut PgConnection,
+	range: RangeInclusive<u64>,
+) -> Result<Vec<(u64, Vec<u8>)>> {
+	let from = i64::try_from(*range.start()).unwrap_or(i64::MAX);
+	let to = i64::try_from(*range.end()).unwrap_or(i64::MAX);
+	#[allow(clippy::toplevel_ref_arg)]
+	let blocks = sqlx::query_as!(
+		NumHash,
+		"SELECT block_num, hash FROM blocks WHERE block_num >= $1 AND block_num <= $2 AND is_canonical",
+		from,
+		to
+	)
+	.fetch_all(conn)
+	.await?;
+	Ok(blocks.into_iter().map(|b| (b.block_num as u64, b.hash)).collect())
+}
+
+/// Get the number and hash of the highest canonical block whose storage has been inserted.
+/// Blocks are indexed ahead of their execution, so this is usually below the highest canonical block.
+pub async fn best_executed_block(conn: &mut PgConnection) -> Result<Option<(u64, Vec<u8>)>> {
+	#[allow(clippy::toplevel_ref_arg)]
+	let block = sqlx::query_as!(
+		NumHash,
+		"SELECT block_num, hash FROM blocks
+        WHERE is_canonical AND EXISTS (SELECT 1 FROM storage WHERE storage.hash = blocks.hash)
+        ORDER BY block_num DESC
+        LIMIT 1",
+	)
+	.fetch_optional(conn)
+	.await?;
+	Ok(block.map(|b| (b.block_num as u64, b.hash)))
+}
+
+/// Whether the storage of block `hash` has been inserted
+pub async fn has_storage(conn: &mut PgConnection, hash: &[u8]) -> Result<bool> {
+	#[allow(clippy::toplevel_ref_arg)]
+	let exists = sqlx::query_as!(DoesExist, "SELECT EXISTS(SELECT 1 FROM storage WHERE hash = $1)", hash)
+		.fetch_one(conn)
+		.await?;
+	Ok(exists.exists.unwrap_or(false))
+}
+
+/// Get the hash of the highest canonical block
+pub async fn best_canonical_hash(conn: &mut PgConnection) -> Result<Option<Vec<u8>>> {
+	#[allow(clippy::toplevel_ref_arg)]
+	let hash =
+		sqlx::query_as!(Bytes, "SELECT hash AS data FROM blocks WHERE is_canonical ORDER BY block_num DESC LIMIT 1")
+			.fetch_optional(conn)
+			.await?;
+	Ok(hash.map(|h| h.data))
+}
+
+/// Get the number of block `hash`, if it is on the canonical chain
+pub async fn canonical_block_num(conn: &mut PgConnection, hash: &[u8]) -> Result<Option<u64>> {
+	#[allow(clippy::toplevel_ref_arg)]
+	let num = sqlx::query_as!(BlockNum, "SELECT block_num FROM blocks WHERE hash = $1 AND is_canonical", hash)
+		.fetch_optional(conn)
+		.await?;
+	Ok(num.map(|n| n.block_num as u64))
+}
+
 /// Check if the runtime version identified by `spec` exists in the relational database
 pub(crate) async fn check_if_meta_exists(spec: u32, conn: &mut PgConnection) -> Result<bool> {
 	let spec = match i32::try_from(spec) {
@@ -175,17 +600,117 @@ pub(crate) async fn has_block<B: BlockT>(hash: B::Hash, conn: &mut PgConnection)
 
 /// Get a list of block_numbers, out of the passed-in blocknumbers, which exist in the relational
 /// database
-pub(crate) async fn has_blocks<B: BlockT>(nums: &[u32], conn: &mut PgConnection) -> Result<Vec<u32>> {
-	let nums: Vec<i32> = nums.iter().filter_map(|n| i32::try_from(*n).ok()).collect();
+pub(crate) async fn has_blocks<B: BlockT>(nums: &[u64], conn: &mut PgConnection) -> Result<Vec<u64>> {
+	let nums: Vec<i64> = nums.iter().filter_map(|n| i64::try_from(*n).ok()).collect();
 	#[allow(clippy::toplevel_ref_arg)]
-	Ok(sqlx::query_as!(BlockNum, "SELECT block_num FROM blocks WHERE block_num = ANY ($1)", &nums,)
+	Ok(sqlx::query_as!(BlockNum, "SELECT DISTINCT block_num FROM blocks WHERE block_num = ANY ($1)", &nums,)
 		.fetch_all(conn)
 		.await?
 		.into_iter()
-		.map(|r| r.block_num as u32)
+		.map(|r| r.block_num as u64)
 		.collect())
 }
 
+/// Set the `is_canonical` flag for every block at the heights in `canonical`,
+/// such that only the block with the given hash at each height is marked canonical.
+/// Returns the number of blocks whose flag changed.
+pub(crate) async fn canonicalize<B: BlockT>(canonical: &[(u64, B::Hash)], conn: &mut PgConnection) -> Result<u64> {
+	let (nums, hashes): (Vec<i64>, Vec<Vec<u8>>) =
+		canonical.iter().filter_map(|(n, h)| Some((i64::try_from(*n).ok()?, h.as_ref().to_vec()))).unzip();
+	#[allow(clippy::toplevel_ref_arg)]
+	Ok(sqlx::query!(
+		"UPDATE blocks SET is_canonical = (blocks.hash = canon.hash)
+        FROM UNNEST($1::bigint[], $2::bytea[]) AS canon(block_num, hash)
+        WHERE blocks.block_num = canon.block_num
+        AND blocks.is_canonical != (blocks.hash = canon.hash)",
+		&nums,
+		&hashes,
+	)
+	.execute(conn)
+	.await?
+	.rows_affected())
+}
+
+/// Set the justification of the blocks in `justifications`, which were finalized after they were indexed.
+/// Returns the number of blocks whose justification changed.
+pub(crate) async fn update_justifications<B: BlockT>(
+	justifications: &[(B::Hash, Vec<u8>)],
+	conn: &mut PgConnection,
+) -> Result<u64> {
+	let (hashes, justifications): (Vec<Vec<u8>>, Vec<Vec<u8>>) =
+		justifications.iter().map(|(h, j)| (h.as_ref().to_vec(), j.clone())).unzip();
+	#[allow(clippy::toplevel_ref_arg)]
+	Ok(sqlx::query!(
+		"UPDATE blocks SET justification = updated.justification
+        FROM UNNEST($1::bytea[], $2::bytea[]) AS updated(hash, justification)
+        WHERE blocks.hash = updated.hash
+        AND blocks.justification IS DISTINCT FROM updated.justification",
+		&hashes,
+		&justifications,
+	)
+	.execute(conn)
+	.await?
+	.rows_affected())
+}
+
+/// Record blocks as executed whose storage has not been inserted into Postgres
+pub(crate) async fn insert_executed<B: BlockT>(conn: &mut PgConnection, blocks: &[(u64, B::Hash)]) -> Result<()> {
+	let (nums, hashes): (Vec<i64>, Vec<Vec<u8>>) =
+		blocks.iter().map(|(n, h)| (i64::try_from(*n).unwrap_or(i64::MAX), h.as_ref().to_vec())).unzip();
+	#[allow(clippy::toplevel_ref_arg)]
+	sqlx::query!(
+		"INSERT INTO executed_blocks (block_num, hash)
+        SELECT * FROM UNNEST($1::bigint[], $2::bytea[])
+        ON CONFLICT DO NOTHING",
+		&nums,
+		&hashes,
+	)
+	.execute(conn)
+	.await?;
+	Ok(())
+}
+
+/// Get the SCALE-encoded metadata of runtime version `spec`
+pub(crate) async fn get_metadata(conn: &mut PgConnection, spec: u32) -> Result<Vec<u8>> {
+	let spec = i32::try_from(spec).map_err(|_| ArchiveError::from("Runtime version cannot be converted to i32"))?;
+	#[allow(clippy::toplevel_ref_arg)]
+	Ok(sqlx::query_as!(Bytes, "SELECT meta AS data FROM metadata WHERE version = $1", spec).fetch_one(conn).await?.data)
+}
+
+/// Get the SCALE-encoded metadata of the runtime that block `hash` was executed with
+pub async fn get_metadata_at(conn: &mut PgConnection, hash: &[u8]) -> Result<Option<Vec<u8>>> {
+	#[allow(clippy::toplevel_ref_arg)]
+	let meta = sqlx::query_as!(
+		Bytes,
+		"SELECT metadata.meta AS data
+        FROM metadata INNER JOIN blocks ON blocks.spec = metadata.version
+        WHERE blocks.hash = $1",
+		hash
+	)
+	.fetch_optional(conn)
+	.await?;
+	Ok(meta.map(|m| m.data))
+}
+
+/// Get the spec version of the runtime that block `hash` was executed with,
+/// and the full runtime version if it was stored with the metadata
+pub async fn get_runtime_version_at(
+	conn: &mut PgConnection,
+	hash: &[u8],
+) -> Result<Option<(u32, Option<serde_json::Value>)>> {
+	#[allow(clippy::toplevel_ref_arg)]
+	let version = sqlx::query_as!(
+		RuntimeVersion,
+		"SELECT metadata.version, metadata.runtime_version
+        FROM metadata INNER JOIN blocks ON blocks.spec = metadata.version
+        WHERE blocks.hash = $1",
+		hash
+	)
+	.fetch_optional(conn)
+	.await?;
+	Ok(version.map(|v| (v.version as u32, v.runtime_version)))
+}
+
 /// Get all the metadata versions stored in the relational database
 pub(crate) async fn get_versions(conn: &mut PgConnection) -> Result<Vec<u32>> {
 	#[allow(clippy::toplevel_ref_arg)]
@@ -217,7 +742,170 @@ pub(crate) async fn get_all_blocks<B: BlockT + DeserializeOwned>(
 	}))
 }
 
+/// Get the value of a storage `key` at canonical block `block_num`: the latest value set at or before the block.
+/// Returns `None` if the key had no value at that block.
+///
+/// The state is read from Postgres alone, so this works even after the node has pruned it.
+/// Only storage that was indexed can be queried: see `StorageFilter`.
+pub async fn storage_at(conn: &mut PgConnection, key: &[u8], block_num: u64) -> Result<Option<Vec<u8>>> {
+	let block_num = i64::try_from(block_num).unwrap_or(i64::MAX);
+	#[allow(clippy::toplevel_ref_arg)]
+	let value = sqlx::query_as!(
+		StorageValue,
+		"SELECT storage.storage
+        FROM storage INNER JOIN blocks ON blocks.hash = storage.hash
+        WHERE storage.key = $1 AND storage.block_num <= $2 AND blocks.is_canonical
+        ORDER BY storage.block_num DESC
+        LIMIT 1",
+		key,
+		block_num
+	)
+	.fetch_optional(conn)
+	.await?;
+	Ok(value.and_then(|v| v.storage))
+}
+
+/// Get every value a storage `key` had in the canonical blocks of `range`, oldest first.
+/// The first change is the value at the start of the range, which may have been set at an earlier block.
+/// Snapshots store values that did not change, so only blocks where the value changed are returned.
+pub async fn storage_history(
+	conn: &mut PgConnection,
+	key: &[u8],
+	range: RangeInclusive<u64>,
+) -> Result<Vec<StorageChange>> {
+	let start = i64::try_from(*range.start()).unwrap_or(i64::MAX);
+	let end = i64::try_from(*range.end()).unwrap_or(i64::MAX);
+	#[allow(clippy::toplevel_ref_arg)]
+	let rows = sqlx::query_as!(
+		StorageRow,
+		"SELECT storage.block_num, storage.hash, storage.storage
+        FROM storage INNER JOIN blocks ON blocks.hash = storage.hash
+        WHERE storage.key = $1 AND storage.block_num BETWEEN $2 AND $3 AND blocks.is_canonical
+        ORDER BY storage.block_num ASC",
+		key,
+		start,
+		end
+	)
+	.fetch_all(&mut *conn)
+	.await?;
+	let mut changes: Vec<StorageChange> = Vec::with_capacity(rows.len() + 1);
+	// the value at the start of the range may have been set before it
+	if rows.first().map(|r| r.block_num != start).unwrap_or(true) {
+		if let Some(hash) = canonical_hash(conn, *range.start()).await? {
+			let value = storage_at(conn, key, *range.start()).await?;
+			changes.push(StorageChange { block_num: *range.start(), hash, value });
+		}
+	}
+	for row in rows.into_iter() {
+		if changes.last().map(|c| c.value == row.storage).unwrap_or(false) {
+			continue;
+		}
+		changes.push(StorageChange { block_num: row.block_num as u64, hash: row.hash, value: row.storage });
+	}
+	Ok(changes)
+}
+
+/// Get the storage keys starting with `prefix` that had a value at canonical block `block_num`.
+pub async fn keys_with_prefix_at(conn: &mut PgConnection, prefix: &[u8], block_num: u64) -> Result<Vec<Vec<u8>>> {
+	let end = prefix_end(prefix);
+	let block_num = i64::try_from(block_num).unwrap_or(i64::MAX);
+	#[allow(clippy::toplevel_ref_arg)]
+	let rows = sqlx::query_as!(
+		KeyValue,
+		"SELECT DISTINCT ON (storage.key) storage.key, storage.storage
+        FROM storage INNER JOIN blocks ON blocks.hash = storage.hash
+        WHERE storage.key >= $1 AND ($2::bytea IS NULL OR storage.key < $2)
+        AND storage.block_num <= $3 AND blocks.is_canonical
+        ORDER BY storage.key, storage.block_num DESC",
+		prefix,
+		end,
+		block_num
+	)
+	.fetch_all(conn)
+	.await?;
+	// the latest change of a key may have removed it
+	Ok(rows.into_iter().filter(|r| r.storage.is_some()).map(|r| r.key).collect())
+}
+
+/// The smallest key greater than every key starting with `prefix`,
+/// or `None` if there is no such key because `prefix` is empty or all `0xff`.
+fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+	let last = prefix.iter().rposition(|b| *b != 0xff)?;
+	let mut end = prefix[..=last].to_vec();
+	end[last] += 1;
+	Some(end)
+}
+
 #[cfg(test)]
 mod tests {
 	//! Must be connected to a postgres database
+
+	use super::*;
+	use polkadot_service::Block;
+	use sp_core::H256;
+	use sqlx::Connection as _;
+
+	async fn count(conn: &mut PgConnection, query: &str) -> i64 {
+		sqlx::query_as::<_, (i64,)>(query).fetch_one(&mut *conn).await.unwrap().0
+	}
+
+	/// Insert block 1 with hash `hash`, and an extrinsic, an event and a digest log of it
+	async fn insert_block(conn: &mut PgConnection, hash: u8, is_canonical: bool) {
+		sqlx::query(
+			"INSERT INTO blocks (parent_hash, hash, block_num, state_root, extrinsics_root, digest, ext, spec, is_canonical)
+            VALUES ($1, $2, 1, $1, $1, $1, $1, 0, $3)",
+		)
+		.bind(vec![0u8; 32])
+		.bind(vec![hash; 32])
+		.bind(is_canonical)
+		.execute(&mut *conn)
+		.await
+		.unwrap();
+		for query in &[
+			"INSERT INTO extrinsics (hash, block_num, index, pallet, call, args) VALUES ($1, 1, 0, 'Timestamp', 'set', '{}')",
+			"INSERT INTO events (hash, block_num, index, extrinsic_index, phase, pallet, event, fields)
+            VALUES ($1, 1, 0, 0, 'ApplyExtrinsic', 'System', 'ExtrinsicSuccess', '[]')",
+			"INSERT INTO digest_logs (hash, block_num, index, kind) VALUES ($1, 1, 0, 'Seal')",
+		] {
+			sqlx::query(query).bind(vec![hash; 32]).execute(&mut *conn).await.unwrap();
+		}
+	}
+
+	#[test]
+	fn should_hide_decoded_data_of_reorged_blocks() {
+		crate::initialize();
+		let _guard = crate::TestGuard::lock();
+		smol::block_on(async {
+			let mut conn = PgConnection::connect(&crate::DATABASE_URL).await.unwrap();
+			insert_block(&mut conn, 1, true).await;
+			insert_block(&mut conn, 2, false).await;
+			for view in &["canonical_extrinsics", "canonical_events", "canonical_digest_logs"] {
+				let query = format!("SELECT COUNT(*) FROM {} WHERE hash = '\\x{}'", view, hex::encode([1u8; 32]));
+				assert_eq!(count(&mut conn, &query).await, 1, "{} shows the canonical block", view);
+			}
+
+			// a reorg replaces block 1
+			let changed = canonicalize::<Block>(&[(1, H256::repeat_byte(2))], &mut conn).await.unwrap();
+			assert_eq!(changed, 2);
+			assert_eq!(canonical_hash(&mut conn, 1).await.unwrap(), Some(vec![2; 32]));
+			for view in &["canonical_extrinsics", "canonical_events", "canonical_digest_logs"] {
+				let query = format!("SELECT COUNT(*) FROM {} WHERE hash = '\\x{}'", view, hex::encode([2u8; 32]));
+				assert_eq!(count(&mut conn, &query).await, 1, "{} shows the replacing block", view);
+				let all = format!("SELECT COUNT(*) FROM {}", view);
+				assert_eq!(count(&mut conn, &all).await, 1, "{} hides the replaced block", view);
+			}
+			// the replaced block and its data are kept
+			assert_eq!(count(&mut conn, "SELECT COUNT(*) FROM extrinsics").await, 2);
+			// canonicalizing again changes nothing
+			assert_eq!(canonicalize::<Block>(&[(1, H256::repeat_byte(2))], &mut conn).await.unwrap(), 0);
+		});
+	}
+
+	#[test]
+	fn should_bound_prefix() {
+		assert_eq!(prefix_end(&[0x01, 0x02]), Some(vec![0x01, 0x03]));
+		assert_eq!(prefix_end(&[0x01, 0xff, 0xff]), Some(vec![0x02]));
+		assert_eq!(prefix_end(&[0xff, 0xff]), None);
+		assert_eq!(prefix_end(&[]), None);
+	}
 }
@@ -0,0 +1,364 @@
+// Copyright 2017-2019 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Decodes extrinsics and events with the metadata of the runtime version they were created with.
+//! Metadata is read from the `metadata` table, which is always populated before a block is inserted.
+//! Wraps `desub`, so that the rest of substrate-archive does not depend on its types.
+
+mod digest;
+
+use desub_core::decoder::{Chain, Decoder as DesubDecoder, Metadata};
+use desub_extras::TypeResolver;
+use hashbrown::HashSet;
+use parking_lot::{Mutex, RwLock};
+use serde_json::{Map, Value};
+use sqlx::PgPool;
+
+use sp_runtime::traits::Block as BlockT;
+
+use substrate_archive_common::{
+	models::{EventModel, ExtrinsicModel},
+	types::Storage,
+	ArchiveError, Result,
+};
+
+use crate::database::queries;
+
+pub use self::digest::{digest_logs, Authorities, AURA_AUTHORITIES, BABE_AUTHORITIES, SESSION_VALIDATORS};
+
+/// Storage key of `System::Events`
+/// (`twox_128("System") ++ twox_128("Events")`)
+const SYSTEM_EVENTS: &str = "26aa394eea5630e07c48ae0c9558cef780d41e5e16056765bc8461851072c9d7";
+
+/// Decodes extrinsics and events of a block.
+pub struct Decoder {
+	// a `Mutex` rather than a `RwLock`, so that the desub decoder only needs to be `Send`
+	inner: Mutex<DesubDecoder>,
+	/// runtime versions whose metadata has been registered with the decoder
+	versions: RwLock<HashSet<u32>>,
+	pool: PgPool,
+}
+
+/// Phase of block execution an event was emitted in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Phase {
+	ApplyExtrinsic(u32),
+	Finalization,
+	Initialization,
+}
+
+/// A single event, decoded with the metadata of the runtime version it was emitted in.
+#[derive(Debug, Clone)]
+struct DecodedEvent {
+	phase: Phase,
+	pallet: String,
+	event: String,
+	fields: Value,
+}
+
+impl Decoder {
+	/// Create a new decoder for the chain named `chain`.
+	/// The name is the `spec_name` of the runtime.
+	pub fn new(chain: &str, pool: PgPool) -> Self {
+		let chain = match chain.to_ascii_lowercase().as_str() {
+			"polkadot" => Chain::Polkadot,
+			"kusama" => Chain::Kusama,
+			"westend" => Chain::Westend,
+			"rococo" => Chain::Rococo,
+			c => Chain::Custom(c.to_string()),
+		};
+		let inner = DesubDecoder::new(TypeResolver::default(), chain);
+		Self { inner: Mutex::new(inner), versions: RwLock::new(HashSet::new()), pool }
+	}
+
+	/// Register the metadata for runtime version `spec` with the decoder,
+	/// if it is not registered already.
+	fn register(&self, spec: u32) -> Result<()> {
+		if self.versions.read().contains(&spec) {
+			return Ok(());
+		}
+		let meta = smol::block_on(async {
+			let mut conn = self.pool.acquire().await?;
+			queries::get_metadata(&mut conn, spec).await
+		})?;
+		self.register_metadata(spec, meta.as_slice());
+		Ok(())
+	}
+
+	/// Register the SCALE-encoded metadata `meta` of runtime version `spec` with the decoder
+	fn register_metadata(&self, spec: u32, meta: &[u8]) {
+		self.inner.lock().register_version(spec, &Metadata::new(meta));
+		self.versions.write().insert(spec);
+	}
+
+	/// Decode the extrinsics and events of a block.
+	/// `extrinsics` is the SCALE-encoded list of extrinsics in the block,
+	/// and `storage` the storage changes from executing it, which contain the events of the block.
+	///
+	/// Extrinsics and events are decoded independently. If the extrinsics cannot be decoded, the events are
+	/// still returned, without a link to the extrinsic that emitted them. If the events cannot be decoded,
+	/// the extrinsics are still returned, without their outcome.
+	/// Fails only if the metadata of the runtime version is not available.
+	pub fn decode<B: BlockT>(
+		&self,
+		spec: u32,
+		extrinsics: &[u8],
+		storage: &Storage<B>,
+	) -> Result<(Vec<ExtrinsicModel<B>>, Vec<EventModel<B>>)> {
+		self.register(spec)?;
+		let events = self.events(spec, storage).unwrap_or_else(|e| {
+			log::warn!("Could not decode events of block {}: {}", storage.hash(), e);
+			Vec::new()
+		});
+		let extrinsics = match self.extrinsics(spec, extrinsics, storage, &events) {
+			Ok(extrinsics) => Some(extrinsics),
+			Err(e) => {
+				log::warn!("Could not decode extrinsics of block {}: {}", storage.hash(), e);
+				None
+			}
+		};
+		let events = events
+			.into_iter()
+			.map(|(index, e)| {
+				let (phase, extrinsic_index) = match e.phase {
+					// events may only reference extrinsics that are stored
+					Phase::ApplyExtrinsic(i) => ("ApplyExtrinsic", Some(i).filter(|_| extrinsics.is_some())),
+					Phase::Finalization => ("Finalization", None),
+					Phase::Initialization => ("Initialization", None),
+				};
+				EventModel {
+					hash: *storage.hash(),
+					block_num: storage.block_num(),
+					index,
+					extrinsic_index,
+					phase: phase.to_string(),
+					pallet: e.pallet,
+					event: e.event,
+					fields: e.fields,
+				}
+			})
+			.collect();
+		Ok((extrinsics.unwrap_or_default(), events))
+	}
+
+	/// Decode the extrinsics of a block.
+	/// `events` are used to find out whether each extrinsic succeeded.
+	fn extrinsics<B: BlockT>(
+		&self,
+		spec: u32,
+		extrinsics: &[u8],
+		storage: &Storage<B>,
+		events: &[(u32, DecodedEvent)],
+	) -> Result<Vec<ExtrinsicModel<B>>> {
+		let decoded = self.inner.lock().decode_extrinsics(spec, extrinsics).map_err(|e| format!("{:?}", e))?;
+
+		decoded
+			.into_iter()
+			.enumerate()
+			.map(|(index, ext)| {
+				let index = index as u32;
+				let signature = ext.signature().map(serde_json::to_value).transpose()?;
+				let extra = signature.as_ref().and_then(|s| s.get("extra"));
+				let args = ext
+					.args()
+					.iter()
+					.map(|(name, arg)| Ok((name.clone(), serde_json::to_value(arg)?)))
+					.collect::<Result<Map<String, Value>>>()?;
+
+				Ok(ExtrinsicModel {
+					hash: *storage.hash(),
+					block_num: storage.block_num(),
+					index,
+					signer: signature.as_ref().and_then(|s| s.get("address")).cloned(),
+					nonce: extra.and_then(|e| find(e, "CheckNonce")).and_then(as_u128).map(|n| n as u64),
+					tip: extra.and_then(|e| find(e, "ChargeTransactionPayment")).and_then(as_u128),
+					pallet: ext.ext_module().to_string(),
+					call: ext.ext_call().to_string(),
+					args: Value::Object(args),
+					success: outcome(events, index),
+				})
+			})
+			.collect()
+	}
+
+	/// Decode the `System::Events` of a block from its storage changes.
+	/// Each event is returned with its position in `System::Events`. Records that cannot be decoded are skipped.
+	fn events<B: BlockT>(&self, spec: u32, storage: &Storage<B>) -> Result<Vec<(u32, DecodedEvent)>> {
+		let key = hex::decode(SYSTEM_EVENTS).expect("Constant is valid hex; qed");
+		let value = match storage.changes().iter().find(|(k, _)| k.0 == key) {
+			Some((_, Some(value))) => value.0.clone(),
+			_ => return Ok(Vec::new()),
+		};
+		let decoded = self.inner.lock().decode_storage(spec, (key, Some(value))).map_err(|e| format!("{:?}", e))?;
+		let decoded = serde_json::to_value(decoded)?;
+		let records = find_records(&decoded).ok_or_else(|| ArchiveError::from("Events have an unexpected layout"))?;
+		Ok(records.iter().enumerate().filter_map(|(i, r)| decode_event(r).map(|e| (i as u32, e))).collect())
+	}
+}
+
+/// Whether the extrinsic at `index` succeeded, according to the
+/// `System::ExtrinsicSuccess` and `System::ExtrinsicFailed` events.
+fn outcome(events: &[(u32, DecodedEvent)], index: u32) -> Option<bool> {
+	events.iter().filter(|(_, e)| e.phase == Phase::ApplyExtrinsic(index) && e.pallet == "System").find_map(|(_, e)| {
+		match e.event.as_str() {
+			"ExtrinsicSuccess" => Some(true),
+			"ExtrinsicFailed" => Some(false),
+			_ => None,
+		}
+	})
+}
+
+/// Find the list of event records in decoded `System::Events` storage
+fn find_records(value: &Value) -> Option<&Vec<Value>> {
+	match value {
+		Value::Array(records) if records.iter().all(|r| r.get("phase").is_some()) => Some(records),
+		Value::Array(values) => values.iter().find_map(find_records),
+		Value::Object(map) => map.values().find_map(find_records),
+		_ => None,
+	}
+}
+
+fn decode_event(record: &Value) -> Option<DecodedEvent> {
+	let phase = match record.get("phase")? {
+		Value::String(s) if s == "Finalization" => Phase::Finalization,
+		Value::String(s) if s == "Initialization" => Phase::Initialization,
+		p => Phase::ApplyExtrinsic(as_u128(p.get("ApplyExtrinsic")?)? as u32),
+	};
+	let event = record.get("event")?;
+	let pallet = event.get("module")?.as_str()?.to_string();
+	let name = event.get("variant").or_else(|| event.get("name"))?.as_str()?.to_string();
+	let fields = event.get("args").cloned().unwrap_or(Value::Null);
+	Some(DecodedEvent { phase, pallet, event: name, fields })
+}
+
+/// Find the value of a named field anywhere in `value`
+fn find<'a>(value: &'a Value, name: &str) -> Option<&'a Value> {
+	match value {
+		Value::Object(map) => map.get(name).or_else(|| map.values().find_map(|v| find(v, name))),
+		Value::Array(values) => values.iter().find_map(|v| find(v, name)),
+		_ => None,
+	}
+}
+
+/// Numbers may be wrapped in compact encodings or single-field structs
+fn as_u128(value: &Value) -> Option<u128> {
+	match value {
+		Value::Number(n) => n.as_u64().map(Into::into),
+		Value::String(s) => s.parse().ok(),
+		Value::Array(values) if values.len() == 1 => as_u128(&values[0]),
+		Value::Object(map) if map.len() == 1 => map.values().next().and_then(as_u128),
+		_ => None,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use serde_json::json;
+
+	#[test]
+	fn should_find_extrinsic_outcome() {
+		let records = json!([
+			{ "phase": { "ApplyExtrinsic": 0 }, "event": { "module": "System", "variant": "ExtrinsicSuccess", "args": [] }, "topics": [] },
+			{ "phase": { "ApplyExtrinsic": 1 }, "event": { "module": "Balances", "variant": "Transfer", "args": [] }, "topics": [] },
+			{ "phase": { "ApplyExtrinsic": 1 }, "event": { "module": "System", "variant": "ExtrinsicFailed", "args": [] }, "topics": [] },
+			{ "phase": "Finalization", "event": { "module": "Treasury", "variant": "Rollover", "args": [] }, "topics": [] },
+		]);
+		let events: Vec<(u32, DecodedEvent)> = find_records(&json!({ "value": records }))
+			.unwrap()
+			.iter()
+			.enumerate()
+			.filter_map(|(i, r)| decode_event(r).map(|e| (i as u32, e)))
+			.collect();
+		assert_eq!(events.len(), 4);
+		assert_eq!(events[3].1.phase, Phase::Finalization);
+		assert_eq!(outcome(&events, 0), Some(true));
+		assert_eq!(outcome(&events, 1), Some(false));
+		assert_eq!(outcome(&events, 2), None);
+	}
+
+	#[test]
+	fn should_find_signed_extra() {
+		let extra =
+			json!([{ "CheckNonce": { "compact": 42 } }, { "ChargeTransactionPayment": "1000000000000000000000" }]);
+		assert_eq!(find(&extra, "CheckNonce").and_then(as_u128), Some(42));
+		assert_eq!(find(&extra, "ChargeTransactionPayment").and_then(as_u128), Some(1_000_000_000_000_000_000_000));
+		assert_eq!(find(&extra, "CheckWeight"), None);
+	}
+
+	/// A decoder for the Kusama runtime the archive is built with, its runtime version,
+	/// and the encoded extrinsics of a block with a single `Timestamp::set`.
+	fn kusama_decoder() -> (Decoder, u32, Vec<u8>) {
+		use codec::Encode;
+		use polkadot_service::kusama_runtime::{self as runtime, Runtime};
+
+		let pool = sqlx::postgres::PgPoolOptions::new().connect_lazy("postgres://localhost/archive").unwrap();
+		let decoder = Decoder::new("kusama", pool);
+		let spec = runtime::VERSION.spec_version;
+		let meta: Vec<u8> = Runtime::metadata().into();
+		decoder.register_metadata(spec, meta.as_slice());
+		let call = runtime::Call::Timestamp(runtime::TimestampCall::set(1_600_000_000_000));
+		let extrinsics = vec![runtime::UncheckedExtrinsic::new_unsigned(call)].encode();
+		(decoder, spec, extrinsics)
+	}
+
+	fn events_storage(events: Vec<u8>) -> Storage<polkadot_service::Block> {
+		use sp_core::{
+			storage::{StorageData, StorageKey},
+			H256,
+		};
+		let key = StorageKey(hex::decode(SYSTEM_EVENTS).unwrap());
+		Storage::new(H256::repeat_byte(1), 1, false, vec![(key, Some(StorageData(events)))], vec![])
+	}
+
+	#[test]
+	fn should_decode_runtime_encoded_extrinsics_and_events() {
+		use codec::Encode;
+		use polkadot_service::kusama_runtime::{self as runtime, Runtime};
+		use sp_core::H256;
+
+		let (decoder, spec, extrinsics) = kusama_decoder();
+		let events = vec![frame_system::EventRecord::<runtime::Event, H256> {
+			phase: frame_system::Phase::ApplyExtrinsic(0),
+			event: runtime::Event::frame_system(frame_system::Event::<Runtime>::ExtrinsicSuccess(Default::default())),
+			topics: Vec::new(),
+		}]
+		.encode();
+		let storage = events_storage(events);
+
+		let (extrinsics, events) = decoder.decode(spec, extrinsics.as_slice(), &storage).unwrap();
+		assert_eq!(extrinsics.len(), 1);
+		assert_eq!((extrinsics[0].pallet.as_str(), extrinsics[0].call.as_str()), ("Timestamp", "set"));
+		assert_eq!(extrinsics[0].signer, None);
+		assert_eq!(extrinsics[0].success, Some(true));
+		assert_eq!(events.len(), 1);
+		assert_eq!((events[0].pallet.as_str(), events[0].event.as_str()), ("System", "ExtrinsicSuccess"));
+		assert_eq!(events[0].extrinsic_index, Some(0));
+	}
+
+	#[test]
+	fn should_keep_extrinsics_if_events_fail_to_decode() {
+		let (decoder, spec, extrinsics) = kusama_decoder();
+		// one event record, but no bytes for it
+		let storage = events_storage(vec![4]);
+
+		let (extrinsics, events) = decoder.decode(spec, extrinsics.as_slice(), &storage).unwrap();
+		assert!(events.is_empty());
+		assert_eq!(extrinsics.len(), 1);
+		assert_eq!((extrinsics[0].pallet.as_str(), extrinsics[0].call.as_str()), ("Timestamp", "set"));
+		assert_eq!(extrinsics[0].success, None);
+	}
+}
@@ -0,0 +1,249 @@
+// Copyright 2017-2019 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Decodes the digest of a block header into its items.
+//! The slot of the block and the index of its author are read from BABE and Aura `PreRuntime` items.
+//! The index is looked up in the BABE or Aura authority set of the block for the authority id of the author,
+//! and in the session validators of the block for the account of the author.
+//! Both are in the order of the validators of the current session.
+//! Items are decoded from their SCALE encoding rather than as `DigestItem`s,
+//! so that item kinds which are newer than the substrate version of the archive are recognized too.
+
+use codec::{Compact, Decode, Encode, Input};
+
+use sp_runtime::traits::{Block as BlockT, Header as _, SaturatedConversion};
+
+use substrate_archive_common::{models::DigestLogModel, Result};
+
+/// Storage key of `Babe::Authorities`
+/// (`twox_128("Babe") ++ twox_128("Authorities")`)
+pub const BABE_AUTHORITIES: [u8; 32] = [
+	0x1c, 0xb6, 0xf3, 0x6e, 0x02, 0x7a, 0xbb, 0x20, 0x91, 0xcf, 0xb5, 0x11, 0x0a, 0xb5, 0x08, 0x7f, 0x5e, 0x06, 0x21,
+	0xc4, 0x86, 0x9a, 0xa6, 0x0c, 0x02, 0xbe, 0x9a, 0xdc, 0xc9, 0x8a, 0x0d, 0x1d,
+];
+/// Storage key of `Aura::Authorities`
+/// (`twox_128("Aura") ++ twox_128("Authorities")`)
+pub const AURA_AUTHORITIES: [u8; 32] = [
+	0x57, 0xf8, 0xdc, 0x2f, 0x5a, 0xb0, 0x94, 0x67, 0x89, 0x6f, 0x47, 0x30, 0x0f, 0x04, 0x24, 0x38, 0x5e, 0x06, 0x21,
+	0xc4, 0x86, 0x9a, 0xa6, 0x0c, 0x02, 0xbe, 0x9a, 0xdc, 0xc9, 0x8a, 0x0d, 0x1d,
+];
+/// Storage key of `Session::Validators`
+/// (`twox_128("Session") ++ twox_128("Validators")`)
+pub const SESSION_VALIDATORS: [u8; 32] = [
+	0xce, 0xc5, 0x07, 0x0d, 0x60, 0x9d, 0xd3, 0x49, 0x7f, 0x72, 0xbd, 0xe0, 0x7f, 0xc9, 0x6b, 0xa0, 0x88, 0xdc, 0xde,
+	0x93, 0x4c, 0x65, 0x82, 0x27, 0xee, 0x1d, 0xfa, 0xfc, 0xd6, 0xe1, 0x69, 0x03,
+];
+
+/// `ConsensusEngineId` of BABE
+const BABE_ENGINE_ID: [u8; 4] = *b"BABE";
+/// `ConsensusEngineId` of Aura
+const AURA_ENGINE_ID: [u8; 4] = *b"aura";
+
+/// A digest item, with its payload left encoded
+#[derive(Debug, Clone, PartialEq)]
+struct Item {
+	kind: &'static str,
+	engine: Option<[u8; 4]>,
+	data: Option<Vec<u8>>,
+}
+
+/// The SCALE-encoded authority sets of a block, read from its state.
+/// `None` if the chain does not have the pallet.
+#[derive(Debug, Default)]
+pub struct Authorities {
+	/// `Babe::Authorities`
+	pub babe: Option<Vec<u8>>,
+	/// `Aura::Authorities`
+	pub aura: Option<Vec<u8>>,
+	/// `Session::Validators`
+	pub validators: Option<Vec<u8>>,
+}
+
+/// Decode the digest of `header` into one digest log per item.
+pub fn digest_logs<B: BlockT>(header: &B::Header, authorities: &Authorities) -> Result<Vec<DigestLogModel<B>>> {
+	let hash = header.hash();
+	let block_num: u64 = (*header.number()).saturated_into();
+	let digest = header.digest().encode();
+	let items = decode_items(digest.as_slice(), hash.as_ref().len())?;
+	let babe_authorities = authorities.babe.as_deref().and_then(|a| decode_babe_authorities(a).ok());
+	let aura_authorities = authorities.aura.as_deref().and_then(|a| decode_aura_authorities(a).ok());
+	let validators = authorities.validators.as_deref().and_then(|v| decode_validators(v).ok());
+
+	Ok(items
+		.into_iter()
+		.enumerate()
+		.map(|(index, item)| {
+			let authorities = match item.engine {
+				Some(BABE_ENGINE_ID) => babe_authorities.as_ref(),
+				Some(AURA_ENGINE_ID) => aura_authorities.as_ref(),
+				_ => None,
+			};
+			let (slot, authority_index) = match (item.kind, item.engine, item.data.as_deref()) {
+				("PreRuntime", Some(engine), Some(data)) => pre_runtime_slot(engine, data, authorities.map(Vec::len))
+					.map(|(slot, index)| (Some(slot), index))
+					.unwrap_or((None, None)),
+				_ => (None, None),
+			};
+			let authority = authority_index.and_then(|i| authorities?.get(i as usize)).map(|a| a.to_vec());
+			let author = authority_index.and_then(|i| validators.as_ref()?.get(i as usize)).map(|v| v.to_vec());
+			DigestLogModel {
+				hash,
+				block_num,
+				index: index as u32,
+				kind: item.kind.to_string(),
+				engine: item.engine.map(|e| String::from_utf8_lossy(&e).into_owned()),
+				data: item.data,
+				slot,
+				authority_index,
+				authority,
+				author,
+			}
+		})
+		.collect())
+}
+
+/// Decode a SCALE-encoded digest into its items.
+/// `hash_len` is the length of a block hash, which is the payload of `ChangesTrieRoot` items.
+fn decode_items(mut digest: &[u8], hash_len: usize) -> std::result::Result<Vec<Item>, codec::Error> {
+	let input = &mut digest;
+	let len = <Compact<u32>>::decode(input)?.0;
+	let mut items = Vec::new();
+	for _ in 0..len {
+		let item = match input.read_byte()? {
+			0 => Item { kind: "Other", engine: None, data: Some(Vec::<u8>::decode(input)?) },
+			2 => {
+				let mut root = vec![0; hash_len];
+				input.read(&mut root)?;
+				Item { kind: "ChangesTrieRoot", engine: None, data: Some(root) }
+			}
+			4 => {
+				Item { kind: "Consensus", engine: Some(Decode::decode(input)?), data: Some(Vec::<u8>::decode(input)?) }
+			}
+			5 => Item { kind: "Seal", engine: Some(Decode::decode(input)?), data: Some(Vec::<u8>::decode(input)?) },
+			6 => {
+				Item { kind: "PreRuntime", engine: Some(Decode::decode(input)?), data: Some(Vec::<u8>::decode(input)?) }
+			}
+			7 => {
+				// `ChangesTrieSignal::NewConfiguration(Option<ChangesTrieConfiguration>)`
+				let signal = <(u8, Option<(u32, u32)>)>::decode(input)?;
+				Item { kind: "ChangesTrieSignal", engine: None, data: Some(signal.encode()) }
+			}
+			8 => Item { kind: "RuntimeEnvironmentUpdated", engine: None, data: None },
+			_ => return Err("Unknown digest item".into()),
+		};
+		items.push(item);
+	}
+	Ok(items)
+}
+
+/// The slot and the authority index of the block author, from the payload of a BABE or Aura `PreRuntime` item.
+/// Aura authorities take turns by slot, so the index of an Aura author is only known with the number of authorities.
+fn pre_runtime_slot(engine: [u8; 4], mut data: &[u8], authorities: Option<usize>) -> Option<(u64, Option<u32>)> {
+	match engine {
+		BABE_ENGINE_ID => {
+			// every kind of BABE pre-digest starts with the authority index and the slot
+			let (_kind, authority_index, slot) = <(u8, u32, u64)>::decode(&mut data).ok()?;
+			Some((slot, Some(authority_index)))
+		}
+		AURA_ENGINE_ID => {
+			let slot = u64::decode(&mut data).ok()?;
+			let authority_index = authorities.filter(|n| *n > 0).map(|n| (slot % n as u64) as u32);
+			Some((slot, authority_index))
+		}
+		_ => None,
+	}
+}
+
+/// Decode the public keys of the SCALE-encoded `Babe::Authorities`, a list of authority ids and their weights.
+fn decode_babe_authorities(mut encoded: &[u8]) -> std::result::Result<Vec<[u8; 32]>, codec::Error> {
+	let authorities = <Vec<([u8; 32], u64)>>::decode(&mut encoded)?;
+	Ok(authorities.into_iter().map(|(id, _weight)| id).collect())
+}
+
+/// Decode the public keys of the SCALE-encoded `Aura::Authorities`, a list of authority ids.
+fn decode_aura_authorities(mut encoded: &[u8]) -> std::result::Result<Vec<[u8; 32]>, codec::Error> {
+	<Vec<[u8; 32]>>::decode(&mut encoded)
+}
+
+/// Decode the accounts of the SCALE-encoded `Session::Validators`, a list of account ids.
+fn decode_validators(mut encoded: &[u8]) -> std::result::Result<Vec<[u8; 32]>, codec::Error> {
+	<Vec<[u8; 32]>>::decode(&mut encoded)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn should_decode_digest_items() {
+		let babe = (2u8, 3u32, 42u64).encode();
+		let mut digest = Compact(3u32).encode();
+		digest.push(6);
+		BABE_ENGINE_ID.encode_to(&mut digest);
+		babe.encode_to(&mut digest);
+		digest.push(8);
+		digest.push(5);
+		BABE_ENGINE_ID.encode_to(&mut digest);
+		vec![7u8; 64].encode_to(&mut digest);
+
+		let items = decode_items(digest.as_slice(), 32).unwrap();
+		assert_eq!(
+			items.iter().map(|i| i.kind).collect::<Vec<_>>(),
+			vec!["PreRuntime", "RuntimeEnvironmentUpdated", "Seal"]
+		);
+		assert_eq!(items[0].data.as_deref(), Some(babe.as_slice()));
+		assert_eq!(pre_runtime_slot(BABE_ENGINE_ID, babe.as_slice(), None), Some((42, Some(3))));
+		assert_eq!(pre_runtime_slot(AURA_ENGINE_ID, 42u64.encode().as_slice(), Some(4)), Some((42, Some(2))));
+	}
+
+	#[test]
+	fn should_resolve_authority_and_author() {
+		use polkadot_service::Block;
+		use sp_runtime::{
+			generic::{Digest, DigestItem},
+			traits::Header as HeaderT,
+		};
+
+		let pre_digest = (2u8, 1u32, 42u64).encode();
+		let digest = Digest { logs: vec![DigestItem::PreRuntime(BABE_ENGINE_ID, pre_digest)] };
+		let header =
+			<Block as BlockT>::Header::new(1, Default::default(), Default::default(), Default::default(), digest);
+		let authorities = Authorities {
+			babe: Some(vec![([1u8; 32], 1u64), ([2u8; 32], 1u64)].encode()),
+			aura: None,
+			validators: Some(vec![[3u8; 32], [4u8; 32]].encode()),
+		};
+		let logs = digest_logs::<Block>(&header, &authorities).unwrap();
+		assert_eq!(logs.len(), 1);
+		assert_eq!((logs[0].slot, logs[0].authority_index), (Some(42), Some(1)));
+		assert_eq!(logs[0].authority, Some(vec![2u8; 32]));
+		assert_eq!(logs[0].author, Some(vec![4u8; 32]));
+
+		// without a session pallet only the authority id is known
+		let authorities = Authorities { validators: None, ..authorities };
+		let logs = digest_logs::<Block>(&header, &authorities).unwrap();
+		assert_eq!(logs[0].authority, Some(vec![2u8; 32]));
+		assert_eq!(logs[0].author, None);
+	}
+
+	#[test]
+	fn should_decode_authorities() {
+		let babe = vec![([1u8; 32], 1u64), ([2u8; 32], 1u64)].encode();
+		assert_eq!(decode_babe_authorities(babe.as_slice()).unwrap(), vec![[1u8; 32], [2u8; 32]]);
+		let aura = vec![[1u8; 32], [2u8; 32]].encode();
+		assert_eq!(decode_aura_authorities(aura.as_slice()).unwrap(), vec![[1u8; 32], [2u8; 32]]);
+	}
+}
@@ -0,0 +1,134 @@
+// Copyright 2017-2019 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Filter the storage changes that are indexed by key prefix.
+//! Prefixes are given either as hex (`0x26aa394eea5630e07c48ae0c9558cef7`) or as the name of a pallet
+//! (`System`) or a storage item (`System::Account`), which are hashed with twox128 like the runtime does.
+
+use sp_core::hashing::twox_128;
+use sp_runtime::traits::Block as BlockT;
+
+use substrate_archive_common::{types::Storage, ArchiveError, Result};
+
+/// Prefixes of the storage keys to index.
+/// A key is indexed if it matches a prefix of the allow list (or the allow list is empty),
+/// and it does not match a prefix of the deny list.
+///
+/// `System::Number` is always indexed. It changes in every block, so every executed block has
+/// storage in the database, which is how the archive knows which blocks have been executed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StorageFilter {
+	allow: Vec<Vec<u8>>,
+	deny: Vec<Vec<u8>>,
+}
+
+impl StorageFilter {
+	/// Create a filter from allow and deny lists of hex prefixes, pallet names or `Pallet::Item` names.
+	pub fn new<S: AsRef<str>>(allow: &[S], deny: &[S]) -> Result<Self> {
+		let parse = |list: &[S]| list.iter().map(|s| parse_prefix(s.as_ref())).collect::<Result<Vec<_>>>();
+		Ok(Self { allow: parse(allow)?, deny: parse(deny)? })
+	}
+
+	/// Whether this filter lets every key through
+	pub fn is_empty(&self) -> bool {
+		self.allow.is_empty() && self.deny.is_empty()
+	}
+
+	/// Whether the change of `key` should be indexed
+	pub fn is_allowed(&self, key: &[u8]) -> bool {
+		if key == system_number().as_slice() {
+			return true;
+		}
+		let allowed = self.allow.is_empty() || self.allow.iter().any(|p| key.starts_with(p));
+		allowed && !self.deny.iter().any(|p| key.starts_with(p))
+	}
+
+	/// Remove the changes that should not be indexed from `storage`.
+	/// Changes of child tries are filtered by the storage key of the child trie.
+	pub fn apply<B: BlockT>(&self, storage: &mut Storage<B>) {
+		if self.is_empty() {
+			return;
+		}
+		storage.changes.retain(|(key, _)| self.is_allowed(key.0.as_slice()));
+		storage.child_changes.retain(|(child, _)| self.is_allowed(child.0.as_slice()));
+	}
+}
+
+/// Parse a hex prefix, a pallet name or a `Pallet::Item` name into a key prefix
+fn parse_prefix(s: &str) -> Result<Vec<u8>> {
+	let s = s.trim();
+	if let Some(hex) = s.strip_prefix("0x") {
+		return hex::decode(hex).map_err(|e| ArchiveError::from(format!("invalid storage prefix `{}`: {}", s, e)));
+	}
+	let mut parts = s.split("::");
+	match (parts.next(), parts.next(), parts.next()) {
+		(Some(pallet), None, None) if !pallet.is_empty() => Ok(twox_128(pallet.as_bytes()).to_vec()),
+		(Some(pallet), Some(item), None) if !pallet.is_empty() && !item.is_empty() => {
+			Ok([twox_128(pallet.as_bytes()), twox_128(item.as_bytes())].concat())
+		}
+		_ => {
+			Err(ArchiveError::from(format!("invalid storage prefix `{}`, expected hex, `Pallet` or `Pallet::Item`", s)))
+		}
+	}
+}
+
+fn system_number() -> Vec<u8> {
+	[twox_128(b"System"), twox_128(b"Number")].concat()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const SYSTEM: &str = "26aa394eea5630e07c48ae0c9558cef7";
+	const SYSTEM_ACCOUNT: &str = "26aa394eea5630e07c48ae0c9558cef7b99d880ec681799c0cf30e8886371da9";
+	const BALANCES: &str = "c2261276cc9d1f8598ea4b6a74b15c2f";
+
+	fn key(hex: &str) -> Vec<u8> {
+		hex::decode(hex).unwrap()
+	}
+
+	#[test]
+	fn should_hash_pallet_names() {
+		assert_eq!(parse_prefix("System").unwrap(), key(SYSTEM));
+		assert_eq!(parse_prefix("System::Account").unwrap(), key(SYSTEM_ACCOUNT));
+		assert_eq!(parse_prefix(&format!("0x{}", BALANCES)).unwrap(), key(BALANCES));
+		assert!(parse_prefix("System::").is_err());
+		assert!(parse_prefix("0xzz").is_err());
+	}
+
+	#[test]
+	fn should_allow_everything_by_default() {
+		let filter = StorageFilter::default();
+		assert!(filter.is_allowed(&key(SYSTEM_ACCOUNT)));
+		assert!(filter.is_allowed(b":code"));
+	}
+
+	#[test]
+	fn should_apply_allow_and_deny_lists() {
+		let filter = StorageFilter::new(&["System", "Balances"], &["System::Account"]).unwrap();
+		assert!(filter.is_allowed(&key(&format!("{}00", BALANCES))));
+		assert!(filter.is_allowed(&key(&format!("{}00", SYSTEM))));
+		assert!(!filter.is_allowed(&key(&format!("{}00", SYSTEM_ACCOUNT))));
+		assert!(!filter.is_allowed(b":code"));
+	}
+
+	#[test]
+	fn should_always_allow_block_number() {
+		let filter = StorageFilter::new(&["Balances"], &["System"]).unwrap();
+		assert!(filter.is_allowed(&system_number()));
+	}
+}
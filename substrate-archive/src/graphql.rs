@@ -0,0 +1,276 @@
+// Copyright 2017-2019 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! A GraphQL service over the archive's tables, with filtering and pagination.
+//! New blocks can be subscribed to over websockets; the subscription is driven by the
+//! `blocks_update` notifications that Postgres sends for every inserted block.
+//!
+//! Byte columns are exposed as `0x`-prefixed hex strings, and decoded extrinsics and events
+//! as JSON. Extrinsics and events are only available if the archive was run with `decode`.
+//! The top-level `extrinsics` and `events` queries only return those of canonical blocks;
+//! those of blocks on forks can be queried through the block.
+
+mod objects;
+
+use std::{convert::Infallible, net::SocketAddr, sync::Arc};
+
+use async_graphql::{
+	dataloader::DataLoader,
+	http::{playground_source, GraphQLPlaygroundConfig},
+	Context, EmptyMutation, FieldResult, Object, Schema, Subscription,
+};
+use futures::{FutureExt, Stream, StreamExt};
+use parking_lot::Mutex;
+use sqlx::{postgres::PgPoolOptions, PgConnection, PgPool};
+use warp::Filter;
+
+use substrate_archive_common::{models::BlockModel, Result};
+
+use self::objects::{
+	block_number, from_hex, page, to_hex, Block, BlockFilter, DigestLogLoader, Event, EventFilter, EventLoader,
+	Extrinsic, ExtrinsicFilter, ExtrinsicLoader, MetadataLoader, Runtime, RuntimeLoader, StorageLoader, StorageValue,
+};
+use crate::database::{queries, Channel, Listener};
+
+/// Maximum depth of a query
+const MAX_DEPTH: usize = 10;
+/// Blocks buffered for a subscriber before it is considered lagging and dropped
+const SUBSCRIBER_BUFFER: usize = 256;
+
+pub type ArchiveSchema = Schema<Query, EmptyMutation, Subscription>;
+
+/// Serve GraphQL over HTTP on `addr`, reading from the Postgres database at `pg_url`.
+/// Queries are POSTed to `/`, subscriptions use websockets on `/`,
+/// and a GraphQL playground is served on `GET /`. Blocks the current thread.
+pub fn serve(addr: SocketAddr, pg_url: &str) -> Result<()> {
+	let pool = smol::block_on(PgPoolOptions::new().max_connections(num_cpus::get() as u32).connect(pg_url))?;
+	let new_blocks = NewBlocks::default();
+	// dropping the listener stops subscriptions
+	let _listener = smol::block_on(new_blocks.clone().listen(pg_url))?;
+	let schema = Schema::build(Query, EmptyMutation, Subscription)
+		.data(DataLoader::new(ExtrinsicLoader(pool.clone())))
+		.data(DataLoader::new(EventLoader(pool.clone())))
+		.data(DataLoader::new(DigestLogLoader(pool.clone())))
+		.data(DataLoader::new(StorageLoader(pool.clone())))
+		.data(DataLoader::new(RuntimeLoader(pool.clone())))
+		.data(DataLoader::new(MetadataLoader(pool.clone())))
+		.data(pool)
+		.data(new_blocks)
+		.limit_depth(MAX_DEPTH)
+		.finish();
+
+	let mut runtime = tokio::runtime::Runtime::new()?;
+	log::info!("Serving GraphQL on http://{}", addr);
+	runtime.block_on(async move {
+		let query = async_graphql_warp::graphql(schema.clone()).and_then(
+			|(schema, request): (ArchiveSchema, async_graphql::Request)| async move {
+				Ok::<_, Infallible>(async_graphql_warp::Response::from(schema.execute(request).await))
+			},
+		);
+		let playground = warp::get().and(warp::path::end()).map(|| {
+			warp::http::Response::builder()
+				.header("content-type", "text/html")
+				.body(playground_source(GraphQLPlaygroundConfig::new("/").subscription_endpoint("/")))
+		});
+		let routes = async_graphql_warp::graphql_subscription(schema).or(playground).or(query);
+		warp::serve(routes).run(addr).await;
+	});
+	Ok(())
+}
+
+/// Broadcasts newly inserted blocks to subscribers
+#[derive(Clone, Default)]
+struct NewBlocks {
+	subscribers: Arc<Mutex<Vec<flume::Sender<BlockModel>>>>,
+}
+
+impl NewBlocks {
+	/// Publish blocks as Postgres notifies us of them
+	async fn listen(self, pg_url: &str) -> Result<Listener> {
+		Listener::builder(pg_url, move |notif, conn| {
+			let new_blocks = self.clone();
+			async move {
+				if !new_blocks.has_subscribers() {
+					return Ok(());
+				}
+				if let Some(block) = queries::get_full_block_by_id(conn, notif.id).await? {
+					new_blocks.publish(block);
+				}
+				Ok(())
+			}
+			.boxed()
+		})
+		.listen_on(Channel::Blocks)
+		.spawn()
+		.await
+	}
+
+	fn has_subscribers(&self) -> bool {
+		!self.subscribers.lock().is_empty()
+	}
+
+	fn subscribe(&self) -> impl Stream<Item = BlockModel> {
+		let (tx, rx) = flume::bounded(SUBSCRIBER_BUFFER);
+		self.subscribers.lock().push(tx);
+		rx.into_stream()
+	}
+
+	/// Send `block` to every subscriber, forgetting those that went away or lag behind
+	fn publish(&self, block: BlockModel) {
+		self.subscribers.lock().retain(|s| match s.try_send(block.clone()) {
+			Ok(()) => true,
+			Err(flume::TrySendError::Full(_)) => {
+				log::warn!("Dropping a block subscriber that fell {} blocks behind", SUBSCRIBER_BUFFER);
+				false
+			}
+			Err(flume::TrySendError::Disconnected(_)) => false,
+		});
+	}
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+	/// A block by hash, or the canonical block by number. The best canonical block if neither is given.
+	async fn block(&self, ctx: &Context<'_>, hash: Option<String>, number: Option<i64>) -> FieldResult<Option<Block>> {
+		let pool = ctx.data_unchecked::<PgPool>();
+		let hash = match (hash, number) {
+			(Some(hash), _) => from_hex(&hash)?,
+			(None, Some(number)) => {
+				let filter = BlockFilter { from: Some(number), to: Some(number), is_canonical: Some(true), spec: None };
+				return Ok(Block::fetch(pool, filter, Some(1), 0).await?.pop());
+			}
+			(None, None) => match queries::best_canonical_hash(&mut *pool.acquire().await?).await? {
+				Some(hash) => hash,
+				None => return Ok(None),
+			},
+		};
+		Ok(queries::get_full_block_by_hash(&mut *pool.acquire().await?, &hash).await?.map(Block))
+	}
+
+	/// Blocks in ascending order of block number
+	async fn blocks(
+		&self,
+		ctx: &Context<'_>,
+		filter: Option<BlockFilter>,
+		limit: Option<i64>,
+		offset: Option<i64>,
+	) -> FieldResult<Vec<Block>> {
+		let (limit, offset) = page(limit, offset)?;
+		Ok(Block::fetch(ctx.data_unchecked(), filter.unwrap_or_default(), Some(limit), offset).await?)
+	}
+
+	/// Decoded extrinsics of canonical blocks, in the order they were included in the chain
+	async fn extrinsics(
+		&self,
+		ctx: &Context<'_>,
+		filter: Option<ExtrinsicFilter>,
+		limit: Option<i64>,
+		offset: Option<i64>,
+	) -> FieldResult<Vec<Extrinsic>> {
+		let (limit, offset) = page(limit, offset)?;
+		Ok(Extrinsic::fetch(ctx.data_unchecked(), filter.unwrap_or_default(), Some(limit), offset).await?)
+	}
+
+	/// Decoded events of canonical blocks, in the order they were emitted
+	async fn events(
+		&self,
+		ctx: &Context<'_>,
+		filter: Option<EventFilter>,
+		limit: Option<i64>,
+		offset: Option<i64>,
+	) -> FieldResult<Vec<Event>> {
+		let (limit, offset) = page(limit, offset)?;
+		Ok(Event::fetch(ctx.data_unchecked(), filter.unwrap_or_default(), Some(limit), offset).await?)
+	}
+
+	/// The value of a storage key at a canonical block, or at the highest block whose state has been archived.
+	/// Only keys that were indexed can be queried.
+	async fn storage(&self, ctx: &Context<'_>, key: String, block: Option<i64>) -> FieldResult<Option<String>> {
+		let mut conn = ctx.data_unchecked::<PgPool>().acquire().await?;
+		let block = state_block(&mut conn, block).await?;
+		Ok(queries::storage_at(&mut conn, &from_hex(&key)?, block).await?.map(|v| to_hex(&v)))
+	}
+
+	/// Every change to a storage key between two canonical blocks, inclusive
+	async fn storage_history(
+		&self,
+		ctx: &Context<'_>,
+		key: String,
+		from: i64,
+		to: i64,
+	) -> FieldResult<Vec<StorageValue>> {
+		let (from, to) = (block_number(from)?, block_number(to)?);
+		let mut conn = ctx.data_unchecked::<PgPool>().acquire().await?;
+		let changes = queries::storage_history(&mut conn, &from_hex(&key)?, from..=to).await?;
+		Ok(changes
+			.into_iter()
+			.map(|c| StorageValue {
+				block_num: c.block_num as i64,
+				block_hash: to_hex(&c.hash),
+				key: key.clone(),
+				value: c.value.as_deref().map(to_hex),
+			})
+			.collect())
+	}
+
+	/// Storage keys starting with `prefix` that have a value at a canonical block,
+	/// or at the highest block whose state has been archived
+	async fn storage_keys(&self, ctx: &Context<'_>, prefix: String, block: Option<i64>) -> FieldResult<Vec<String>> {
+		let mut conn = ctx.data_unchecked::<PgPool>().acquire().await?;
+		let block = state_block(&mut conn, block).await?;
+		let keys = queries::keys_with_prefix_at(&mut conn, &from_hex(&prefix)?, block).await?;
+		Ok(keys.into_iter().map(|k| to_hex(&k)).collect())
+	}
+
+	/// Runtime versions. Their metadata is only fetched if requested
+	async fn runtimes(&self, ctx: &Context<'_>, version: Option<i32>) -> FieldResult<Vec<Runtime>> {
+		Ok(Runtime::fetch(ctx.data_unchecked(), version).await?)
+	}
+}
+
+/// The canonical block to read state at.
+/// Defaults to the highest block whose storage has been inserted, and fails for blocks whose storage has not been.
+async fn state_block(conn: &mut PgConnection, block: Option<i64>) -> FieldResult<u64> {
+	let block = match block {
+		Some(b) => block_number(b)?,
+		None => {
+			return queries::best_executed_block(conn)
+				.await?
+				.map(|(num, _)| num)
+				.ok_or_else(|| "no blocks have been executed".into())
+		}
+	};
+	let hash = match queries::canonical_hash(conn, block).await? {
+		Some(hash) => hash,
+		None => return Err(format!("block {} has not been archived", block).into()),
+	};
+	if !queries::has_storage(conn, &hash).await? {
+		return Err(format!("state of block {} has not been archived yet", block).into());
+	}
+	Ok(block)
+}
+
+pub struct Subscription;
+
+#[Subscription]
+impl Subscription {
+	/// Blocks as they are archived. Includes blocks on forks if the archive indexes forks.
+	async fn new_blocks(&self, ctx: &Context<'_>) -> impl Stream<Item = Block> {
+		ctx.data_unchecked::<NewBlocks>().subscribe().map(Block)
+	}
+}
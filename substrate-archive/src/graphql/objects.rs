@@ -0,0 +1,880 @@
+// Copyright 2017-2019 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! GraphQL objects, and the queries that fetch them.
+//!
+//! Fields of a block that are lists, and the runtime of the block, are fetched through a [`DataLoader`],
+//! which batches the field of every block in a response into a single query.
+
+use std::{collections::HashMap, sync::Arc};
+
+use async_graphql::{
+	dataloader::{DataLoader, Loader},
+	Context, FieldResult, InputObject, Json, Object, SimpleObject,
+};
+use serde_json::Value;
+use sqlx::PgPool;
+
+use substrate_archive_common::{models::BlockModel, Result};
+
+/// Number of rows returned if a query does not set a limit
+const DEFAULT_LIMIT: i64 = 20;
+/// Maximum number of rows a query may return
+const MAX_LIMIT: i64 = 100;
+
+pub fn to_hex(bytes: &[u8]) -> String {
+	format!("0x{}", hex::encode(bytes))
+}
+
+pub fn from_hex(s: &str) -> FieldResult<Vec<u8>> {
+	hex::decode(s.trim_start_matches("0x")).map_err(|e| format!("`{}` is not hex: {}", s, e).into())
+}
+
+/// Validate the `limit` and `offset` of a paginated query
+pub fn page(limit: Option<i64>, offset: Option<i64>) -> FieldResult<(i64, i64)> {
+	let limit = limit.unwrap_or(DEFAULT_LIMIT);
+	if !(0..=MAX_LIMIT).contains(&limit) {
+		return Err(format!("limit must be between 0 and {}", MAX_LIMIT).into());
+	}
+	let offset = offset.unwrap_or(0);
+	if offset < 0 {
+		return Err("offset must not be negative".into());
+	}
+	Ok((limit, offset))
+}
+
+/// Apply a page validated by [`page`] to a list that was loaded as a whole
+fn paginate<T>(items: impl Iterator<Item = T>, limit: i64, offset: i64) -> Vec<T> {
+	items.skip(offset as usize).take(limit as usize).collect()
+}
+
+/// Validate a block number argument
+pub fn block_number(number: i64) -> FieldResult<u64> {
+	if number < 0 {
+		return Err(format!("block number {} is negative", number).into());
+	}
+	Ok(number as u64)
+}
+
+/// Group rows by the block they belong to, keeping their order
+fn by_block<R, T: From<R>>(rows: Vec<R>, hash: impl Fn(&R) -> Vec<u8>) -> HashMap<Vec<u8>, Vec<T>> {
+	let mut blocks: HashMap<Vec<u8>, Vec<T>> = HashMap::new();
+	for row in rows {
+		blocks.entry(hash(&row)).or_default().push(row.into());
+	}
+	blocks
+}
+
+#[derive(InputObject, Default)]
+pub struct BlockFilter {
+	/// Lowest block number, inclusive
+	pub from: Option<i64>,
+	/// Highest block number, inclusive
+	pub to: Option<i64>,
+	/// Spec version of the runtime the block was executed with
+	pub spec: Option<i32>,
+	/// Only blocks on the canonical chain if true, only blocks on forks if false
+	pub is_canonical: Option<bool>,
+}
+
+#[derive(InputObject, Default)]
+pub struct ExtrinsicFilter {
+	/// Lowest block number, inclusive
+	pub from: Option<i64>,
+	/// Highest block number, inclusive
+	pub to: Option<i64>,
+	pub pallet: Option<String>,
+	pub call: Option<String>,
+	pub success: Option<bool>,
+}
+
+impl ExtrinsicFilter {
+	/// Whether `e` passes the filter. Used for the extrinsics of a single block, which are loaded as a whole
+	fn matches(&self, e: &Extrinsic) -> bool {
+		self.from.map_or(true, |from| e.block_num >= from)
+			&& self.to.map_or(true, |to| e.block_num <= to)
+			&& self.pallet.as_ref().map_or(true, |pallet| *pallet == e.pallet)
+			&& self.call.as_ref().map_or(true, |call| *call == e.call)
+			&& self.success.map_or(true, |success| e.success == Some(success))
+	}
+}
+
+#[derive(InputObject, Default)]
+pub struct EventFilter {
+	/// Lowest block number, inclusive
+	pub from: Option<i64>,
+	/// Highest block number, inclusive
+	pub to: Option<i64>,
+	pub pallet: Option<String>,
+	pub event: Option<String>,
+	/// Index of the extrinsic that emitted the event
+	pub extrinsic_index: Option<i32>,
+}
+
+impl EventFilter {
+	/// Whether `e` passes the filter. Used for the events of a single block, which are loaded as a whole
+	fn matches(&self, e: &Event) -> bool {
+		self.from.map_or(true, |from| e.block_num >= from)
+			&& self.to.map_or(true, |to| e.block_num <= to)
+			&& self.pallet.as_ref().map_or(true, |pallet| *pallet == e.pallet)
+			&& self.event.as_ref().map_or(true, |event| *event == e.event)
+			&& self.extrinsic_index.map_or(true, |index| e.extrinsic_index == Some(index))
+	}
+}
+
+pub struct Block(pub BlockModel);
+
+impl Block {
+	pub async fn fetch(pool: &PgPool, filter: BlockFilter, limit: Option<i64>, offset: i64) -> Result<Vec<Block>> {
+		#[allow(clippy::toplevel_ref_arg)]
+		let blocks = sqlx::query_as!(
+			BlockModel,
+			"SELECT id, parent_hash, hash, block_num, state_root, extrinsics_root, digest, ext, spec, is_canonical, justification
+            FROM blocks
+            WHERE ($1::bigint IS NULL OR block_num >= $1) AND ($2::bigint IS NULL OR block_num <= $2)
+            AND ($3::int IS NULL OR spec = $3) AND ($4::bool IS NULL OR is_canonical = $4)
+            ORDER BY block_num, id
+            LIMIT $5 OFFSET $6",
+			filter.from,
+			filter.to,
+			filter.spec,
+			filter.is_canonical,
+			limit,
+			offset
+		)
+		.fetch_all(pool)
+		.await?;
+		Ok(blocks.into_iter().map(Block).collect())
+	}
+}
+
+#[Object]
+impl Block {
+	async fn hash(&self) -> String {
+		to_hex(&self.0.hash)
+	}
+
+	async fn parent_hash(&self) -> String {
+		to_hex(&self.0.parent_hash)
+	}
+
+	async fn number(&self) -> i64 {
+		self.0.block_num
+	}
+
+	async fn state_root(&self) -> String {
+		to_hex(&self.0.state_root)
+	}
+
+	async fn extrinsics_root(&self) -> String {
+		to_hex(&self.0.extrinsics_root)
+	}
+
+	/// SCALE-encoded digest
+	async fn digest(&self) -> String {
+		to_hex(&self.0.digest)
+	}
+
+	/// SCALE-encoded extrinsics
+	async fn encoded_extrinsics(&self) -> String {
+		to_hex(&self.0.ext)
+	}
+
+	/// Spec version of the runtime the block was executed with
+	async fn spec(&self) -> i32 {
+		self.0.spec
+	}
+
+	async fn is_canonical(&self) -> bool {
+		self.0.is_canonical
+	}
+
+	/// SCALE-encoded finality justification, if the node stored one
+	async fn justification(&self) -> Option<String> {
+		self.0.justification.as_deref().map(to_hex)
+	}
+
+	/// Decoded extrinsics of this block, in order
+	async fn extrinsics(
+		&self,
+		ctx: &Context<'_>,
+		filter: Option<ExtrinsicFilter>,
+		limit: Option<i64>,
+		offset: Option<i64>,
+	) -> FieldResult<Vec<Extrinsic>> {
+		let (limit, offset) = page(limit, offset)?;
+		let filter = filter.unwrap_or_default();
+		let loader = ctx.data_unchecked::<DataLoader<ExtrinsicLoader>>();
+		let extrinsics = loader.load_one(self.0.hash.clone()).await?.unwrap_or_default();
+		Ok(paginate(extrinsics.into_iter().filter(|e| filter.matches(e)), limit, offset))
+	}
+
+	/// Decoded events of this block, in order
+	async fn events(
+		&self,
+		ctx: &Context<'_>,
+		filter: Option<EventFilter>,
+		limit: Option<i64>,
+		offset: Option<i64>,
+	) -> FieldResult<Vec<Event>> {
+		let (limit, offset) = page(limit, offset)?;
+		let filter = filter.unwrap_or_default();
+		let loader = ctx.data_unchecked::<DataLoader<EventLoader>>();
+		let events = loader.load_one(self.0.hash.clone()).await?.unwrap_or_default();
+		Ok(paginate(events.into_iter().filter(|e| filter.matches(e)), limit, offset))
+	}
+
+	/// Decoded items of the digest of this block, in order
+	async fn digest_logs(
+		&self,
+		ctx: &Context<'_>,
+		limit: Option<i64>,
+		offset: Option<i64>,
+	) -> FieldResult<Vec<DigestLog>> {
+		let (limit, offset) = page(limit, offset)?;
+		let loader = ctx.data_unchecked::<DataLoader<DigestLogLoader>>();
+		let logs = loader.load_one(self.0.hash.clone()).await?.unwrap_or_default();
+		Ok(paginate(logs.into_iter(), limit, offset))
+	}
+
+	/// Storage changed by this block, in order of key
+	async fn storage(
+		&self,
+		ctx: &Context<'_>,
+		prefix: Option<String>,
+		limit: Option<i64>,
+		offset: Option<i64>,
+	) -> FieldResult<Vec<StorageValue>> {
+		let (limit, offset) = page(limit, offset)?;
+		let prefix = prefix.as_deref().map(from_hex).transpose()?;
+		let key = StoragePage { hash: self.0.hash.clone(), prefix, limit, offset };
+		Ok(ctx.data_unchecked::<DataLoader<StorageLoader>>().load_one(key).await?.unwrap_or_default())
+	}
+
+	/// The runtime this block was executed with
+	async fn runtime(&self, ctx: &Context<'_>) -> FieldResult<Option<Runtime>> {
+		Ok(ctx.data_unchecked::<DataLoader<RuntimeLoader>>().load_one(self.0.spec).await?)
+	}
+}
+
+/// Return type of queries for extrinsics
+struct ExtrinsicRow {
+	hash: Vec<u8>,
+	block_num: i64,
+	index: i32,
+	signer: Option<Value>,
+	nonce: Option<i64>,
+	tip: Option<String>,
+	pallet: String,
+	call: String,
+	args: Value,
+	success: Option<bool>,
+}
+
+#[derive(SimpleObject, Clone)]
+pub struct Extrinsic {
+	block_num: i64,
+	block_hash: String,
+	/// Position of the extrinsic in the block
+	index: i32,
+	/// Address of the signer. Null for unsigned extrinsics
+	signer: Option<Json<Value>>,
+	nonce: Option<i64>,
+	/// Tip as a decimal string, since it may not fit in a 64-bit number
+	tip: Option<String>,
+	pallet: String,
+	call: String,
+	args: Json<Value>,
+	/// Null if the outcome could not be determined from the events of the block
+	success: Option<bool>,
+}
+
+impl From<ExtrinsicRow> for Extrinsic {
+	fn from(r: ExtrinsicRow) -> Extrinsic {
+		Extrinsic {
+			block_num: r.block_num,
+			block_hash: to_hex(&r.hash),
+			index: r.index,
+			signer: r.signer.map(Json),
+			nonce: r.nonce,
+			tip: r.tip,
+			pallet: r.pallet,
+			call: r.call,
+			args: Json(r.args),
+			success: r.success,
+		}
+	}
+}
+
+impl Extrinsic {
+	/// Extrinsics of canonical blocks
+	pub async fn fetch(
+		pool: &PgPool,
+		filter: ExtrinsicFilter,
+		limit: Option<i64>,
+		offset: i64,
+	) -> Result<Vec<Extrinsic>> {
+		#[allow(clippy::toplevel_ref_arg)]
+		let rows = sqlx::query_as!(
+			ExtrinsicRow,
+			"SELECT extrinsics.hash, extrinsics.block_num, index, signer, nonce, tip::text AS tip, pallet, call, args, success
+            FROM extrinsics
+            JOIN blocks ON blocks.hash = extrinsics.hash
+            WHERE blocks.is_canonical
+            AND ($1::bigint IS NULL OR extrinsics.block_num >= $1) AND ($2::bigint IS NULL OR extrinsics.block_num <= $2)
+            AND ($3::text IS NULL OR pallet = $3) AND ($4::text IS NULL OR call = $4)
+            AND ($5::bool IS NULL OR success = $5)
+            ORDER BY extrinsics.block_num, index
+            LIMIT $6 OFFSET $7",
+			filter.from,
+			filter.to,
+			filter.pallet,
+			filter.call,
+			filter.success,
+			limit,
+			offset
+		)
+		.fetch_all(pool)
+		.await?;
+		Ok(rows.into_iter().map(Extrinsic::from).collect())
+	}
+}
+
+/// Loads every extrinsic of blocks by hash
+pub struct ExtrinsicLoader(pub PgPool);
+
+#[async_trait::async_trait]
+impl Loader<Vec<u8>> for ExtrinsicLoader {
+	type Value = Vec<Extrinsic>;
+	type Error = Arc<sqlx::Error>;
+
+	async fn load(&self, hashes: &[Vec<u8>]) -> std::result::Result<HashMap<Vec<u8>, Vec<Extrinsic>>, Self::Error> {
+		#[allow(clippy::toplevel_ref_arg)]
+		let rows = sqlx::query_as!(
+			ExtrinsicRow,
+			"SELECT hash, block_num, index, signer, nonce, tip::text AS tip, pallet, call, args, success
+            FROM extrinsics
+            WHERE hash = ANY($1)
+            ORDER BY hash, index",
+			&hashes.to_vec()
+		)
+		.fetch_all(&self.0)
+		.await
+		.map_err(Arc::new)?;
+		Ok(by_block(rows, |r| r.hash.clone()))
+	}
+}
+
+/// Return type of queries for events
+struct EventRow {
+	hash: Vec<u8>,
+	block_num: i64,
+	index: i32,
+	extrinsic_index: Option<i32>,
+	phase: String,
+	pallet: String,
+	event: String,
+	fields: Value,
+}
+
+#[derive(SimpleObject, Clone)]
+pub struct Event {
+	block_num: i64,
+	block_hash: String,
+	/// Position of the event in `System::Events`
+	index: i32,
+	/// Index of the extrinsic that emitted the event.
+	/// Null for events emitted while initializing or finalizing the block
+	extrinsic_index: Option<i32>,
+	/// One of `ApplyExtrinsic`, `Initialization` or `Finalization`
+	phase: String,
+	pallet: String,
+	event: String,
+	fields: Json<Value>,
+}
+
+impl From<EventRow> for Event {
+	fn from(r: EventRow) -> Event {
+		Event {
+			block_num: r.block_num,
+			block_hash: to_hex(&r.hash),
+			index: r.index,
+			extrinsic_index: r.extrinsic_index,
+			phase: r.phase,
+			pallet: r.pallet,
+			event: r.event,
+			fields: Json(r.fields),
+		}
+	}
+}
+
+impl Event {
+	/// Events of canonical blocks
+	pub async fn fetch(pool: &PgPool, filter: EventFilter, limit: Option<i64>, offset: i64) -> Result<Vec<Event>> {
+		#[allow(clippy::toplevel_ref_arg)]
+		let rows = sqlx::query_as!(
+			EventRow,
+			"SELECT events.hash, events.block_num, index, extrinsic_index, phase, pallet, event, fields
+            FROM events
+            JOIN blocks ON blocks.hash = events.hash
+            WHERE blocks.is_canonical
+            AND ($1::bigint IS NULL OR events.block_num >= $1) AND ($2::bigint IS NULL OR events.block_num <= $2)
+            AND ($3::text IS NULL OR pallet = $3) AND ($4::text IS NULL OR event = $4)
+            AND ($5::int IS NULL OR extrinsic_index = $5)
+            ORDER BY events.block_num, index
+            LIMIT $6 OFFSET $7",
+			filter.from,
+			filter.to,
+			filter.pallet,
+			filter.event,
+			filter.extrinsic_index,
+			limit,
+			offset
+		)
+		.fetch_all(pool)
+		.await?;
+		Ok(rows.into_iter().map(Event::from).collect())
+	}
+}
+
+/// Loads every event of blocks by hash
+pub struct EventLoader(pub PgPool);
+
+#[async_trait::async_trait]
+impl Loader<Vec<u8>> for EventLoader {
+	type Value = Vec<Event>;
+	type Error = Arc<sqlx::Error>;
+
+	async fn load(&self, hashes: &[Vec<u8>]) -> std::result::Result<HashMap<Vec<u8>, Vec<Event>>, Self::Error> {
+		#[allow(clippy::toplevel_ref_arg)]
+		let rows = sqlx::query_as!(
+			EventRow,
+			"SELECT hash, block_num, index, extrinsic_index, phase, pallet, event, fields
+            FROM events
+            WHERE hash = ANY($1)
+            ORDER BY hash, index",
+			&hashes.to_vec()
+		)
+		.fetch_all(&self.0)
+		.await
+		.map_err(Arc::new)?;
+		Ok(by_block(rows, |r| r.hash.clone()))
+	}
+}
+
+/// Return type of queries for digest logs
+struct DigestLogRow {
+	hash: Vec<u8>,
+	index: i32,
+	kind: String,
+	engine: Option<String>,
+	data: Option<Vec<u8>>,
+	slot: Option<i64>,
+	authority_index: Option<i32>,
+	authority: Option<Vec<u8>>,
+	author: Option<Vec<u8>>,
+}
+
+#[derive(SimpleObject, Clone)]
+pub struct DigestLog {
+	/// Position of the item in the digest
+	index: i32,
+	/// One of `PreRuntime`, `Consensus`, `Seal`, `RuntimeEnvironmentUpdated`, `ChangesTrieRoot`,
+	/// `ChangesTrieSignal` or `Other`
+	kind: String,
+	/// Consensus engine of the item, e.g. `BABE`
+	engine: Option<String>,
+	/// SCALE-encoded payload of the item
+	data: Option<String>,
+	/// Slot the block was authored in, from a BABE or Aura pre-runtime item
+	slot: Option<i64>,
+	/// Index of the block author in the authority set
+	authority_index: Option<i32>,
+	/// Authority id of the block author in the BABE or Aura authority set
+	authority: Option<String>,
+	/// Account of the block author in the session validators
+	author: Option<String>,
+}
+
+impl From<DigestLogRow> for DigestLog {
+	fn from(r: DigestLogRow) -> DigestLog {
+		DigestLog {
+			index: r.index,
+			kind: r.kind,
+			engine: r.engine,
+			data: r.data.as_deref().map(to_hex),
+			slot: r.slot,
+			authority_index: r.authority_index,
+			authority: r.authority.as_deref().map(to_hex),
+			author: r.author.as_deref().map(to_hex),
+		}
+	}
+}
+
+/// Loads the digest logs of blocks by hash
+pub struct DigestLogLoader(pub PgPool);
+
+#[async_trait::async_trait]
+impl Loader<Vec<u8>> for DigestLogLoader {
+	type Value = Vec<DigestLog>;
+	type Error = Arc<sqlx::Error>;
+
+	async fn load(&self, hashes: &[Vec<u8>]) -> std::result::Result<HashMap<Vec<u8>, Vec<DigestLog>>, Self::Error> {
+		#[allow(clippy::toplevel_ref_arg)]
+		let rows = sqlx::query_as!(
+			DigestLogRow,
+			"SELECT hash, index, kind, engine, data, slot, authority_index, authority, author
+            FROM digest_logs
+            WHERE hash = ANY($1)
+            ORDER BY hash, index",
+			&hashes.to_vec()
+		)
+		.fetch_all(&self.0)
+		.await
+		.map_err(Arc::new)?;
+		Ok(by_block(rows, |r| r.hash.clone()))
+	}
+}
+
+/// Return type of queries for storage
+struct StorageRow {
+	block_num: i64,
+	hash: Vec<u8>,
+	key: Vec<u8>,
+	storage: Option<Vec<u8>>,
+}
+
+#[derive(SimpleObject, Clone)]
+pub struct StorageValue {
+	pub block_num: i64,
+	pub block_hash: String,
+	pub key: String,
+	/// Null if the key was removed
+	pub value: Option<String>,
+}
+
+impl From<StorageRow> for StorageValue {
+	fn from(r: StorageRow) -> StorageValue {
+		StorageValue {
+			block_num: r.block_num,
+			block_hash: to_hex(&r.hash),
+			key: to_hex(&r.key),
+			value: r.storage.as_deref().map(to_hex),
+		}
+	}
+}
+
+/// A page of the storage changed by block `hash`. Unlike extrinsics, the storage of a block is paginated
+/// in the database, because the first block and blocks with a state snapshot change every key.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct StoragePage {
+	hash: Vec<u8>,
+	prefix: Option<Vec<u8>>,
+	limit: i64,
+	offset: i64,
+}
+
+/// Loads pages of the storage changed by blocks
+pub struct StorageLoader(pub PgPool);
+
+#[async_trait::async_trait]
+impl Loader<StoragePage> for StorageLoader {
+	type Value = Vec<StorageValue>;
+	type Error = Arc<sqlx::Error>;
+
+	async fn load(
+		&self,
+		pages: &[StoragePage],
+	) -> std::result::Result<HashMap<StoragePage, Vec<StorageValue>>, Self::Error> {
+		// blocks whose storage is queried with the same arguments are loaded together
+		let mut blocks: HashMap<(Option<Vec<u8>>, i64, i64), Vec<Vec<u8>>> = HashMap::new();
+		for key in pages {
+			blocks.entry((key.prefix.clone(), key.limit, key.offset)).or_default().push(key.hash.clone());
+		}
+		let mut values = HashMap::new();
+		for ((prefix, limit, offset), hashes) in blocks {
+			#[allow(clippy::toplevel_ref_arg)]
+			let rows = sqlx::query_as!(
+				StorageRow,
+				"SELECT s.block_num, s.hash, s.key, s.storage
+                FROM UNNEST($1::bytea[]) AS queried(hash)
+                CROSS JOIN LATERAL (
+                    SELECT block_num, hash, key, storage
+                    FROM storage
+                    WHERE storage.hash = queried.hash
+                    AND ($2::bytea IS NULL OR substring(key FROM 1 FOR length($2)) = $2)
+                    ORDER BY key
+                    LIMIT $3 OFFSET $4
+                ) AS s
+                ORDER BY s.hash, s.key",
+				&hashes,
+				prefix,
+				limit,
+				offset
+			)
+			.fetch_all(&self.0)
+			.await
+			.map_err(Arc::new)?;
+			for (hash, storage) in by_block(rows, |r| r.hash.clone()) {
+				values.insert(StoragePage { hash, prefix: prefix.clone(), limit, offset }, storage);
+			}
+		}
+		Ok(values)
+	}
+}
+
+/// Return type of queries for runtime versions
+struct RuntimeRow {
+	version: i32,
+	runtime_version: Option<Value>,
+}
+
+#[derive(Clone)]
+pub struct Runtime {
+	version: i32,
+	runtime_version: Option<Value>,
+}
+
+impl From<RuntimeRow> for Runtime {
+	fn from(r: RuntimeRow) -> Runtime {
+		Runtime { version: r.version, runtime_version: r.runtime_version }
+	}
+}
+
+impl Runtime {
+	pub async fn fetch(pool: &PgPool, version: Option<i32>) -> Result<Vec<Runtime>> {
+		#[allow(clippy::toplevel_ref_arg)]
+		let rows = sqlx::query_as!(
+			RuntimeRow,
+			"SELECT version, runtime_version FROM metadata WHERE ($1::int IS NULL OR version = $1) ORDER BY version",
+			version
+		)
+		.fetch_all(pool)
+		.await?;
+		Ok(rows.into_iter().map(Runtime::from).collect())
+	}
+}
+
+#[Object]
+impl Runtime {
+	/// Spec version of the runtime
+	async fn version(&self) -> i32 {
+		self.version
+	}
+
+	/// SCALE-encoded metadata. Only loaded if requested, since it is several hundred kilobytes
+	async fn metadata(&self, ctx: &Context<'_>) -> FieldResult<String> {
+		let meta = ctx.data_unchecked::<DataLoader<MetadataLoader>>().load_one(self.version).await?;
+		meta.ok_or_else(|| format!("metadata of runtime {} is missing", self.version).into())
+	}
+
+	/// The full runtime version. Null if it was archived before runtime versions were stored
+	async fn runtime_version(&self) -> Option<Json<Value>> {
+		self.runtime_version.clone().map(Json)
+	}
+}
+
+/// Loads runtimes by spec version, without their metadata
+pub struct RuntimeLoader(pub PgPool);
+
+#[async_trait::async_trait]
+impl Loader<i32> for RuntimeLoader {
+	type Value = Runtime;
+	type Error = Arc<sqlx::Error>;
+
+	async fn load(&self, versions: &[i32]) -> std::result::Result<HashMap<i32, Runtime>, Self::Error> {
+		#[allow(clippy::toplevel_ref_arg)]
+		let rows = sqlx::query_as!(
+			RuntimeRow,
+			"SELECT version, runtime_version FROM metadata WHERE version = ANY($1)",
+			&versions.to_vec()
+		)
+		.fetch_all(&self.0)
+		.await
+		.map_err(Arc::new)?;
+		Ok(rows.into_iter().map(|r| (r.version, r.into())).collect())
+	}
+}
+
+/// Return type of queries for metadata
+struct MetadataRow {
+	version: i32,
+	meta: Vec<u8>,
+}
+
+/// Loads the hex-encoded metadata of runtimes by spec version
+pub struct MetadataLoader(pub PgPool);
+
+#[async_trait::async_trait]
+impl Loader<i32> for MetadataLoader {
+	type Value = String;
+	type Error = Arc<sqlx::Error>;
+
+	async fn load(&self, versions: &[i32]) -> std::result::Result<HashMap<i32, String>, Self::Error> {
+		#[allow(clippy::toplevel_ref_arg)]
+		let rows = sqlx::query_as!(
+			MetadataRow,
+			"SELECT version, meta FROM metadata WHERE version = ANY($1)",
+			&versions.to_vec()
+		)
+		.fetch_all(&self.0)
+		.await
+		.map_err(Arc::new)?;
+		Ok(rows.into_iter().map(|r| (r.version, to_hex(&r.meta))).collect())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sqlx::{postgres::PgPoolOptions, PgConnection};
+
+	fn extrinsic(block_num: i64, pallet: &str, success: Option<bool>) -> Extrinsic {
+		Extrinsic {
+			block_num,
+			block_hash: to_hex(&[0]),
+			index: 0,
+			signer: None,
+			nonce: None,
+			tip: None,
+			pallet: pallet.into(),
+			call: "remark".into(),
+			args: Json(Value::Null),
+			success,
+		}
+	}
+
+	async fn insert_block(conn: &mut PgConnection, num: i64, hash: u8, is_canonical: bool) {
+		sqlx::query(
+			"INSERT INTO blocks (parent_hash, hash, block_num, state_root, extrinsics_root, digest, ext, spec, is_canonical)
+            VALUES ($1, $2, $3, $1, $1, $1, $1, 0, $4)",
+		)
+		.bind(vec![0u8; 32])
+		.bind(vec![hash; 32])
+		.bind(num)
+		.bind(is_canonical)
+		.execute(&mut *conn)
+		.await
+		.unwrap();
+		sqlx::query(
+			"INSERT INTO extrinsics (hash, block_num, index, pallet, call, args) VALUES ($1, $2, 0, 'System', 'remark', 'null')",
+		)
+		.bind(vec![hash; 32])
+		.bind(num)
+		.execute(&mut *conn)
+		.await
+		.unwrap();
+		for key in &[[1u8, 1], [1, 2], [1, 3], [2, 1]] {
+			sqlx::query("INSERT INTO storage (block_num, hash, is_full, key, storage) VALUES ($1, $2, false, $3, $3)")
+				.bind(num)
+				.bind(vec![hash; 32])
+				.bind(key.to_vec())
+				.execute(&mut *conn)
+				.await
+				.unwrap();
+		}
+	}
+
+	#[test]
+	fn should_validate_pages() {
+		assert_eq!(page(None, None).unwrap(), (DEFAULT_LIMIT, 0));
+		assert_eq!(page(Some(MAX_LIMIT), Some(10)).unwrap(), (MAX_LIMIT, 10));
+		assert!(page(Some(MAX_LIMIT + 1), None).is_err());
+		assert!(page(Some(-1), None).is_err());
+		assert!(page(None, Some(-1)).is_err());
+	}
+
+	#[test]
+	fn should_convert_hex() {
+		assert_eq!(from_hex("0x0102").unwrap(), vec![1, 2]);
+		assert_eq!(from_hex("0102").unwrap(), vec![1, 2]);
+		assert!(from_hex("0x010").is_err());
+		assert!(from_hex("0xzz").is_err());
+		assert_eq!(to_hex(&[1, 2]), "0x0102");
+	}
+
+	#[test]
+	fn should_reject_negative_block_numbers() {
+		assert_eq!(block_number(0).unwrap(), 0);
+		assert!(block_number(-1).is_err());
+	}
+
+	#[test]
+	fn should_paginate_nested_lists() {
+		assert_eq!(paginate(0..10, 3, 0), vec![0, 1, 2]);
+		assert_eq!(paginate(0..10, 3, 8), vec![8, 9]);
+		assert_eq!(paginate(0..10, 0, 0), Vec::<i32>::new());
+		assert_eq!(paginate(0..10, 3, 20), Vec::<i32>::new());
+	}
+
+	#[test]
+	fn should_filter_nested_extrinsics() {
+		let filter = ExtrinsicFilter { pallet: Some("System".into()), success: Some(true), ..Default::default() };
+		assert!(filter.matches(&extrinsic(1, "System", Some(true))));
+		assert!(!filter.matches(&extrinsic(1, "Balances", Some(true))));
+		assert!(!filter.matches(&extrinsic(1, "System", None)));
+		let filter = ExtrinsicFilter { from: Some(2), to: Some(3), ..Default::default() };
+		assert!(!filter.matches(&extrinsic(1, "System", None)));
+		assert!(filter.matches(&extrinsic(3, "System", None)));
+		assert!(ExtrinsicFilter::default().matches(&extrinsic(1, "System", None)));
+	}
+
+	#[test]
+	fn should_load_storage_pages_of_several_blocks() {
+		crate::initialize();
+		let _guard = crate::TestGuard::lock();
+		smol::block_on(async {
+			let pool = PgPoolOptions::new().connect(&crate::DATABASE_URL).await.unwrap();
+			let mut conn = pool.acquire().await.unwrap();
+			insert_block(&mut conn, 1, 1, true).await;
+			insert_block(&mut conn, 2, 2, true).await;
+			let page = |hash: u8, prefix: Option<Vec<u8>>, offset: i64| StoragePage {
+				hash: vec![hash; 32],
+				prefix,
+				limit: 2,
+				offset,
+			};
+			let pages = vec![page(1, None, 2), page(2, None, 1), page(2, Some(vec![1]), 2)];
+
+			let loaded = StorageLoader(pool.clone()).load(&pages).await.unwrap();
+			let keys = |page: &StoragePage| loaded[page].iter().map(|v| v.key.clone()).collect::<Vec<_>>();
+			assert_eq!(keys(&pages[0]), vec!["0x0103", "0x0201"]);
+			assert_eq!(keys(&pages[1]), vec!["0x0102", "0x0103"]);
+			assert_eq!(keys(&pages[2]), vec!["0x0103"]);
+			assert!(loaded[&pages[0]].iter().all(|v| v.block_num == 1));
+		});
+	}
+
+	#[test]
+	fn should_only_return_extrinsics_of_canonical_blocks() {
+		crate::initialize();
+		let _guard = crate::TestGuard::lock();
+		smol::block_on(async {
+			let pool = PgPoolOptions::new().connect(&crate::DATABASE_URL).await.unwrap();
+			let mut conn = pool.acquire().await.unwrap();
+			insert_block(&mut conn, 1, 1, true).await;
+			insert_block(&mut conn, 1, 2, false).await;
+
+			let extrinsics = Extrinsic::fetch(&pool, ExtrinsicFilter::default(), None, 0).await.unwrap();
+			assert_eq!(extrinsics.len(), 1);
+			assert_eq!(extrinsics[0].block_hash, to_hex(&[1; 32]));
+
+			// the block of a fork still lists its extrinsics
+			let loaded = ExtrinsicLoader(pool.clone()).load(&[vec![1; 32], vec![2; 32]]).await.unwrap();
+			assert_eq!(loaded.len(), 2);
+		});
+	}
+}
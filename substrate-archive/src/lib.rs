@@ -20,16 +20,28 @@
 mod actors;
 pub mod archive;
 mod database;
+mod decode;
+mod filter;
+#[cfg(feature = "graphql")]
+pub mod graphql;
+#[cfg(feature = "metrics")]
+pub mod metrics;
 mod migrations;
+#[cfg(feature = "rpc")]
+pub mod rpc;
+pub mod sink;
 mod sql_block_builder;
 mod tasks;
 mod traits;
 
-pub use self::actors::System;
+pub use self::actors::{System, SystemConfig};
 pub use self::archive::Builder as ArchiveBuilder;
 pub use self::database::queries;
+pub use self::filter::StorageFilter;
 pub use self::migrations::MigrationConfig;
-pub use self::traits::Archive;
+pub use self::sink::ArchiveSink;
+pub use self::tasks::Snapshots;
+pub use self::traits::{Archive, ShutdownReport, Status, VerificationReport};
 
 #[cfg(feature = "logging")]
 pub use substrate_archive_common::util::init_logger;
@@ -153,8 +165,12 @@ mod test {
 					"
                     TRUNCATE TABLE metadata CASCADE;
                     TRUNCATE TABLE storage CASCADE;
+                    TRUNCATE TABLE child_storage CASCADE;
+                    TRUNCATE TABLE extrinsics CASCADE;
+                    TRUNCATE TABLE events CASCADE;
                     TRUNCATE TABLE blocks CASCADE;
-                    TRUNCATE TABLE _background_tasks
+                    TRUNCATE TABLE _background_tasks;
+                    TRUNCATE TABLE _storage_mode
                     ",
 				)
 				.await
@@ -0,0 +1,41 @@
+// Copyright 2017-2019 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Serves the archive's prometheus metrics over HTTP.
+
+use std::net::SocketAddr;
+
+use tiny_http::{Header, Response, Server};
+
+use substrate_archive_common::{metrics, ArchiveError, Result};
+
+/// Serve metrics in the prometheus text format on `addr`, from a background thread.
+/// Every path responds with the metrics, so the usual `/metrics` works.
+pub fn serve(addr: SocketAddr) -> Result<()> {
+	let server = Server::http(addr).map_err(|e| ArchiveError::from(format!("metrics server: {}", e)))?;
+	let content_type =
+		Header::from_bytes(&b"Content-Type"[..], &b"text/plain; version=0.0.4"[..]).expect("header is valid ascii");
+	log::info!("Serving metrics on http://{}/metrics", addr);
+	std::thread::Builder::new().name("metrics-server".into()).spawn(move || {
+		for request in server.incoming_requests() {
+			let response = Response::from_string(metrics::gather()).with_header(content_type.clone());
+			if let Err(e) = request.respond(response) {
+				log::warn!("Could not respond to metrics request: {}", e);
+			}
+		}
+	})?;
+	Ok(())
+}
@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS child_storage (
+  id SERIAL PRIMARY KEY,
+  block_num int check (block_num >= 0 and block_num < 2147483647) NOT NULL,
+  hash bytea NOT NULL REFERENCES blocks(hash) ON DELETE CASCADE ON UPDATE CASCADE,
+  is_full boolean NOT NULL,
+  -- storage key of the child trie, without the `:child_storage:default:` prefix
+  child_key bytea NOT NULL,
+  key bytea NOT NULL,
+  storage bytea
+);
+
+CREATE UNIQUE INDEX only_unique_hash_child_key_storage ON child_storage (hash, child_key, key, md5(storage));
+CREATE INDEX child_storage_block_num_index ON child_storage (block_num);
+CREATE INDEX child_storage_child_key_index ON child_storage (child_key);
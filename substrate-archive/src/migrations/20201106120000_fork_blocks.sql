@@ -0,0 +1,6 @@
+-- Allow more than one block per height, so that blocks on forks can be indexed
+ALTER TABLE blocks DROP CONSTRAINT IF EXISTS blocks_block_num_key;
+-- whether the block is part of the canonical chain.
+-- Blocks that are not yet finalized may still change from canonical to non-canonical (and vice-versa).
+ALTER TABLE blocks ADD COLUMN is_canonical boolean NOT NULL DEFAULT true;
+CREATE INDEX blocks_non_canonical_index ON blocks (block_num) WHERE NOT is_canonical;
@@ -0,0 +1,19 @@
+CREATE TABLE IF NOT EXISTS extrinsics (
+  hash bytea NOT NULL REFERENCES blocks(hash) ON DELETE CASCADE ON UPDATE CASCADE,
+  block_num int check (block_num >= 0 and block_num < 2147483647) NOT NULL,
+  -- position of the extrinsic in the block
+  index int NOT NULL,
+  -- address of the signer. NULL for unsigned extrinsics
+  signer jsonb,
+  nonce bigint,
+  tip numeric(39, 0),
+  pallet text NOT NULL,
+  call text NOT NULL,
+  args jsonb NOT NULL,
+  -- NULL if the outcome could not be determined from the events of the block
+  success boolean,
+  PRIMARY KEY (hash, index)
+);
+
+CREATE INDEX extrinsics_block_num_index ON extrinsics (block_num);
+CREATE INDEX extrinsics_pallet_call_index ON extrinsics (pallet, call);
@@ -0,0 +1,19 @@
+CREATE TABLE IF NOT EXISTS events (
+  hash bytea NOT NULL REFERENCES blocks(hash) ON DELETE CASCADE ON UPDATE CASCADE,
+  block_num int check (block_num >= 0 and block_num < 2147483647) NOT NULL,
+  -- position of the event in `System::Events`
+  index int NOT NULL,
+  -- index of the extrinsic that emitted the event.
+  -- NULL for events emitted while initializing or finalizing the block
+  extrinsic_index int,
+  -- one of 'ApplyExtrinsic', 'Initialization' or 'Finalization'
+  phase text NOT NULL,
+  pallet text NOT NULL,
+  event text NOT NULL,
+  fields jsonb NOT NULL,
+  PRIMARY KEY (hash, index),
+  FOREIGN KEY (hash, extrinsic_index) REFERENCES extrinsics(hash, index) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+CREATE INDEX events_block_num_index ON events (block_num);
+CREATE INDEX events_pallet_event_index ON events (pallet, event);
@@ -0,0 +1,26 @@
+-- Blocks waiting to be queued for execution.
+-- A row is written in the same transaction that inserts the block,
+-- and is removed in the same transaction that queues its `execute_block` task,
+-- so a block can never be lost between the two.
+CREATE TABLE IF NOT EXISTS _block_outbox (
+  hash bytea PRIMARY KEY NOT NULL REFERENCES blocks(hash) ON DELETE CASCADE ON UPDATE CASCADE,
+  created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE OR REPLACE FUNCTION block_outbox_trigger_fn()
+   RETURNS TRIGGER
+   LANGUAGE PLPGSQL
+AS $BODY$
+BEGIN
+    INSERT INTO _block_outbox (hash) VALUES (NEW.hash) ON CONFLICT DO NOTHING;
+    RETURN NULL;
+END;
+$BODY$;
+
+-- the genesis block is never executed
+CREATE TRIGGER block_outbox_trigger
+    AFTER INSERT
+    ON blocks
+    FOR EACH ROW
+    WHEN (NEW.block_num != 0)
+    EXECUTE PROCEDURE block_outbox_trigger_fn();
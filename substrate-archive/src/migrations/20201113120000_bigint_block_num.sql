@@ -0,0 +1,25 @@
+-- Block numbers are stored as bigint, so that chains with u64 block numbers can be archived
+ALTER TABLE blocks
+  DROP CONSTRAINT IF EXISTS blocks_block_num_check,
+  ALTER COLUMN block_num TYPE bigint,
+  ADD CONSTRAINT blocks_block_num_check CHECK (block_num >= 0);
+
+ALTER TABLE storage
+  DROP CONSTRAINT IF EXISTS storage_block_num_check,
+  ALTER COLUMN block_num TYPE bigint,
+  ADD CONSTRAINT storage_block_num_check CHECK (block_num >= 0);
+
+ALTER TABLE child_storage
+  DROP CONSTRAINT IF EXISTS child_storage_block_num_check,
+  ALTER COLUMN block_num TYPE bigint,
+  ADD CONSTRAINT child_storage_block_num_check CHECK (block_num >= 0);
+
+ALTER TABLE extrinsics
+  DROP CONSTRAINT IF EXISTS extrinsics_block_num_check,
+  ALTER COLUMN block_num TYPE bigint,
+  ADD CONSTRAINT extrinsics_block_num_check CHECK (block_num >= 0);
+
+ALTER TABLE events
+  DROP CONSTRAINT IF EXISTS events_block_num_check,
+  ALTER COLUMN block_num TYPE bigint,
+  ADD CONSTRAINT events_block_num_check CHECK (block_num >= 0);
@@ -0,0 +1,2 @@
+-- Looking up the value of a key at a block, for point-in-time queries of the state
+CREATE INDEX storage_key_block_num_index ON storage (key, block_num);
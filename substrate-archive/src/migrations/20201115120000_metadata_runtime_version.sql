@@ -0,0 +1,3 @@
+-- The full runtime version, as returned by `state_getRuntimeVersion`.
+-- NULL for metadata stored before this column existed.
+ALTER TABLE metadata ADD COLUMN runtime_version jsonb;
@@ -0,0 +1,11 @@
+-- Blocks whose archived storage, applied to the state of their parent,
+-- does not produce the state root in their header
+CREATE TABLE IF NOT EXISTS storage_root_mismatches (
+  hash bytea PRIMARY KEY REFERENCES blocks(hash) ON DELETE CASCADE ON UPDATE CASCADE,
+  block_num bigint NOT NULL CHECK (block_num >= 0),
+  -- state root in the block header
+  expected bytea NOT NULL,
+  -- state root computed from the archived storage
+  computed bytea NOT NULL,
+  checked_at timestamptz NOT NULL DEFAULT now()
+);
@@ -0,0 +1,3 @@
+-- The SCALE-encoded finality justification of a block, if the node stored one.
+-- NULL for blocks without a justification and blocks archived before this column existed.
+ALTER TABLE blocks ADD COLUMN justification bytea;
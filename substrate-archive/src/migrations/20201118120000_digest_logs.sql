@@ -0,0 +1,23 @@
+CREATE TABLE IF NOT EXISTS digest_logs (
+  hash bytea NOT NULL REFERENCES blocks(hash) ON DELETE CASCADE ON UPDATE CASCADE,
+  block_num bigint check (block_num >= 0) NOT NULL,
+  -- position of the item in the digest of the block header
+  index int NOT NULL,
+  -- `PreRuntime`, `Consensus`, `Seal`, `RuntimeEnvironmentUpdated`, `ChangesTrieRoot`, `ChangesTrieSignal` or `Other`
+  kind text NOT NULL,
+  -- consensus engine id, e.g. `BABE`. NULL for items that do not belong to an engine
+  engine text,
+  -- undecoded payload of the item
+  data bytea,
+  -- decoded from BABE and Aura pre-runtime items
+  slot bigint,
+  authority_index int,
+  -- authority id of the author in the BABE or Aura authority set. NULL if it could not be resolved
+  authority bytea,
+  -- account of the author in `Session::Validators`. NULL if the chain has no session pallet
+  author bytea,
+  PRIMARY KEY (hash, index)
+);
+
+CREATE INDEX digest_logs_block_num_index ON digest_logs (block_num);
+CREATE INDEX digest_logs_author_index ON digest_logs (author);
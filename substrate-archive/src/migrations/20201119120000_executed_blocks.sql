@@ -0,0 +1,7 @@
+-- blocks that have been executed while their storage was written to sinks other than Postgres
+CREATE TABLE IF NOT EXISTS executed_blocks (
+  hash bytea PRIMARY KEY REFERENCES blocks(hash) ON DELETE CASCADE ON UPDATE CASCADE,
+  block_num bigint check (block_num >= 0) NOT NULL
+);
+
+CREATE INDEX executed_blocks_block_num_index ON executed_blocks (block_num);
@@ -0,0 +1,10 @@
+-- How storage has been indexed, over every run of the archive on this database.
+-- Verifying storage needs every change of a block in Postgres, which is not the case
+-- once storage has been filtered or only written to other sinks.
+CREATE TABLE IF NOT EXISTS _storage_mode (
+  id boolean PRIMARY KEY DEFAULT true CHECK (id),
+  -- storage was indexed through a storage filter
+  filtered boolean NOT NULL,
+  -- storage was only written to sinks other than Postgres
+  external boolean NOT NULL
+);
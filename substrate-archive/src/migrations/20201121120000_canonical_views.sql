@@ -0,0 +1,11 @@
+-- Decoded data of the blocks on the canonical chain.
+-- Blocks replaced by a reorg are kept with `is_canonical = false`, together with their extrinsics,
+-- events and digest logs. These views leave the data of replaced blocks and other forks out.
+CREATE OR REPLACE VIEW canonical_extrinsics AS
+  SELECT extrinsics.* FROM extrinsics INNER JOIN blocks ON blocks.hash = extrinsics.hash WHERE blocks.is_canonical;
+
+CREATE OR REPLACE VIEW canonical_events AS
+  SELECT events.* FROM events INNER JOIN blocks ON blocks.hash = events.hash WHERE blocks.is_canonical;
+
+CREATE OR REPLACE VIEW canonical_digest_logs AS
+  SELECT digest_logs.* FROM digest_logs INNER JOIN blocks ON blocks.hash = digest_logs.hash WHERE blocks.is_canonical;
@@ -0,0 +1,479 @@
+// Copyright 2017-2019 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! A JSON-RPC server that answers a subset of the Substrate RPC from the archive's Postgres tables,
+//! so that historical queries can be served without an archive node.
+//!
+//! Supported methods:
+//! - `chain_getBlockHash` (`chain_getHead`)
+//! - `chain_getHeader`
+//! - `chain_getBlock`
+//! - `state_getStorage` (`state_getStorageAt`)
+//! - `state_getKeys`
+//! - `state_getMetadata`
+//! - `state_getRuntimeVersion` (`chain_getRuntimeVersion`)
+//! - `rpc_methods`
+//!
+//! Storage is only served for blocks on the canonical chain whose storage has been inserted,
+//! and only for keys that were indexed.
+
+use std::{io::Read, marker::PhantomData, net::SocketAddr, sync::Arc};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+use sp_core::Bytes;
+use sp_runtime::{generic::SignedBlock, traits::Block as BlockT};
+use sqlx::{postgres::PgPoolOptions, PgPool};
+use tiny_http::{Header, Method, Response, Server};
+
+use substrate_archive_common::{ArchiveError, Result};
+
+use crate::{
+	database::{queries, DbConn},
+	sql_block_builder::SqlBlockBuilder,
+};
+
+const METHODS: &[&str] = &[
+	"chain_getBlockHash",
+	"chain_getHead",
+	"chain_getHeader",
+	"chain_getBlock",
+	"chain_getRuntimeVersion",
+	"state_getStorage",
+	"state_getStorageAt",
+	"state_getKeys",
+	"state_getMetadata",
+	"state_getRuntimeVersion",
+	"rpc_methods",
+];
+
+/// Serve JSON-RPC over HTTP on `addr`, reading from the Postgres database at `pg_url`.
+/// Blocks the current thread while requests are answered on one worker thread per CPU.
+pub fn serve<B: BlockT>(addr: SocketAddr, pg_url: &str) -> Result<()> {
+	let workers = num_cpus::get();
+	let pool = smol::block_on(PgPoolOptions::new().max_connections(workers as u32).connect(pg_url))?;
+	let server = Server::http(addr).map_err(|e| ArchiveError::from(format!("rpc server: {}", e)))?;
+	let server = Arc::new(server);
+	let rpc = Arc::new(Rpc::<B>::new(pool));
+	log::info!("Serving JSON-RPC on http://{}", addr);
+
+	let handles = (0..workers)
+		.map(|i| {
+			let (server, rpc) = (server.clone(), rpc.clone());
+			std::thread::Builder::new().name(format!("rpc-worker-{}", i)).spawn(move || {
+				for request in server.incoming_requests() {
+					rpc.respond(request);
+				}
+			})
+		})
+		.collect::<Result<Vec<_>, _>>()?;
+	for handle in handles {
+		if handle.join().is_err() {
+			log::error!("RPC worker panicked");
+		}
+	}
+	Ok(())
+}
+
+/// Errors returned to the caller in the `error` object of a response
+#[derive(Debug)]
+enum RpcError {
+	Parse,
+	InvalidRequest,
+	MethodNotFound(String),
+	InvalidParams(String),
+	UnknownBlock(String),
+	/// the block is archived, but its storage has not been inserted yet
+	StateUnavailable(String),
+	/// the runtime was archived before full runtime versions were stored
+	RuntimeVersionUnavailable(u32),
+	Internal(String),
+}
+
+impl RpcError {
+	fn to_json(&self) -> Value {
+		let (code, message) = match self {
+			RpcError::Parse => (-32700, "Parse error".to_string()),
+			RpcError::InvalidRequest => (-32600, "Invalid request".to_string()),
+			RpcError::MethodNotFound(m) => (-32601, format!("Method not found: {}", m)),
+			RpcError::InvalidParams(e) => (-32602, format!("Invalid params: {}", e)),
+			RpcError::UnknownBlock(b) => (-32000, format!("Unknown block: {}", b)),
+			RpcError::StateUnavailable(b) => (-32001, format!("State of block {} has not been archived yet", b)),
+			RpcError::RuntimeVersionUnavailable(spec) => {
+				(-32002, format!("Runtime version of spec {} was archived without its full runtime version", spec))
+			}
+			RpcError::Internal(e) => (-32603, format!("Internal error: {}", e)),
+		};
+		json!({ "code": code, "message": message })
+	}
+}
+
+impl From<ArchiveError> for RpcError {
+	fn from(e: ArchiveError) -> RpcError {
+		RpcError::Internal(e.to_string())
+	}
+}
+
+impl From<sqlx::Error> for RpcError {
+	fn from(e: sqlx::Error) -> RpcError {
+		RpcError::Internal(e.to_string())
+	}
+}
+
+impl From<codec::Error> for RpcError {
+	fn from(e: codec::Error) -> RpcError {
+		RpcError::Internal(format!("decode {}", e))
+	}
+}
+
+impl From<serde_json::Error> for RpcError {
+	fn from(e: serde_json::Error) -> RpcError {
+		RpcError::Internal(e.to_string())
+	}
+}
+
+/// A JSON-RPC 2.0 request. Requests without an `id` are notifications, and get no response.
+#[derive(Deserialize)]
+struct Call {
+	id: Option<Value>,
+	method: String,
+	#[serde(default)]
+	params: Value,
+}
+
+/// Positional parameters of a call
+struct Params(Value);
+
+impl Params {
+	/// The parameter at `index`. Missing and `null` parameters are `None`.
+	fn get<T: DeserializeOwned>(&self, index: usize) -> Result<Option<T>, RpcError> {
+		let param = match &self.0 {
+			Value::Array(params) => params.get(index),
+			Value::Null => None,
+			_ => return Err(RpcError::InvalidParams("parameters must be an array".into())),
+		};
+		match param {
+			None | Some(Value::Null) => Ok(None),
+			Some(p) => serde_json::from_value(p.clone()).map(Some).map_err(|e| RpcError::InvalidParams(e.to_string())),
+		}
+	}
+
+	fn required<T: DeserializeOwned>(&self, index: usize, name: &str) -> Result<T, RpcError> {
+		self.get(index)?.ok_or_else(|| RpcError::InvalidParams(format!("missing `{}`", name)))
+	}
+}
+
+/// A block number, as a number or a hex string
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum NumberOrHex {
+	Number(u64),
+	Hex(String),
+}
+
+impl NumberOrHex {
+	fn into_u64(self) -> Result<u64, RpcError> {
+		match self {
+			NumberOrHex::Number(n) => Ok(n),
+			NumberOrHex::Hex(h) => u64::from_str_radix(h.trim_start_matches("0x"), 16)
+				.map_err(|_| RpcError::InvalidParams(format!("`{}` is not a block number", h))),
+		}
+	}
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ListOrValue<T> {
+	Value(T),
+	List(Vec<T>),
+}
+
+struct Rpc<B: BlockT> {
+	pool: PgPool,
+	_marker: PhantomData<B>,
+}
+
+impl<B: BlockT> Rpc<B> {
+	fn new(pool: PgPool) -> Self {
+		Self { pool, _marker: PhantomData }
+	}
+
+	fn respond(&self, mut request: tiny_http::Request) {
+		let response = if request.method() != &Method::Post {
+			Response::from_string("Only POST is supported").with_status_code(405)
+		} else {
+			let mut body = String::new();
+			let reply = match request.as_reader().read_to_string(&mut body) {
+				Ok(_) => smol::block_on(self.handle_body(&body)),
+				Err(_) => Some(failure(Value::Null, RpcError::Parse)),
+			};
+			let reply = reply.map(|r| r.to_string()).unwrap_or_default();
+			let content_type =
+				Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).expect("header is valid ascii");
+			Response::from_string(reply).with_header(content_type)
+		};
+		if let Err(e) = request.respond(response) {
+			log::warn!("Could not respond to RPC request: {}", e);
+		}
+	}
+
+	/// Handle a single call or a batch of calls
+	async fn handle_body(&self, body: &str) -> Option<Value> {
+		match serde_json::from_str::<Value>(body) {
+			Err(_) => Some(failure(Value::Null, RpcError::Parse)),
+			Ok(Value::Array(calls)) if calls.is_empty() => Some(failure(Value::Null, RpcError::InvalidRequest)),
+			Ok(Value::Array(calls)) => {
+				let mut responses = Vec::with_capacity(calls.len());
+				for call in calls.into_iter() {
+					responses.extend(self.handle_call(call).await);
+				}
+				if responses.is_empty() {
+					None
+				} else {
+					Some(Value::Array(responses))
+				}
+			}
+			Ok(call) => self.handle_call(call).await,
+		}
+	}
+
+	async fn handle_call(&self, call: Value) -> Option<Value> {
+		let call: Call = match serde_json::from_value(call) {
+			Ok(c) => c,
+			Err(_) => return Some(failure(Value::Null, RpcError::InvalidRequest)),
+		};
+		// every method is read-only, so notifications can be skipped entirely
+		let id = call.id?;
+		let response = match self.call(&call.method, Params(call.params)).await {
+			Ok(result) => json!({ "jsonrpc": "2.0", "result": result, "id": id }),
+			Err(e) => {
+				if let RpcError::Internal(e) = &e {
+					log::error!("RPC call {} failed: {}", call.method, e);
+				}
+				failure(id, e)
+			}
+		};
+		Some(response)
+	}
+
+	async fn call(&self, method: &str, params: Params) -> Result<Value, RpcError> {
+		let mut conn = self.pool.acquire().await?;
+		match method {
+			"chain_getBlockHash" | "chain_getHead" => self.block_hash(&mut conn, params.get(0)?).await,
+			"chain_getHeader" => {
+				let block = self.block(&mut conn, params.get(0)?).await?;
+				to_json(block.map(|b| b.block.header().clone()))
+			}
+			"chain_getBlock" => to_json(self.block(&mut conn, params.get(0)?).await?),
+			"state_getStorage" | "state_getStorageAt" => {
+				let key: Bytes = params.required(0, "key")?;
+				let num = self.state_block_num(&mut conn, params.get(1)?).await?;
+				to_json(queries::storage_at(&mut conn, &key, num).await?.map(Bytes))
+			}
+			"state_getKeys" => {
+				let prefix: Bytes = params.required(0, "prefix")?;
+				let num = self.state_block_num(&mut conn, params.get(1)?).await?;
+				let keys = queries::keys_with_prefix_at(&mut conn, &prefix, num).await?;
+				to_json(keys.into_iter().map(Bytes).collect::<Vec<_>>())
+			}
+			"state_getMetadata" => {
+				let hash = self.block_hash_or_best(&mut conn, params.get(0)?).await?;
+				let meta = queries::get_metadata_at(&mut conn, &hash).await?;
+				to_json(Bytes(meta.ok_or_else(|| unknown_block(&hash))?))
+			}
+			"state_getRuntimeVersion" | "chain_getRuntimeVersion" => {
+				let hash = self.block_hash_or_best(&mut conn, params.get(0)?).await?;
+				match queries::get_runtime_version_at(&mut conn, &hash).await? {
+					Some((_, Some(version))) => Ok(version),
+					Some((spec, None)) => Err(RpcError::RuntimeVersionUnavailable(spec)),
+					None => Err(unknown_block(&hash)),
+				}
+			}
+			"rpc_methods" => Ok(json!({ "version": 1, "methods": METHODS })),
+			m => Err(RpcError::MethodNotFound(m.to_string())),
+		}
+	}
+
+	async fn block_hash(&self, conn: &mut DbConn, number: Option<ListOrValue<NumberOrHex>>) -> Result<Value, RpcError> {
+		match number {
+			None => Ok(hash_to_json(queries::best_canonical_hash(conn).await?)),
+			Some(ListOrValue::Value(n)) => Ok(hash_to_json(queries::canonical_hash(conn, n.into_u64()?).await?)),
+			Some(ListOrValue::List(nums)) => {
+				let mut hashes = Vec::with_capacity(nums.len());
+				for n in nums.into_iter() {
+					hashes.push(hash_to_json(queries::canonical_hash(conn, n.into_u64()?).await?));
+				}
+				Ok(Value::Array(hashes))
+			}
+		}
+	}
+
+	/// Rebuild block `hash`, or the best block if `hash` is `None`
+	async fn block(&self, conn: &mut DbConn, hash: Option<B::Hash>) -> Result<Option<SignedBlock<B>>, RpcError> {
+		let hash = match hash {
+			Some(h) => h.as_ref().to_vec(),
+			None => match queries::best_canonical_hash(conn).await? {
+				Some(h) => h,
+				None => return Ok(None),
+			},
+		};
+		match queries::get_full_block_by_hash(conn, &hash).await? {
+			Some(block) => {
+				let justification = block.justification.clone();
+				Ok(Some(SignedBlock { block: SqlBlockBuilder::<B>::with_single(block)?.0, justification }))
+			}
+			None => Ok(None),
+		}
+	}
+
+	async fn block_hash_or_best(&self, conn: &mut DbConn, hash: Option<B::Hash>) -> Result<Vec<u8>, RpcError> {
+		match hash {
+			Some(h) => Ok(h.as_ref().to_vec()),
+			None => queries::best_canonical_hash(conn)
+				.await?
+				.ok_or_else(|| RpcError::UnknownBlock("no blocks have been archived".into())),
+		}
+	}
+
+	/// The number of the block to read state at. State is only served for the canonical chain,
+	/// and only for blocks whose storage has been inserted.
+	/// Defaults to the highest block whose storage has been inserted.
+	async fn state_block_num(&self, conn: &mut DbConn, hash: Option<B::Hash>) -> Result<u64, RpcError> {
+		let hash = match hash {
+			Some(h) => h.as_ref().to_vec(),
+			None => {
+				return queries::best_executed_block(conn)
+					.await?
+					.map(|(num, _)| num)
+					.ok_or_else(|| RpcError::UnknownBlock("no blocks have been executed".into()))
+			}
+		};
+		let num = queries::canonical_block_num(conn, &hash).await?.ok_or_else(|| unknown_block(&hash))?;
+		if !queries::has_storage(conn, &hash).await? {
+			return Err(RpcError::StateUnavailable(format!("0x{}", hex::encode(&hash))));
+		}
+		Ok(num)
+	}
+}
+
+fn failure(id: Value, error: RpcError) -> Value {
+	json!({ "jsonrpc": "2.0", "error": error.to_json(), "id": id })
+}
+
+fn unknown_block(hash: &[u8]) -> RpcError {
+	RpcError::UnknownBlock(format!("0x{}", hex::encode(hash)))
+}
+
+fn hash_to_json(hash: Option<Vec<u8>>) -> Value {
+	hash.map(|h| Value::String(format!("0x{}", hex::encode(h)))).unwrap_or(Value::Null)
+}
+
+fn to_json<T: Serialize>(value: T) -> Result<Value, RpcError> {
+	Ok(serde_json::to_value(value)?)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use polkadot_service::Block;
+	use sqlx::postgres::PgPoolOptions;
+
+	/// An RPC server whose pool never connects, for requests that do not reach the database
+	fn rpc() -> Rpc<Block> {
+		Rpc::new(PgPoolOptions::new().connect_lazy("postgres://localhost/archive").unwrap())
+	}
+
+	fn error_code(response: &Value) -> i64 {
+		response["error"]["code"].as_i64().unwrap()
+	}
+
+	#[test]
+	fn should_get_params() {
+		let params = Params(json!([1, null, "0x02"]));
+		assert_eq!(params.get::<u64>(0).unwrap(), Some(1));
+		assert_eq!(params.get::<u64>(1).unwrap(), None);
+		assert_eq!(params.get::<u64>(3).unwrap(), None);
+		assert_eq!(params.required::<String>(2, "hash").unwrap(), "0x02");
+		assert!(matches!(params.required::<u64>(1, "number"), Err(RpcError::InvalidParams(_))));
+		assert!(matches!(params.get::<u64>(2), Err(RpcError::InvalidParams(_))));
+		assert_eq!(Params(Value::Null).get::<u64>(0).unwrap(), None);
+		assert!(matches!(Params(json!({ "number": 1 })).get::<u64>(0), Err(RpcError::InvalidParams(_))));
+	}
+
+	#[test]
+	fn should_parse_block_numbers() {
+		let number = |v: Value| serde_json::from_value::<NumberOrHex>(v).unwrap().into_u64();
+		assert_eq!(number(json!(42)).unwrap(), 42);
+		assert_eq!(number(json!("0x2a")).unwrap(), 42);
+		assert_eq!(number(json!("2a")).unwrap(), 42);
+		assert!(matches!(number(json!("0xzz")), Err(RpcError::InvalidParams(_))));
+	}
+
+	#[test]
+	fn should_handle_malformed_bodies() {
+		let rpc = rpc();
+		let response = smol::block_on(rpc.handle_body("{")).unwrap();
+		assert_eq!(error_code(&response), -32700);
+		let response = smol::block_on(rpc.handle_body("[]")).unwrap();
+		assert_eq!(error_code(&response), -32600);
+		let response = smol::block_on(rpc.handle_body(r#"{ "jsonrpc": "2.0", "id": 1 }"#)).unwrap();
+		assert_eq!(error_code(&response), -32600);
+	}
+
+	#[test]
+	fn should_not_respond_to_notifications() {
+		let rpc = rpc();
+		let notification = r#"{ "jsonrpc": "2.0", "method": "chain_getBlockHash", "params": [] }"#;
+		assert_eq!(smol::block_on(rpc.handle_body(notification)), None);
+		let batch = format!("[{}, {}]", notification, notification);
+		assert_eq!(smol::block_on(rpc.handle_body(&batch)), None);
+	}
+
+	#[test]
+	fn should_respond_to_each_call_of_a_batch() {
+		let rpc = rpc();
+		let batch = r#"[
+			{ "jsonrpc": "2.0", "method": "chain_getBlockHash", "params": [] },
+			1,
+			{ "jsonrpc": "2.0", "id": 2 }
+		]"#;
+		let response = smol::block_on(rpc.handle_body(batch)).unwrap();
+		let responses = response.as_array().unwrap();
+		assert_eq!(responses.len(), 2);
+		assert!(responses.iter().all(|r| error_code(r) == -32600));
+		assert_eq!(responses[1]["id"], json!(null));
+	}
+
+	#[test]
+	fn should_not_serve_partial_runtime_versions() {
+		crate::initialize();
+		let _guard = crate::TestGuard::lock();
+		let rpc = Rpc::<Block>::new(crate::PG_POOL.clone());
+		smol::block_on(async {
+			// the metadata of the test guard has no full runtime version
+			let version = rpc.call("state_getRuntimeVersion", Params(Value::Null)).await;
+			assert!(matches!(version, Err(RpcError::RuntimeVersionUnavailable(0))));
+
+			let full = json!({ "specName": "kusama", "specVersion": 0 });
+			sqlx::query("UPDATE metadata SET runtime_version = $1 WHERE version = 0")
+				.bind(&full)
+				.execute(&*crate::PG_POOL)
+				.await
+				.unwrap();
+			assert_eq!(rpc.call("state_getRuntimeVersion", Params(Value::Null)).await.unwrap(), full);
+		});
+	}
+}
@@ -0,0 +1,237 @@
+// Copyright 2017-2019 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Destinations for indexed data.
+//! By default, everything is written to Postgres. Postgres may be replaced by, or combined with, other sinks.
+//! Postgres always holds the task queue, blocks and metadata, since the archive needs them to know what is left to do.
+
+mod file;
+
+use std::sync::Arc;
+
+use xtra::prelude::*;
+
+use sp_runtime::traits::Block as BlockT;
+
+use substrate_archive_common::{
+	types::{BatchBlock, BatchDecoded, BatchStorage, Metadata},
+	Result,
+};
+
+pub use self::file::{FileFormat, FileSink};
+use crate::actors::{ActorPool, DatabaseActor, Executed};
+
+/// A destination for the data the archive indexes.
+/// Batches are shared between all sinks, so a sink that needs to own a batch has to clone it.
+#[async_trait::async_trait]
+pub trait ArchiveSink<B: BlockT>: Send + Sync {
+	/// Write a batch of blocks.
+	/// The metadata of every runtime version in the batch has been written before the blocks.
+	async fn write_blocks(&self, blocks: Arc<BatchBlock<B>>) -> Result<()>;
+
+	/// Write the storage changes of a batch of executed blocks.
+	async fn write_storage(&self, storage: Arc<BatchStorage<B>>) -> Result<()>;
+
+	/// Write the metadata of a runtime version.
+	async fn write_metadata(&self, metadata: Arc<Metadata>) -> Result<()>;
+
+	/// Write the decoded extrinsics, events and digest logs of a batch of blocks.
+	/// Sinks that only keep raw data may ignore them.
+	async fn write_decoded(&self, _decoded: Arc<BatchDecoded<B>>) -> Result<()> {
+		Ok(())
+	}
+
+	/// Called once on shutdown, after everything has been written.
+	async fn flush(&self) -> Result<()> {
+		Ok(())
+	}
+}
+
+/// Take the batch out of the `Arc`, or clone it if another sink still holds it.
+fn owned<T: Clone>(shared: Arc<T>) -> T {
+	Arc::try_unwrap(shared).unwrap_or_else(|shared| (*shared).clone())
+}
+
+/// Writes everything to the Postgres database through the pool of `DatabaseActor`s.
+pub(crate) struct PostgresSink<B: BlockT + Unpin> {
+	db: Address<ActorPool<DatabaseActor<B>>>,
+}
+
+impl<B: BlockT + Unpin> PostgresSink<B> {
+	pub fn new(db: Address<ActorPool<DatabaseActor<B>>>) -> Self {
+		Self { db }
+	}
+}
+
+#[async_trait::async_trait]
+impl<B: BlockT + Unpin> ArchiveSink<B> for PostgresSink<B>
+where
+	B::Hash: Unpin,
+{
+	async fn write_blocks(&self, blocks: Arc<BatchBlock<B>>) -> Result<()> {
+		self.db.send(owned(blocks).into()).await?.await
+	}
+
+	async fn write_storage(&self, storage: Arc<BatchStorage<B>>) -> Result<()> {
+		self.db.send(owned(storage).into()).await?.await
+	}
+
+	async fn write_metadata(&self, metadata: Arc<Metadata>) -> Result<()> {
+		self.db.send(owned(metadata).into()).await?.await
+	}
+
+	async fn write_decoded(&self, decoded: Arc<BatchDecoded<B>>) -> Result<()> {
+		self.db.send(owned(decoded).into()).await?.await
+	}
+}
+
+/// Keeps the blocks and metadata the archive needs in Postgres when Postgres is not a sink.
+/// Storage is not inserted, the blocks are only recorded as executed.
+pub(crate) struct PostgresIndex<B: BlockT + Unpin> {
+	db: Address<ActorPool<DatabaseActor<B>>>,
+}
+
+impl<B: BlockT + Unpin> PostgresIndex<B> {
+	pub fn new(db: Address<ActorPool<DatabaseActor<B>>>) -> Self {
+		Self { db }
+	}
+}
+
+#[async_trait::async_trait]
+impl<B: BlockT + Unpin> ArchiveSink<B> for PostgresIndex<B>
+where
+	B::Hash: Unpin,
+{
+	async fn write_blocks(&self, blocks: Arc<BatchBlock<B>>) -> Result<()> {
+		self.db.send(owned(blocks).into()).await?.await
+	}
+
+	async fn write_storage(&self, storage: Arc<BatchStorage<B>>) -> Result<()> {
+		let blocks = storage.inner().iter().map(|s| (s.block_num(), *s.hash())).collect();
+		self.db.send(Executed { blocks }.into()).await?.await
+	}
+
+	async fn write_metadata(&self, metadata: Arc<Metadata>) -> Result<()> {
+		self.db.send(owned(metadata).into()).await?.await
+	}
+}
+
+/// Fans writes out to every sink.
+/// Each batch is shared between the sinks and dropped here before the writes start,
+/// so a sink that is the last to hold a batch can take it without cloning.
+/// A failing sink does not stop the others from being written to. The first error is returned.
+pub(crate) struct Sinks<B: BlockT> {
+	sinks: Arc<Vec<Arc<dyn ArchiveSink<B>>>>,
+}
+
+impl<B: BlockT> Clone for Sinks<B> {
+	fn clone(&self) -> Self {
+		Self { sinks: self.sinks.clone() }
+	}
+}
+
+impl<B: BlockT> Sinks<B> {
+	pub fn new(sinks: Vec<Arc<dyn ArchiveSink<B>>>) -> Self {
+		Self { sinks: Arc::new(sinks) }
+	}
+
+	pub async fn write_blocks(&self, blocks: BatchBlock<B>) -> Result<()> {
+		let blocks = Arc::new(blocks);
+		let writes = self.sinks.iter().map(|s| s.write_blocks(blocks.clone())).collect::<Vec<_>>();
+		std::mem::drop(blocks);
+		first_error(futures::future::join_all(writes).await)
+	}
+
+	pub async fn write_storage(&self, storage: BatchStorage<B>) -> Result<()> {
+		let storage = Arc::new(storage);
+		let writes = self.sinks.iter().map(|s| s.write_storage(storage.clone())).collect::<Vec<_>>();
+		std::mem::drop(storage);
+		first_error(futures::future::join_all(writes).await)
+	}
+
+	pub async fn write_metadata(&self, metadata: Metadata) -> Result<()> {
+		let metadata = Arc::new(metadata);
+		let writes = self.sinks.iter().map(|s| s.write_metadata(metadata.clone())).collect::<Vec<_>>();
+		std::mem::drop(metadata);
+		first_error(futures::future::join_all(writes).await)
+	}
+
+	pub async fn write_decoded(&self, decoded: BatchDecoded<B>) -> Result<()> {
+		let decoded = Arc::new(decoded);
+		let writes = self.sinks.iter().map(|s| s.write_decoded(decoded.clone())).collect::<Vec<_>>();
+		std::mem::drop(decoded);
+		first_error(futures::future::join_all(writes).await)
+	}
+
+	pub async fn flush(&self) -> Result<()> {
+		first_error(futures::future::join_all(self.sinks.iter().map(|s| s.flush())).await)
+	}
+}
+
+fn first_error(results: Vec<Result<()>>) -> Result<()> {
+	results.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use parking_lot::Mutex;
+	use polkadot_service::Block;
+
+	/// Records the metadata versions written to it, and whether it could take the batch without cloning.
+	#[derive(Default)]
+	struct Recorder {
+		written: Mutex<Vec<(u32, bool)>>,
+		fail: bool,
+	}
+
+	#[async_trait::async_trait]
+	impl ArchiveSink<Block> for Recorder {
+		async fn write_blocks(&self, _: Arc<BatchBlock<Block>>) -> Result<()> {
+			Ok(())
+		}
+
+		async fn write_storage(&self, _: Arc<BatchStorage<Block>>) -> Result<()> {
+			Ok(())
+		}
+
+		async fn write_metadata(&self, metadata: Arc<Metadata>) -> Result<()> {
+			if self.fail {
+				return Err("sink failed".into());
+			}
+			self.written.lock().push((metadata.version(), Arc::strong_count(&metadata) == 1));
+			Ok(())
+		}
+	}
+
+	#[test]
+	fn should_write_to_every_sink_and_return_errors() {
+		let failing = Arc::new(Recorder { fail: true, ..Default::default() });
+		let recorder = Arc::new(Recorder::default());
+		let sinks = Sinks::<Block>::new(vec![failing, recorder.clone()]);
+		let result = smol::block_on(sinks.write_metadata(Metadata::new(7, vec![1, 2, 3])));
+		assert!(result.is_err());
+		assert_eq!(recorder.written.lock().iter().map(|(v, _)| *v).collect::<Vec<_>>(), vec![7]);
+	}
+
+	#[test]
+	fn should_not_share_batch_with_single_sink() {
+		let recorder = Arc::new(Recorder::default());
+		let sinks = Sinks::<Block>::new(vec![recorder.clone()]);
+		smol::block_on(sinks.write_metadata(Metadata::new(9, Vec::new()))).unwrap();
+		assert_eq!(recorder.written.lock().as_slice(), &[(9, true)]);
+	}
+}
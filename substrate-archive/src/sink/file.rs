@@ -0,0 +1,553 @@
+// Copyright 2017-2019 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! A sink that exports blocks, storage and metadata to files, for ingestion by other tools.
+//! Blocks and storage are buffered until a segment holds enough blocks or bytes, and every segment is one file
+//! named after the range of blocks it contains: `blocks/{first}-{last}.ndjson` and `storage/{first}-{last}.ndjson`.
+//! Whatever is buffered is written when the sink is flushed on shutdown.
+//! Metadata is written right away, one file per runtime version: `metadata/{version}.ndjson`.
+
+#[cfg(feature = "parquet")]
+mod parquet;
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use codec::Encode;
+use hashbrown::HashSet;
+use parking_lot::Mutex;
+use serde::{Serialize, Serializer};
+
+use sp_runtime::traits::{Block as BlockT, Header as _, SaturatedConversion};
+
+use substrate_archive_common::{
+	models::ChildStorageModel,
+	types::{BatchBlock, BatchStorage, Metadata},
+	ArchiveError, Result,
+};
+
+use super::ArchiveSink;
+
+/// Format of the exported files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileFormat {
+	/// Newline-delimited JSON. Byte strings are hex-encoded.
+	Json,
+	/// Apache Parquet
+	#[cfg(feature = "parquet")]
+	Parquet,
+}
+
+impl FileFormat {
+	fn extension(&self) -> &'static str {
+		match self {
+			FileFormat::Json => "ndjson",
+			#[cfg(feature = "parquet")]
+			FileFormat::Parquet => "parquet",
+		}
+	}
+}
+
+impl FromStr for FileFormat {
+	type Err = ArchiveError;
+
+	fn from_str(s: &str) -> Result<Self> {
+		match s.to_ascii_lowercase().as_str() {
+			"json" | "ndjson" => Ok(FileFormat::Json),
+			#[cfg(feature = "parquet")]
+			"parquet" => Ok(FileFormat::Parquet),
+			_ => Err(ArchiveError::from(format!("unknown export format `{}`", s))),
+		}
+	}
+}
+
+/// Default number of blocks in a segment
+const SEGMENT_BLOCKS: usize = 10_000;
+/// Default size of the rows in a segment, in bytes
+const SEGMENT_BYTES: usize = 128 * 1024 * 1024;
+
+/// Writes everything it receives to files in a directory
+#[derive(Debug, Clone)]
+pub struct FileSink {
+	dir: PathBuf,
+	format: FileFormat,
+	/// a segment is written once it holds this many blocks
+	segment_blocks: usize,
+	/// a segment is written once its rows are this many bytes
+	segment_bytes: usize,
+	blocks: Arc<Mutex<Segment<BlockRow>>>,
+	storage: Arc<Mutex<Segment<StorageRow>>>,
+}
+
+impl FileSink {
+	/// Create a sink that writes files of `format` into `dir`.
+	/// The directory is created if it does not exist yet.
+	pub fn new<P: AsRef<Path>>(dir: P, format: FileFormat) -> Result<Self> {
+		let dir = dir.as_ref().to_path_buf();
+		for kind in &["blocks", "storage", "metadata"] {
+			std::fs::create_dir_all(dir.join(kind))?;
+		}
+		Ok(Self {
+			dir,
+			format,
+			segment_blocks: SEGMENT_BLOCKS,
+			segment_bytes: SEGMENT_BYTES,
+			blocks: Arc::new(Mutex::new(Segment::default())),
+			storage: Arc::new(Mutex::new(Segment::default())),
+		})
+	}
+
+	/// Start a new segment once the current one holds `blocks` blocks, or its rows are `bytes` bytes.
+	///
+	/// # Default
+	/// Defaults to 10,000 blocks or 128 MiB
+	pub fn segment_size(mut self, blocks: usize, bytes: usize) -> Self {
+		self.segment_blocks = blocks.max(1);
+		self.segment_bytes = bytes.max(1);
+		self
+	}
+
+	/// Add `rows` to `segment`. Returns the rows of the segment if it is full.
+	fn buffer<T: Row>(&self, segment: &Mutex<Segment<T>>, rows: Vec<T>) -> Option<Vec<T>> {
+		let mut segment = segment.lock();
+		segment.extend(rows);
+		if segment.blocks.len() >= self.segment_blocks || segment.bytes >= self.segment_bytes {
+			Some(segment.take())
+		} else {
+			None
+		}
+	}
+
+	/// Create a new segment of `kind`. Never overwrites an existing segment:
+	/// if a segment for the same range exists, a numeric suffix is added.
+	fn create_segment(&self, kind: &str, name: &str) -> Result<File> {
+		let ext = self.format.extension();
+		let mut path = self.dir.join(kind).join(format!("{}.{}", name, ext));
+		let mut n = 0;
+		loop {
+			match OpenOptions::new().write(true).create_new(true).open(&path) {
+				Ok(file) => return Ok(file),
+				Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+					n += 1;
+					path = self.dir.join(kind).join(format!("{}.{}.{}", name, n, ext));
+				}
+				Err(e) => return Err(e.into()),
+			}
+		}
+	}
+
+	/// Write `rows` to a new segment called `name`.
+	fn write_rows(&self, name: &str, rows: Rows) -> Result<()> {
+		if rows.is_empty() {
+			return Ok(());
+		}
+		let file = self.create_segment(rows.kind(), name)?;
+		match self.format {
+			FileFormat::Json => write_ndjson(file, rows),
+			#[cfg(feature = "parquet")]
+			FileFormat::Parquet => self::parquet::write(file, rows),
+		}
+	}
+
+	/// Write the blocks or storage of a full segment, named after the range of blocks it contains.
+	async fn write_segment<T: Row + Send + 'static>(&self, rows: Vec<T>, kind: fn(Vec<T>) -> Rows) -> Result<()> {
+		let name = range_name(rows.iter().map(Row::block_num));
+		let sink = self.clone();
+		smol::unblock(move || sink.write_rows(&name, kind(rows))).await
+	}
+}
+
+fn write_ndjson(file: File, rows: Rows) -> Result<()> {
+	let mut writer = BufWriter::new(file);
+	match rows {
+		Rows::Blocks(rows) => write_lines(&mut writer, rows)?,
+		Rows::Storage(rows) => write_lines(&mut writer, rows)?,
+		Rows::Metadata(rows) => write_lines(&mut writer, rows)?,
+	}
+	writer.flush()?;
+	Ok(())
+}
+
+/// A row of a segment
+trait Row {
+	fn block_num(&self) -> u64;
+	/// approximate size of the row, in bytes
+	fn size(&self) -> usize;
+}
+
+/// Rows buffered for the next segment
+#[derive(Debug)]
+struct Segment<T> {
+	rows: Vec<T>,
+	/// the blocks the rows belong to
+	blocks: HashSet<u64>,
+	bytes: usize,
+}
+
+impl<T> Default for Segment<T> {
+	fn default() -> Self {
+		Self { rows: Vec::new(), blocks: HashSet::new(), bytes: 0 }
+	}
+}
+
+impl<T: Row> Segment<T> {
+	fn extend(&mut self, rows: Vec<T>) {
+		for row in rows {
+			self.blocks.insert(row.block_num());
+			self.bytes += row.size();
+			self.rows.push(row);
+		}
+	}
+
+	/// Take the rows out of the segment, starting a new one
+	fn take(&mut self) -> Vec<T> {
+		self.blocks.clear();
+		self.bytes = 0;
+		std::mem::take(&mut self.rows)
+	}
+}
+
+fn write_lines<W: Write, T: Serialize>(writer: &mut W, rows: Vec<T>) -> Result<()> {
+	for row in rows.iter() {
+		serde_json::to_writer(&mut *writer, row)?;
+		writer.write_all(b"\n")?;
+	}
+	Ok(())
+}
+
+/// Name of a segment containing blocks `first` to `last`.
+/// Numbers are zero-padded so that segments sort by block number.
+fn range_name(nums: impl Iterator<Item = u64>) -> String {
+	let (first, last) = nums.fold((u64::MAX, 0), |(min, max), n| (min.min(n), max.max(n)));
+	format!("{:010}-{:010}", first, last)
+}
+
+fn as_hex<S: Serializer>(bytes: &[u8], s: S) -> std::result::Result<S::Ok, S::Error> {
+	s.serialize_str(&format!("0x{}", hex::encode(bytes)))
+}
+
+fn as_hex_opt<S: Serializer>(bytes: &Option<Vec<u8>>, s: S) -> std::result::Result<S::Ok, S::Error> {
+	match bytes {
+		Some(b) => as_hex(b, s),
+		None => s.serialize_none(),
+	}
+}
+
+/// A batch of rows of one kind
+enum Rows {
+	Blocks(Vec<BlockRow>),
+	Storage(Vec<StorageRow>),
+	Metadata(Vec<MetadataRow>),
+}
+
+impl Rows {
+	/// directory the segments of these rows are written to
+	fn kind(&self) -> &'static str {
+		match self {
+			Rows::Blocks(_) => "blocks",
+			Rows::Storage(_) => "storage",
+			Rows::Metadata(_) => "metadata",
+		}
+	}
+
+	fn is_empty(&self) -> bool {
+		match self {
+			Rows::Blocks(r) => r.is_empty(),
+			Rows::Storage(r) => r.is_empty(),
+			Rows::Metadata(r) => r.is_empty(),
+		}
+	}
+}
+
+#[derive(Debug, Serialize)]
+struct BlockRow {
+	block_num: u64,
+	#[serde(serialize_with = "as_hex")]
+	hash: Vec<u8>,
+	#[serde(serialize_with = "as_hex")]
+	parent_hash: Vec<u8>,
+	#[serde(serialize_with = "as_hex")]
+	state_root: Vec<u8>,
+	#[serde(serialize_with = "as_hex")]
+	extrinsics_root: Vec<u8>,
+	#[serde(serialize_with = "as_hex")]
+	digest: Vec<u8>,
+	#[serde(serialize_with = "as_hex")]
+	extrinsics: Vec<u8>,
+	spec: u32,
+	is_canonical: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct StorageRow {
+	block_num: u64,
+	#[serde(serialize_with = "as_hex")]
+	hash: Vec<u8>,
+	is_full: bool,
+	/// storage key of the child trie, if the change is in a child trie
+	#[serde(serialize_with = "as_hex_opt")]
+	child: Option<Vec<u8>>,
+	#[serde(serialize_with = "as_hex")]
+	key: Vec<u8>,
+	/// `None` if the key was deleted
+	#[serde(serialize_with = "as_hex_opt")]
+	value: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Serialize)]
+struct MetadataRow {
+	version: u32,
+	#[serde(serialize_with = "as_hex")]
+	meta: Vec<u8>,
+}
+
+impl Row for BlockRow {
+	fn block_num(&self) -> u64 {
+		self.block_num
+	}
+
+	fn size(&self) -> usize {
+		self.hash.len()
+			+ self.parent_hash.len()
+			+ self.state_root.len()
+			+ self.extrinsics_root.len()
+			+ self.digest.len()
+			+ self.extrinsics.len()
+	}
+}
+
+impl Row for StorageRow {
+	fn block_num(&self) -> u64 {
+		self.block_num
+	}
+
+	fn size(&self) -> usize {
+		self.hash.len()
+			+ self.child.as_ref().map(Vec::len).unwrap_or(0)
+			+ self.key.len()
+			+ self.value.as_ref().map(Vec::len).unwrap_or(0)
+	}
+}
+
+#[async_trait::async_trait]
+impl<B: BlockT> ArchiveSink<B> for FileSink {
+	async fn write_blocks(&self, blocks: Arc<BatchBlock<B>>) -> Result<()> {
+		let rows: Vec<BlockRow> = blocks
+			.inner()
+			.iter()
+			.map(|b| {
+				let header = b.inner.block.header();
+				BlockRow {
+					block_num: (*header.number()).saturated_into(),
+					hash: header.hash().as_ref().to_vec(),
+					parent_hash: header.parent_hash().as_ref().to_vec(),
+					state_root: header.state_root().as_ref().to_vec(),
+					extrinsics_root: header.extrinsics_root().as_ref().to_vec(),
+					digest: header.digest().encode(),
+					extrinsics: b.inner.block.extrinsics().encode(),
+					spec: b.spec,
+					is_canonical: b.is_canonical,
+				}
+			})
+			.collect();
+		match self.buffer(&self.blocks, rows) {
+			Some(rows) => self.write_segment(rows, Rows::Blocks).await,
+			None => Ok(()),
+		}
+	}
+
+	async fn write_storage(&self, storage: Arc<BatchStorage<B>>) -> Result<()> {
+		let child_storage = Vec::<ChildStorageModel<B>>::from(&*storage);
+		let mut rows: Vec<StorageRow> = storage
+			.inner()
+			.iter()
+			.flat_map(|s| {
+				s.changes().iter().map(move |(key, value)| StorageRow {
+					block_num: s.block_num(),
+					hash: s.hash().as_ref().to_vec(),
+					is_full: s.is_full(),
+					child: None,
+					key: key.0.clone(),
+					value: value.as_ref().map(|d| d.0.clone()),
+				})
+			})
+			.collect();
+		rows.extend(child_storage.into_iter().map(|s| StorageRow {
+			block_num: s.block_num(),
+			hash: s.hash().as_ref().to_vec(),
+			is_full: s.is_full(),
+			child: Some(s.child_key().0.clone()),
+			key: s.key().0.clone(),
+			value: s.data().map(|d| d.0.clone()),
+		}));
+		match self.buffer(&self.storage, rows) {
+			Some(rows) => self.write_segment(rows, Rows::Storage).await,
+			None => Ok(()),
+		}
+	}
+
+	async fn write_metadata(&self, metadata: Arc<Metadata>) -> Result<()> {
+		let name = metadata.version().to_string();
+		let rows = vec![MetadataRow { version: metadata.version(), meta: metadata.meta().to_vec() }];
+		let sink = self.clone();
+		smol::unblock(move || sink.write_rows(&name, Rows::Metadata(rows))).await
+	}
+
+	async fn flush(&self) -> Result<()> {
+		let blocks = self.blocks.lock().take();
+		let storage = self.storage.lock().take();
+		self.write_segment(blocks, Rows::Blocks).await?;
+		self.write_segment(storage, Rows::Storage).await
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use polkadot_service::Block;
+	use sp_core::{
+		storage::{StorageData, StorageKey},
+		H256,
+	};
+	use sp_runtime::{generic::SignedBlock, traits::Header as HeaderT};
+	use substrate_archive_common::types::{self, Storage};
+
+	#[test]
+	fn should_name_ranges() {
+		assert_eq!(range_name(vec![12, 3, 7].into_iter()), "0000000003-0000000012");
+		assert_eq!(range_name(std::iter::once(42)), "0000000042-0000000042");
+	}
+
+	#[test]
+	fn should_not_overwrite_segments() {
+		let dir = tempfile::tempdir().unwrap();
+		let sink = FileSink::new(dir.path(), FileFormat::Json).unwrap();
+		sink.create_segment("blocks", "0000000000-0000000009").unwrap();
+		sink.create_segment("blocks", "0000000000-0000000009").unwrap();
+		sink.create_segment("blocks", "0000000000-0000000009").unwrap();
+		let mut names: Vec<String> = std::fs::read_dir(dir.path().join("blocks"))
+			.unwrap()
+			.map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+			.collect();
+		names.sort();
+		assert_eq!(
+			names,
+			vec!["0000000000-0000000009.1.ndjson", "0000000000-0000000009.2.ndjson", "0000000000-0000000009.ndjson"]
+		);
+	}
+
+	fn batch(nums: std::ops::Range<u32>) -> Arc<BatchBlock<Block>> {
+		let blocks = nums
+			.map(|n| {
+				let header = <Block as BlockT>::Header::new(
+					n,
+					Default::default(),
+					Default::default(),
+					H256::repeat_byte(n as u8),
+					Default::default(),
+				);
+				let block = SignedBlock { block: Block::new(header, Vec::new()), justification: None };
+				types::Block::new(block, 7)
+			})
+			.collect();
+		Arc::new(BatchBlock::new(blocks))
+	}
+
+	fn segments(dir: &Path, kind: &str) -> Vec<String> {
+		let mut names: Vec<String> = std::fs::read_dir(dir.join(kind))
+			.unwrap()
+			.map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+			.collect();
+		names.sort();
+		names
+	}
+
+	#[test]
+	fn should_roll_segments() {
+		let dir = tempfile::tempdir().unwrap();
+		let sink = FileSink::new(dir.path(), FileFormat::Json).unwrap().segment_size(3, usize::MAX);
+		smol::block_on(async {
+			sink.write_blocks(batch(1..3)).await.unwrap();
+			assert!(segments(dir.path(), "blocks").is_empty());
+			sink.write_blocks(batch(3..5)).await.unwrap();
+			assert_eq!(segments(dir.path(), "blocks"), vec!["0000000001-0000000004.ndjson"]);
+			sink.write_blocks(batch(5..6)).await.unwrap();
+			ArchiveSink::<Block>::flush(&sink).await.unwrap();
+		});
+		assert_eq!(
+			segments(dir.path(), "blocks"),
+			vec!["0000000001-0000000004.ndjson", "0000000005-0000000005.ndjson"]
+		);
+	}
+
+	#[test]
+	fn should_read_back_ndjson() {
+		let dir = tempfile::tempdir().unwrap();
+		let sink = FileSink::new(dir.path(), FileFormat::Json).unwrap();
+		let hash = H256::repeat_byte(9);
+		let storage = Storage::<Block>::new(
+			hash,
+			4,
+			false,
+			vec![(StorageKey(vec![1, 2]), Some(StorageData(vec![3]))), (StorageKey(vec![4]), None)],
+			vec![(StorageKey(vec![5]), vec![(StorageKey(vec![6]), Some(StorageData(vec![7])))])],
+		);
+		let blocks = batch(4..5);
+		smol::block_on(async {
+			sink.write_blocks(blocks.clone()).await.unwrap();
+			sink.write_storage(Arc::new(BatchStorage::new(vec![storage]))).await.unwrap();
+			ArchiveSink::<Block>::flush(&sink).await.unwrap();
+		});
+
+		let read = |kind: &str| -> Vec<serde_json::Value> {
+			let path = dir.path().join(kind).join("0000000004-0000000004.ndjson");
+			std::fs::read_to_string(path).unwrap().lines().map(|l| serde_json::from_str(l).unwrap()).collect()
+		};
+		let header = blocks.inner()[0].inner.block.header();
+		assert_eq!(
+			read("blocks"),
+			vec![serde_json::json!({
+				"block_num": 4,
+				"hash": format!("0x{}", hex::encode(header.hash())),
+				"parent_hash": format!("0x{}", hex::encode(header.parent_hash())),
+				"state_root": format!("0x{}", hex::encode(header.state_root())),
+				"extrinsics_root": format!("0x{}", hex::encode(header.extrinsics_root())),
+				"digest": "0x00",
+				"extrinsics": "0x00",
+				"spec": 7,
+				"is_canonical": true,
+			})]
+		);
+		let hash = format!("0x{}", hex::encode(hash));
+		let row = |child: Option<&str>, key: &str, value: Option<&str>| {
+			serde_json::json!({
+				"block_num": 4,
+				"hash": hash,
+				"is_full": false,
+				"child": child,
+				"key": key,
+				"value": value,
+			})
+		};
+		assert_eq!(
+			read("storage"),
+			vec![row(None, "0x0102", Some("0x03")), row(None, "0x04", None), row(Some("0x05"), "0x06", Some("0x07"))]
+		);
+	}
+}
@@ -0,0 +1,241 @@
+// Copyright 2017-2019 Parity Technologies (UK) Ltd.
+// This file is part of substrate-archive.
+
+// substrate-archive is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// substrate-archive is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Parquet segments. Every segment is written as a single row group.
+
+use std::fs::File;
+use std::sync::Arc;
+
+use parquet::{
+	column::writer::ColumnWriter,
+	data_type::ByteArray,
+	errors::ParquetError,
+	file::{
+		properties::WriterProperties,
+		writer::{FileWriter, RowGroupWriter, SerializedFileWriter},
+	},
+	schema::parser::parse_message_type,
+};
+
+use substrate_archive_common::{ArchiveError, Result};
+
+use super::Rows;
+
+const BLOCKS_SCHEMA: &str = "
+	message block {
+		REQUIRED INT64 block_num;
+		REQUIRED BYTE_ARRAY hash;
+		REQUIRED BYTE_ARRAY parent_hash;
+		REQUIRED BYTE_ARRAY state_root;
+		REQUIRED BYTE_ARRAY extrinsics_root;
+		REQUIRED BYTE_ARRAY digest;
+		REQUIRED BYTE_ARRAY extrinsics;
+		REQUIRED INT32 spec;
+		REQUIRED BOOLEAN is_canonical;
+	}
+";
+
+const STORAGE_SCHEMA: &str = "
+	message storage {
+		REQUIRED INT64 block_num;
+		REQUIRED BYTE_ARRAY hash;
+		REQUIRED BOOLEAN is_full;
+		OPTIONAL BYTE_ARRAY child;
+		REQUIRED BYTE_ARRAY key;
+		OPTIONAL BYTE_ARRAY value;
+	}
+";
+
+const METADATA_SCHEMA: &str = "
+	message metadata {
+		REQUIRED INT32 version;
+		REQUIRED BYTE_ARRAY meta;
+	}
+";
+
+/// The values of one column, in the order of the rows
+enum Column {
+	Int32(Vec<i32>),
+	Int64(Vec<i64>),
+	Bool(Vec<bool>),
+	Bytes(Vec<ByteArray>),
+	OptionalBytes(Vec<Option<ByteArray>>),
+}
+
+fn bytes<'a, T: 'a>(rows: &'a [T], field: impl Fn(&'a T) -> &'a Vec<u8>) -> Column {
+	Column::Bytes(rows.iter().map(|r| ByteArray::from(field(r).clone())).collect())
+}
+
+fn optional_bytes<'a, T: 'a>(rows: &'a [T], field: impl Fn(&'a T) -> &'a Option<Vec<u8>>) -> Column {
+	Column::OptionalBytes(rows.iter().map(|r| field(r).clone().map(ByteArray::from)).collect())
+}
+
+/// Split rows into their schema and columns
+fn columns(rows: &Rows) -> (&'static str, Vec<Column>) {
+	match rows {
+		Rows::Blocks(r) => (
+			BLOCKS_SCHEMA,
+			vec![
+				Column::Int64(r.iter().map(|b| b.block_num as i64).collect()),
+				bytes(r, |b| &b.hash),
+				bytes(r, |b| &b.parent_hash),
+				bytes(r, |b| &b.state_root),
+				bytes(r, |b| &b.extrinsics_root),
+				bytes(r, |b| &b.digest),
+				bytes(r, |b| &b.extrinsics),
+				Column::Int32(r.iter().map(|b| b.spec as i32).collect()),
+				Column::Bool(r.iter().map(|b| b.is_canonical).collect()),
+			],
+		),
+		Rows::Storage(r) => (
+			STORAGE_SCHEMA,
+			vec![
+				Column::Int64(r.iter().map(|s| s.block_num as i64).collect()),
+				bytes(r, |s| &s.hash),
+				Column::Bool(r.iter().map(|s| s.is_full).collect()),
+				optional_bytes(r, |s| &s.child),
+				bytes(r, |s| &s.key),
+				optional_bytes(r, |s| &s.value),
+			],
+		),
+		Rows::Metadata(r) => {
+			(METADATA_SCHEMA, vec![Column::Int32(r.iter().map(|m| m.version as i32).collect()), bytes(r, |m| &m.meta)])
+		}
+	}
+}
+
+fn write_column(writer: &mut ColumnWriter, column: Column) -> std::result::Result<(), ParquetError> {
+	match (writer, column) {
+		(ColumnWriter::Int32ColumnWriter(w), Column::Int32(v)) => w.write_batch(&v, None, None)?,
+		(ColumnWriter::Int64ColumnWriter(w), Column::Int64(v)) => w.write_batch(&v, None, None)?,
+		(ColumnWriter::BoolColumnWriter(w), Column::Bool(v)) => w.write_batch(&v, None, None)?,
+		(ColumnWriter::ByteArrayColumnWriter(w), Column::Bytes(v)) => w.write_batch(&v, None, None)?,
+		(ColumnWriter::ByteArrayColumnWriter(w), Column::OptionalBytes(v)) => {
+			// nulls are only recorded in the definition levels
+			let levels: Vec<i16> = v.iter().map(|b| if b.is_some() { 1 } else { 0 }).collect();
+			let values: Vec<ByteArray> = v.into_iter().flatten().collect();
+			w.write_batch(&values, Some(&levels), None)?
+		}
+		_ => return Err(ParquetError::General("column does not match the schema".into())),
+	};
+	Ok(())
+}
+
+fn write_rows(file: File, rows: Rows) -> std::result::Result<(), ParquetError> {
+	let (schema, columns) = columns(&rows);
+	let schema = Arc::new(parse_message_type(schema)?);
+	let props = Arc::new(WriterProperties::builder().build());
+	let mut writer = SerializedFileWriter::new(file, schema, props)?;
+	let mut row_group = writer.next_row_group()?;
+	let mut columns = columns.into_iter();
+	while let Some(mut column_writer) = row_group.next_column()? {
+		let column = columns.next().ok_or_else(|| ParquetError::General("missing column".into()))?;
+		write_column(&mut column_writer, column)?;
+		row_group.close_column(column_writer)?;
+	}
+	writer.close_row_group(row_group)?;
+	writer.close()?;
+	Ok(())
+}
+
+/// Write `rows` as a parquet file
+pub(super) fn write(file: File, rows: Rows) -> Result<()> {
+	write_rows(file, rows).map_err(|e| ArchiveError::from(format!("parquet: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::sink::file::{BlockRow, StorageRow};
+	use parquet::{
+		file::reader::{FileReader, SerializedFileReader},
+		record::Field,
+	};
+
+	/// Read every row of a parquet file back as its fields
+	fn read(path: &std::path::Path) -> Vec<Vec<Field>> {
+		let reader = SerializedFileReader::new(File::open(path).unwrap()).unwrap();
+		reader.get_row_iter(None).unwrap().map(|row| row.get_column_iter().map(|(_, f)| f.clone()).collect()).collect()
+	}
+
+	fn bytes(b: &[u8]) -> Field {
+		Field::Bytes(ByteArray::from(b.to_vec()))
+	}
+
+	#[test]
+	fn should_read_back_storage() {
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join("storage.parquet");
+		let rows = vec![
+			StorageRow {
+				block_num: 4,
+				hash: vec![9; 32],
+				is_full: true,
+				child: None,
+				key: vec![1, 2],
+				value: Some(vec![3]),
+			},
+			StorageRow {
+				block_num: 4,
+				hash: vec![9; 32],
+				is_full: false,
+				child: Some(vec![5]),
+				key: vec![6],
+				value: None,
+			},
+		];
+		write(File::create(&path).unwrap(), Rows::Storage(rows)).unwrap();
+		assert_eq!(
+			read(&path),
+			vec![
+				vec![Field::Long(4), bytes(&[9; 32]), Field::Bool(true), Field::Null, bytes(&[1, 2]), bytes(&[3])],
+				vec![Field::Long(4), bytes(&[9; 32]), Field::Bool(false), bytes(&[5]), bytes(&[6]), Field::Null],
+			]
+		);
+	}
+
+	#[test]
+	fn should_read_back_blocks() {
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join("blocks.parquet");
+		let row = BlockRow {
+			block_num: 1,
+			hash: vec![1; 32],
+			parent_hash: vec![0; 32],
+			state_root: vec![2; 32],
+			extrinsics_root: vec![3; 32],
+			digest: vec![0],
+			extrinsics: vec![4, 5],
+			spec: 7,
+			is_canonical: true,
+		};
+		write(File::create(&path).unwrap(), Rows::Blocks(vec![row])).unwrap();
+		assert_eq!(
+			read(&path),
+			vec![vec![
+				Field::Long(1),
+				bytes(&[1; 32]),
+				bytes(&[0; 32]),
+				bytes(&[2; 32]),
+				bytes(&[3; 32]),
+				bytes(&[0]),
+				bytes(&[4, 5]),
+				Field::Int(7),
+				Field::Bool(true),
+			]]
+		);
+	}
+}
@@ -20,10 +20,10 @@
 
 use std::marker::PhantomData;
 
-use codec::{Decode, Encode, Error as DecodeError};
+use codec::{Decode, Error as DecodeError};
 use sp_runtime::{
 	generic::SignedBlock,
-	traits::{Block as BlockT, Header as HeaderT},
+	traits::{Block as BlockT, Header as HeaderT, SaturatedConversion},
 };
 
 use substrate_archive_common::{models::BlockModel, types};
@@ -38,9 +38,11 @@ impl<'a, B: BlockT> SqlBlockBuilder<B> {
 		blocks
 			.into_iter()
 			.map(|b| {
+				let is_canonical = b.is_canonical;
+				let justification = b.justification.clone();
 				let (block, spec) = Self::with_single(b)?;
-				let block = SignedBlock { block, justification: None };
-				Ok(types::Block::new(block, spec))
+				let block = SignedBlock { block, justification };
+				Ok(types::Block::new(block, spec).canonical(is_canonical))
 			})
 			.collect()
 	}
@@ -61,7 +63,7 @@ struct BlockDecoder<B: BlockT> {
 
 impl<B: BlockT> BlockDecoder<B> {
 	fn decode(block: BlockModel) -> Result<Self, DecodeError> {
-		let block_num = Decode::decode(&mut (block.block_num as u32).encode().as_slice())?;
+		let block_num = (block.block_num as u64).saturated_into();
 		let extrinsics_root = Decode::decode(&mut block.extrinsics_root.as_slice())?;
 		let state_root = Decode::decode(&mut block.state_root.as_slice())?;
 		let parent_hash = Decode::decode(&mut block.parent_hash.as_slice())?;
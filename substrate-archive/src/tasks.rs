@@ -19,23 +19,41 @@
 
 use std::marker::PhantomData;
 use std::panic::AssertUnwindSafe;
-use std::sync::Arc;
+use std::sync::{
+	atomic::{AtomicBool, AtomicUsize, Ordering},
+	Arc,
+};
+use std::time::Duration;
 
+use codec::Encode;
 use xtra::prelude::*;
 
 use sc_client_api::backend;
 use serde::de::DeserializeOwned;
 use sp_api::{ApiExt, ConstructRuntimeApi};
 use sp_block_builder::BlockBuilder as BlockBuilderApi;
+use sp_core::storage::{StorageData, StorageKey};
 use sp_runtime::{
 	generic::BlockId,
-	traits::{Block as BlockT, Header, NumberFor},
+	traits::{Block as BlockT, Header, SaturatedConversion},
 };
 
 use substrate_archive_backend::{ApiAccess, BlockExecutor, ReadOnlyBackend as Backend};
-use substrate_archive_common::{types::Storage, ReadOnlyDB};
+use substrate_archive_common::{
+	metrics,
+	types::{BatchDecoded, BatchStorage, Storage},
+	ReadOnlyDB,
+};
+
+use crate::{
+	actors::StorageAggregator,
+	decode::{self, Decoder},
+	filter::StorageFilter,
+	sink::Sinks,
+};
 
-use crate::actors::StorageAggregator;
+/// Keys written to the sinks at once when storing the full state of a block
+const SNAPSHOT_CHUNK: usize = 10_000;
 
 /// The environment passed to each task
 pub struct Environment<B, R, C, D>
@@ -47,9 +65,84 @@ where
 	backend: Arc<Backend<B, D>>,
 	client: Arc<C>,
 	storage: Address<StorageAggregator<B>>,
+	/// snapshots are written to the sinks directly, a chunk at a time
+	sinks: Sinks<B>,
+	/// decodes extrinsics, if decoding is enabled
+	decoder: Option<Arc<Decoder>>,
+	/// storage keys to index
+	filter: Arc<StorageFilter>,
+	/// when to store the full state of a block
+	snapshots: Snapshots,
+	/// tasks that are executing
+	tasks: RunningTasks,
 	_marker: PhantomData<R>,
 }
 
+/// Counts the tasks that are executing, so that shutdown can wait for them.
+/// Once stopped, tasks that have not started yet fail, and are retried the next time the archive runs.
+#[derive(Clone, Default)]
+pub struct RunningTasks {
+	running: Arc<AtomicUsize>,
+	stopped: Arc<AtomicBool>,
+}
+
+impl RunningTasks {
+	/// Mark a task as running until the returned guard is dropped.
+	/// `None` if tasks have been stopped.
+	fn start(&self) -> Option<RunningTask<'_>> {
+		self.running.fetch_add(1, Ordering::SeqCst);
+		let task = RunningTask(&self.running);
+		if self.stopped.load(Ordering::SeqCst) {
+			return None;
+		}
+		Some(task)
+	}
+
+	/// Keep tasks that have not started yet from running
+	pub fn stop(&self) {
+		self.stopped.store(true, Ordering::SeqCst);
+	}
+
+	/// Resolves once no task is running
+	pub async fn finished(&self) {
+		while self.running.load(Ordering::SeqCst) > 0 {
+			smol::Timer::after(Duration::from_millis(50)).await;
+		}
+	}
+}
+
+struct RunningTask<'a>(&'a AtomicUsize);
+
+impl Drop for RunningTask<'_> {
+	fn drop(&mut self) {
+		self.0.fetch_sub(1, Ordering::SeqCst);
+	}
+}
+
+/// When to store the full state of a block, instead of only the changes it made
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Snapshots {
+	/// every `interval` blocks
+	pub interval: Option<u64>,
+	/// at every block that upgrades the runtime
+	pub on_upgrade: bool,
+}
+
+impl Snapshots {
+	/// Whether to store the full state of block `number`. `upgraded` tells whether the block upgraded the runtime,
+	/// and is only called if snapshots are taken on upgrades.
+	fn includes<E>(&self, number: u64, upgraded: impl FnOnce() -> Result<bool, E>) -> Result<bool, E> {
+		if self.interval.and_then(|n| number.checked_rem(n)) == Some(0) {
+			return Ok(true);
+		}
+		if self.on_upgrade {
+			upgraded()
+		} else {
+			Ok(false)
+		}
+	}
+}
+
 type Env<B, R, C, D> = AssertUnwindSafe<Environment<B, R, C, D>>;
 impl<B, R, C, D> Environment<B, R, C, D>
 where
@@ -57,8 +150,52 @@ where
 	B: BlockT + Unpin,
 	B::Hash: Unpin,
 {
-	pub fn new(backend: Arc<Backend<B, D>>, client: Arc<C>, storage: Address<StorageAggregator<B>>) -> Self {
-		Self { backend, client, storage, _marker: PhantomData }
+	pub fn new(
+		backend: Arc<Backend<B, D>>,
+		client: Arc<C>,
+		storage: Address<StorageAggregator<B>>,
+		sinks: Sinks<B>,
+		decoder: Option<Arc<Decoder>>,
+		filter: Arc<StorageFilter>,
+		snapshots: Snapshots,
+		tasks: RunningTasks,
+	) -> Self {
+		Self { backend, client, storage, sinks, decoder, filter, snapshots, tasks, _marker: PhantomData }
+	}
+
+	/// Write the full state of block `hash` to the sinks in chunks of `SNAPSHOT_CHUNK` keys,
+	/// so that the state is never held in memory as a whole. Returns the number of keys written.
+	fn write_snapshot(&self, hash: B::Hash, number: u64) -> Result<usize, coil::PerformError> {
+		let write = |pairs: Vec<(StorageKey, StorageData)>| {
+			let mut storage = Storage::snapshot(hash, number, pairs);
+			self.filter.apply(&mut storage);
+			let keys = storage.changes().len();
+			if keys == 0 {
+				return Ok(0);
+			}
+			smol::block_on(self.sinks.write_storage(BatchStorage::new(vec![storage]))).map(|()| keys)
+		};
+		let mut chunk = Vec::with_capacity(SNAPSHOT_CHUNK);
+		let (mut keys, mut error) = (0, None);
+		self.backend
+			.for_storage_pairs(hash, |key, value| {
+				// iteration cannot be stopped, so the remaining keys are skipped after an error
+				if error.is_some() {
+					return;
+				}
+				chunk.push((StorageKey(key.to_vec()), StorageData(value.to_vec())));
+				if chunk.len() == SNAPSHOT_CHUNK {
+					match write(std::mem::replace(&mut chunk, Vec::with_capacity(SNAPSHOT_CHUNK))) {
+						Ok(written) => keys += written,
+						Err(e) => error = Some(e),
+					}
+				}
+			})
+			.ok_or_else(|| format!("No state found for block {}", hash))?;
+		if let Some(e) = error {
+			return Err(e.into());
+		}
+		Ok(keys + write(chunk)?)
 	}
 }
 
@@ -77,32 +214,128 @@ pub fn execute_block<B, RA, Api, D>(
 where
 	D: ReadOnlyDB + 'static,
 	B: BlockT + DeserializeOwned + Unpin,
-	NumberFor<B>: Into<u32>,
 	B::Hash: Unpin,
 	RA: ConstructRuntimeApi<B, Api> + Send + Sync + 'static,
 	RA::RuntimeApi: BlockBuilderApi<B, Error = sp_blockchain::Error>
 		+ ApiExt<B, StateBackend = backend::StateBackendFor<Backend<B, D>, B>>,
 	Api: ApiAccess<B, Backend<B, D>, RA> + 'static,
 {
+	let _running = env.tasks.start().ok_or("Shutting down, the block is executed the next time the archive runs")?;
 	let api = env.client.runtime_api();
 
 	if *block.header().parent_hash() == Default::default() {
 		return Ok(());
 	}
 
-	log::trace!(
-		"Executing Block: {}:{}, version {}",
-		block.header().hash(),
-		block.header().number(),
-		env.client
-			.runtime_version_at(&BlockId::Hash(block.header().hash()))
-			.map_err(|e| format!("{:?}", e))?
-			.spec_version,
-	);
+	let hash = block.header().hash();
+	let number: u64 = (*block.header().number()).saturated_into();
+	let spec = env.client.runtime_version_at(&BlockId::Hash(hash)).map_err(|e| format!("{:?}", e))?.spec_version;
+	let snapshot = env.snapshots.includes(number, || {
+		let parent = BlockId::Hash(*block.header().parent_hash());
+		env.client.runtime_version_at(&parent).map(|v| v.spec_version != spec).map_err(|e| format!("{:?}", e))
+	})?;
+	log::trace!("Executing Block: {}:{}, version {}", hash, block.header().number(), spec);
+	// the block is consumed by execution, so keep the encoded extrinsics and the header around for decoding
+	let undecoded = env.decoder.as_ref().map(|_| (block.extrinsics().encode(), block.header().clone()));
 	let now = std::time::Instant::now();
 	let block = BlockExecutor::new(api, &env.backend, block)?.block_into_storage()?;
-	log::debug!("Took {:?} to execute block", now.elapsed());
-	let storage = Storage::from(block);
+	let elapsed = now.elapsed();
+	log::debug!("Took {:?} to execute block", elapsed);
+	metrics::block_executed(elapsed);
+	let mut storage = Storage::from(block);
+	if let (Some(decoder), Some((extrinsics, header))) = (env.decoder.as_ref(), undecoded) {
+		// a block that cannot be decoded should not stop its storage from being indexed.
+		// extrinsics and events are decoded independently, so this only fails without the metadata of the block
+		let (extrinsics, events) = decoder.decode(spec, extrinsics.as_slice(), &storage).unwrap_or_else(|e| {
+			log::warn!("Could not decode block {}: {}", hash, e);
+			(Vec::new(), Vec::new())
+		});
+		let authorities = decode::Authorities {
+			babe: env.backend.storage(hash, &decode::BABE_AUTHORITIES),
+			aura: env.backend.storage(hash, &decode::AURA_AUTHORITIES),
+			validators: env.backend.storage(hash, &decode::SESSION_VALIDATORS),
+		};
+		let digest_logs = decode::digest_logs::<B>(&header, &authorities).unwrap_or_else(|e| {
+			log::warn!("Could not decode digest of block {}: {}", hash, e);
+			Vec::new()
+		});
+		smol::block_on(env.storage.send(BatchDecoded::new(extrinsics, events, digest_logs)))??;
+	}
+	if snapshot {
+		// the job only finishes once the whole state is written, so an interrupted snapshot is written again
+		// when the job is retried
+		let now = std::time::Instant::now();
+		let keys = env.write_snapshot(hash, number)?;
+		storage = storage.into_full();
+		log::info!("Took {:?} to snapshot {} keys of block {}", now.elapsed(), keys, number);
+	}
+	// filtered after decoding, since events are read from storage
+	env.filter.apply(&mut storage);
 	smol::block_on(env.storage.send(storage))?;
 	Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use polkadot_service::Block;
+	use substrate_archive_common::models::{ChildStorageModel, StorageModel};
+
+	fn key(k: u8) -> StorageKey {
+		StorageKey(vec![k])
+	}
+
+	fn data(d: u8) -> StorageData {
+		StorageData(vec![d])
+	}
+
+	#[test]
+	fn should_snapshot_every_interval() {
+		let snapshots = Snapshots { interval: Some(10), on_upgrade: false };
+		assert_eq!(snapshots.includes(0, || Err(())), Ok(true));
+		assert_eq!(snapshots.includes(20, || Err(())), Ok(true));
+		// upgrades are not checked unless snapshots are taken on upgrades
+		assert_eq!(snapshots.includes(21, || Err(())), Ok(false));
+		assert_eq!(Snapshots::default().includes(20, || Err(())), Ok(false));
+		// an interval of 0 never snapshots
+		let snapshots = Snapshots { interval: Some(0), on_upgrade: false };
+		assert_eq!(snapshots.includes(0, || Err(())), Ok(false));
+	}
+
+	#[test]
+	fn should_snapshot_on_upgrade() {
+		let snapshots = Snapshots { interval: Some(10), on_upgrade: true };
+		assert_eq!(snapshots.includes(21, || Ok::<_, ()>(true)), Ok(true));
+		assert_eq!(snapshots.includes(21, || Ok::<_, ()>(false)), Ok(false));
+		assert_eq!(snapshots.includes(21, || Err(())), Err(()));
+		// blocks on the interval do not need to check for an upgrade
+		assert_eq!(snapshots.includes(30, || Err(())), Ok(true));
+	}
+
+	#[test]
+	fn should_keep_deleted_keys_in_full_storage() {
+		let hash = Default::default();
+		let changes = vec![(key(1), Some(data(1))), (key(2), None)];
+		let child_changes = vec![(key(9), vec![(key(3), Some(data(3))), (key(4), None)])];
+		let storage = Storage::<Block>::new(hash, 5, false, changes, child_changes).into_full();
+
+		assert!(storage.is_full());
+		// values the block set are part of the snapshot chunks
+		assert_eq!(storage.changes(), &[(key(2), None)]);
+		let child = Vec::<ChildStorageModel<Block>>::from(&storage);
+		assert_eq!(child.len(), 2);
+		assert!(child.iter().all(|c| !c.is_full()));
+		let rows = Vec::<StorageModel<Block>>::from(storage);
+		assert_eq!(rows.len(), 1);
+		assert!(rows[0].is_full());
+		assert_eq!(rows[0].data(), None);
+	}
+
+	#[test]
+	fn should_store_snapshot_chunks_as_full_storage() {
+		let storage = Storage::<Block>::snapshot(Default::default(), 5, vec![(key(1), data(1)), (key(2), data(2))]);
+		assert!(storage.is_full());
+		assert!(storage.child_changes().is_empty());
+		assert_eq!(storage.changes(), &[(key(1), Some(data(1))), (key(2), Some(data(2)))]);
+	}
+}
@@ -14,6 +14,8 @@
 // You should have received a copy of the GNU General Public License
 // along with substrate-archive.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::ops::RangeInclusive;
+
 use sp_runtime::traits::Block as BlockT;
 
 use substrate_archive_common::{ReadOnlyDB, Result};
@@ -26,15 +28,82 @@ where
 	/// start driving the execution of the archive
 	fn drive(&mut self) -> Result<()>;
 
-	/// this method will block indefinitely
+	/// Block until the archive stops, either because it was shut down or because it failed
 	async fn block_until_stopped(&self) -> ();
 
-	/// shutdown the system
-	fn shutdown(self) -> Result<()>;
+	/// Whether the archive has stopped, because it was shut down, failed, or finished indexing its block range
+	fn is_stopped(&self) -> bool;
+
+	/// shutdown the system.
+	/// Stops queueing new blocks, and waits up to the shutdown timeout for running tasks and buffered storage.
+	fn shutdown(self) -> Result<ShutdownReport>;
 
 	/// Shutdown the system when self is boxed (useful when erasing the types of the runtime)
-	fn boxed_shutdown(self: Box<Self>) -> Result<()>;
+	fn boxed_shutdown(self: Box<Self>) -> Result<ShutdownReport>;
 
 	/// Get a reference to the context the actors are using
 	fn context(&self) -> Result<super::actors::ActorContext<B, D>>;
+
+	/// Get a snapshot of how far indexing has progressed
+	async fn status(&self) -> Result<Status>;
+
+	/// Delete the storage, events, digest logs and storage root mismatches of every block in `range`,
+	/// including blocks on forks, and queue the blocks to be executed again.
+	/// The blocks are executed by a running archive, or the next time one is driven.
+	/// Returns the number of blocks queued.
+	fn reexecute(&self, range: RangeInclusive<u64>) -> Result<u64>;
+
+	/// Check the archived storage of every block in `range`, including blocks on forks.
+	/// The storage of each block is applied to the state of its parent in the chain database,
+	/// and the resulting storage root is compared to the state root in the block's header.
+	/// Mismatches are recorded in the `storage_root_mismatches` table.
+	///
+	/// Fails if storage has ever been indexed into this database with a storage filter, or with `postgres_sink`
+	/// disabled, since the storage in Postgres is then incomplete and cannot reproduce the state root.
+	fn verify(&self, range: RangeInclusive<u64>) -> Result<VerificationReport>;
+}
+
+/// A snapshot of the progress of the archive
+#[derive(Debug, Clone, PartialEq)]
+pub struct Status {
+	/// best block number in the RocksDB backend
+	pub best_block: u64,
+	/// finalized block number in the RocksDB backend
+	pub finalized_block: u64,
+	/// highest block number in Postgres. `None` if no blocks have been indexed yet
+	pub max_indexed_block: Option<u64>,
+	/// block number up to which every block has been sent to Postgres
+	pub last_max: u64,
+	/// number of `execute_block` tasks waiting in the background task queue
+	pub pending_tasks: u64,
+	/// number of blocks in Postgres without any storage
+	pub missing_storage: u64,
+	/// blocks executed and stored per second, measured over the last ten seconds or more
+	pub blocks_per_second: f64,
+}
+
+/// What was left unfinished when the archive shut down.
+/// Blocks left in the task queue or the outbox are executed the next time the archive runs.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ShutdownReport {
+	/// `execute_block` tasks that were running finished before the deadline
+	pub tasks_finished: bool,
+	/// storage buffered in memory was inserted into Postgres before the deadline
+	pub storage_flushed: bool,
+	/// number of `execute_block` tasks left in the background task queue
+	pub pending_tasks: u64,
+	/// number of blocks left in the `_block_outbox` table
+	pub outbox: u64,
+}
+
+/// The outcome of checking archived storage against the state roots of blocks
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VerificationReport {
+	/// number of blocks whose storage produced the state root in their header
+	pub verified: u64,
+	/// number and hash of the blocks whose storage did not
+	pub mismatched: Vec<(u64, Vec<u8>)>,
+	/// number of blocks that were skipped, because they have no storage yet
+	/// or the state of their parent is not in the chain database
+	pub skipped: u64,
 }